@@ -0,0 +1,2 @@
+-- Hashed (SHA-256) access tokens, managed with the `token` command or the admin API.
+CREATE TABLE tokens (id SERIAL PRIMARY KEY, hash TEXT NOT NULL UNIQUE, label TEXT NOT NULL, kind TEXT NOT NULL, buoys TEXT, products TEXT, created BIGINT NOT NULL, expires BIGINT, revoked BIGINT);
@@ -0,0 +1,5 @@
+-- Events received again with the same name (Notehub event UUID), but with a different payload
+-- than the stored event. Kept for investigation, the stored event is not changed.
+CREATE TABLE event_conflicts (id SERIAL PRIMARY KEY, dev TEXT NOT NULL, event TEXT NOT NULL, received BIGINT NOT NULL, message_type TEXT NOT NULL, data BYTEA NOT NULL, reported BIGINT NOT NULL);
+
+CREATE INDEX event_conflicts_dev ON event_conflicts (dev, event);
@@ -0,0 +1,2 @@
+-- Notehub product (or Rockblock account) of buoy, used for scoped tokens.
+ALTER TABLE buoys ADD COLUMN product TEXT;
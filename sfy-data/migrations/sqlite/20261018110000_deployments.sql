@@ -0,0 +1,4 @@
+-- Deployments of buoys, times in milliseconds since epoch. Ongoing deployments have no end.
+CREATE TABLE deployments (id INTEGER PRIMARY KEY AUTOINCREMENT, dev TEXT NOT NULL, start_time BIGINT NOT NULL, end_time BIGINT, site TEXT, lat REAL, lon REAL, hull TEXT, mooring TEXT, notes TEXT, features TEXT);
+
+CREATE INDEX deployments_dev ON deployments (dev, start_time);
@@ -0,0 +1,5 @@
+-- Persistent queue of webhook deliveries. Delivered events are removed, events that failed
+-- too many times are kept with `failed` set.
+CREATE TABLE webhook_queue (id INTEGER PRIMARY KEY AUTOINCREMENT, url TEXT NOT NULL, payload BLOB NOT NULL, created BIGINT NOT NULL, attempts INTEGER NOT NULL DEFAULT 0, next_attempt BIGINT NOT NULL, last_error TEXT, failed BIGINT);
+
+CREATE INDEX webhook_queue_next ON webhook_queue (next_attempt) WHERE failed IS NULL;
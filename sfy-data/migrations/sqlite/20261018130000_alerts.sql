@@ -0,0 +1,5 @@
+-- Alerts raised by the alert engine. An alert is active until it is resolved, at most one
+-- alert of each kind is active for a buoy.
+CREATE TABLE alerts (id INTEGER PRIMARY KEY AUTOINCREMENT, dev TEXT NOT NULL, kind TEXT NOT NULL, message TEXT NOT NULL, raised BIGINT NOT NULL, resolved BIGINT);
+
+CREATE INDEX alerts_active ON alerts (dev, kind) WHERE resolved IS NULL;
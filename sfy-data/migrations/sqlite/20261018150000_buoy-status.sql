@@ -0,0 +1,19 @@
+-- Latest state of each buoy, updated as events are ingested. Positions and wave heights are only
+-- known for events ingested after this migration.
+CREATE TABLE buoy_status (dev TEXT PRIMARY KEY, last_received BIGINT, last_message_type TEXT, lat DOUBLE, lon DOUBLE, fix_time BIGINT, hs DOUBLE, hs_received BIGINT);
+
+CREATE TABLE buoy_message_counts (dev TEXT NOT NULL, message_type TEXT NOT NULL, count BIGINT NOT NULL, PRIMARY KEY (dev, message_type));
+
+INSERT INTO buoy_status (dev, last_received, last_message_type)
+    SELECT dev, MAX(received), message_type FROM (
+        SELECT dev, received, message_type FROM events WHERE dev IS NOT NULL
+        UNION ALL
+        SELECT dev, received, message_type FROM omb_events
+    ) GROUP BY dev;
+
+INSERT INTO buoy_message_counts (dev, message_type, count)
+    SELECT dev, message_type, SUM(n) FROM (
+        SELECT dev, message_type, COUNT(*) AS n FROM events WHERE dev IS NOT NULL GROUP BY dev, message_type
+        UNION ALL
+        SELECT dev, message_type, COUNT(*) AS n FROM omb_events GROUP BY dev, message_type
+    ) GROUP BY dev, message_type;
@@ -0,0 +1,2 @@
+-- Bulk wave parameters computed from acceleration packages, for windows of `window_length` ms.
+CREATE TABLE wave_stats (dev TEXT NOT NULL, window_length BIGINT NOT NULL, nseg INTEGER NOT NULL, start BIGINT NOT NULL, freq REAL, segments INTEGER NOT NULL, gaps INTEGER NOT NULL, coverage DOUBLE NOT NULL, hm0 DOUBLE, tp DOUBLE, tm01 DOUBLE, tm02 DOUBLE, computed BIGINT NOT NULL, PRIMARY KEY (dev, window_length, nseg, start));
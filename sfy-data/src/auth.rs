@@ -0,0 +1,406 @@
+//! Access tokens, the scopes of read tokens, and signatures of Notehub routes.
+//!
+//! Tokens are either listed in the configuration file, or stored hashed in the database where
+//! they can be created, expired and revoked while the server is running.
+
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::database::{Buoy, Database};
+use crate::State;
+
+/// Length of generated tokens.
+pub const TOKEN_LENGTH: usize = 48;
+
+/// What a token gives access to.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenKind {
+    /// Posting events.
+    Write,
+    /// Reading buoys and events.
+    Read,
+    /// Managing tokens.
+    Admin,
+}
+
+impl TokenKind {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            TokenKind::Write => "write",
+            TokenKind::Read => "read",
+            TokenKind::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for TokenKind {
+    type Err = eyre::ErrReport;
+
+    fn from_str(s: &str) -> eyre::Result<TokenKind> {
+        match s {
+            "write" => Ok(TokenKind::Write),
+            "read" => Ok(TokenKind::Read),
+            "admin" => Ok(TokenKind::Admin),
+            _ => Err(eyre!("unknown token kind: {}", s)),
+        }
+    }
+}
+
+/// A token stored in the database. Only the hash of the token is kept.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Token {
+    pub id: i32,
+    pub label: String,
+    pub kind: TokenKind,
+
+    /// Scope of read tokens, no buoys or products gives access to all buoys.
+    pub buoys: Vec<String>,
+    pub products: Vec<String>,
+
+    /// Times of creation, expiry and revocation [ms].
+    pub created: i64,
+    pub expires: Option<i64>,
+    pub revoked: Option<i64>,
+}
+
+impl Token {
+    pub fn active(&self, now: i64) -> bool {
+        self.revoked.is_none() && self.expires.filter(|e| *e <= now).is_none()
+    }
+
+    pub fn scope(&self) -> Scope {
+        if self.buoys.is_empty() && self.products.is_empty() {
+            Scope::All
+        } else {
+            Scope::Limited {
+                buoys: self.buoys.clone(),
+                products: self.products.clone(),
+            }
+        }
+    }
+}
+
+/// A token to be created.
+#[derive(Debug, Deserialize, Clone)]
+pub struct NewToken {
+    pub label: String,
+    pub kind: TokenKind,
+
+    #[serde(default)]
+    pub buoys: Vec<String>,
+
+    #[serde(default)]
+    pub products: Vec<String>,
+
+    pub expires: Option<i64>,
+}
+
+impl NewToken {
+    /// Generate and store the token. Returns the stored token and the token itself, which
+    /// cannot be recovered later.
+    pub async fn create(self, db: &Database) -> eyre::Result<(Token, String)> {
+        ensure!(!self.label.is_empty(), "token label is empty");
+
+        let secret = generate();
+        let mut token = Token {
+            id: 0,
+            label: self.label,
+            kind: self.kind,
+            buoys: self.buoys,
+            products: self.products,
+            created: chrono::Utc::now().timestamp_millis(),
+            expires: self.expires,
+            revoked: None,
+        };
+
+        token.id = db.add_token(&hash(&secret), &token).await?;
+        info!(
+            "created {} token {} ({}): {}",
+            token.kind.to_str(),
+            token.id,
+            fingerprint(&secret),
+            token.label
+        );
+
+        Ok((token, secret))
+    }
+}
+
+/// Generate a new random token.
+pub fn generate() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// SHA-256 hash of token, as stored in the database.
+pub fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Short identifier of a token that is safe to log.
+pub fn fingerprint(token: &str) -> String {
+    hash(token)[..8].to_string()
+}
+
+/// Look up an active token of `kind` in the database.
+async fn lookup(state: &State, token: &str, kind: TokenKind) -> Option<Token> {
+    let now = chrono::Utc::now().timestamp_millis();
+
+    match state.db.token(&hash(token)).await {
+        Ok(Some(t)) if t.kind == kind && t.active(now) => Some(t),
+        Ok(_) => None,
+        Err(e) => {
+            error!("failed to look up token: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Check whether `token` gives write access.
+pub async fn write(state: &State, token: &str) -> bool {
+    state.config.tokens.iter().any(|t| t == token)
+        || lookup(state, token, TokenKind::Write).await.is_some()
+}
+
+/// The scope of `token` if it gives read access.
+pub async fn read(state: &State, token: &str) -> Option<Scope> {
+    if state.config.read_tokens.iter().any(|t| t == token) {
+        Some(Scope::All)
+    } else if let Some(t) = state.config.scoped_tokens.iter().find(|t| t.token == token) {
+        Some(Scope::from(t))
+    } else {
+        lookup(state, token, TokenKind::Read)
+            .await
+            .map(|t| t.scope())
+    }
+}
+
+/// Check whether `token` gives access to manage tokens.
+pub async fn admin(state: &State, token: &str) -> bool {
+    lookup(state, token, TokenKind::Admin).await.is_some()
+}
+
+/// Header with the HMAC-SHA256 signature of a route payload, hex encoded.
+pub const SIGNATURE: &str = "SFY-Signature";
+
+/// Header with the time the payload was signed [s since epoch].
+pub const TIMESTAMP: &str = "SFY-Timestamp";
+
+/// Secret that a Notehub route signs its payloads with.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RouteSecret {
+    /// Name of route, for logging.
+    pub route: String,
+    pub secret: String,
+}
+
+fn mac(secret: &str, timestamp: &str, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+/// Signature of `body` signed at `timestamp`: the HMAC-SHA256 of `{timestamp}.{body}`.
+#[cfg(test)]
+pub fn sign(secret: &str, timestamp: &str, body: &[u8]) -> String {
+    hex::encode(mac(secret, timestamp, body).finalize().into_bytes())
+}
+
+/// Verify that `body` is signed with one of the route secrets less than `window` seconds from
+/// `now` [s]. Returns the route that signed it.
+pub fn verify<'a>(
+    secrets: &'a [RouteSecret],
+    window: i64,
+    now: i64,
+    timestamp: &str,
+    signature: &str,
+    body: &[u8],
+) -> eyre::Result<&'a RouteSecret> {
+    let t: i64 = timestamp.parse()?;
+    ensure!(
+        (now - t).abs() <= window,
+        "signature timestamp outside window: {} s",
+        now - t
+    );
+
+    let signature = hex::decode(signature)?;
+
+    secrets
+        .iter()
+        .find(|s| {
+            mac(&s.secret, timestamp, body)
+                .verify_slice(&signature)
+                .is_ok()
+        })
+        .ok_or_else(|| eyre!("signature does not match any route secret"))
+}
+
+/// The route with `secret`, for services that cannot sign their payloads. Compared in constant
+/// time.
+pub fn route<'a>(secrets: &'a [RouteSecret], secret: &str) -> Option<&'a RouteSecret> {
+    secrets.iter().find(|s| {
+        s.secret.len() == secret.len()
+            && s.secret
+                .bytes()
+                .zip(secret.bytes())
+                .fold(0, |d, (a, b)| d | (a ^ b))
+                == 0
+    })
+}
+
+/// A read token that only gives access to a set of buoys, or to the buoys of a Notehub product
+/// (`BUOYPR` in the firmware). For OpenMetBuoys the product is the Rockblock account.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScopedToken {
+    pub token: String,
+
+    /// Buoys (dev) the token gives access to.
+    #[serde(default)]
+    pub buoys: Vec<String>,
+
+    /// Products the token gives access to, with or without the `product:` prefix.
+    #[serde(default)]
+    pub products: Vec<String>,
+}
+
+/// The buoys a read token gives access to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Scope {
+    All,
+    Limited {
+        buoys: Vec<String>,
+        products: Vec<String>,
+    },
+}
+
+fn strip_product(product: &str) -> &str {
+    product.strip_prefix("product:").unwrap_or(product)
+}
+
+impl From<&ScopedToken> for Scope {
+    fn from(t: &ScopedToken) -> Scope {
+        Scope::Limited {
+            buoys: t.buoys.clone(),
+            products: t.products.clone(),
+        }
+    }
+}
+
+impl Scope {
+    pub fn permits_dev(&self, dev: &str, product: Option<&str>) -> bool {
+        match self {
+            Scope::All => true,
+            Scope::Limited { buoys, products } => {
+                buoys.iter().any(|b| b == dev)
+                    || product
+                        .map(|p| {
+                            products
+                                .iter()
+                                .any(|s| strip_product(s) == strip_product(p))
+                        })
+                        .unwrap_or(false)
+            }
+        }
+    }
+
+    pub fn permits(&self, buoy: &Buoy) -> bool {
+        self.permits_dev(buoy.dev(), buoy.product())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limited_scope() {
+        let scope = Scope::from(&ScopedToken {
+            token: "t".into(),
+            buoys: vec!["dev1".into()],
+            products: vec!["no.met.gauteh:sfy".into()],
+        });
+
+        assert!(scope.permits_dev("dev1", None));
+        assert!(scope.permits_dev("dev2", Some("product:no.met.gauteh:sfy")));
+        assert!(scope.permits_dev("dev2", Some("no.met.gauteh:sfy")));
+        assert!(!scope.permits_dev("dev2", Some("product:other")));
+        assert!(!scope.permits_dev("dev2", None));
+
+        assert!(Scope::All.permits_dev("dev2", None));
+    }
+
+    #[test]
+    fn route_signature() {
+        let secrets = vec![
+            RouteSecret {
+                route: "a".into(),
+                secret: "secret-a".into(),
+            },
+            RouteSecret {
+                route: "b".into(),
+                secret: "secret-b".into(),
+            },
+        ];
+
+        let body = b"{}";
+        let signature = sign("secret-b", "1000", body);
+        assert_eq!(signature.len(), 64);
+
+        let route = verify(&secrets, 300, 1100, "1000", &signature, body).unwrap();
+        assert_eq!(route.route, "b");
+
+        // Replayed later, or with the timestamp changed.
+        assert!(verify(&secrets, 300, 1400, "1000", &signature, body).is_err());
+        assert!(verify(&secrets, 300, 1400, "1400", &signature, body).is_err());
+
+        assert!(verify(&secrets, 300, 1000, "1000", &signature, b"{ }").is_err());
+        assert!(verify(&secrets, 300, 1000, "1000", "zz", body).is_err());
+        assert!(verify(&secrets[..1], 300, 1000, "1000", &signature, body).is_err());
+    }
+
+    #[test]
+    fn hash_token() {
+        assert_eq!(
+            hash("fourier"),
+            "2e1712f7908f611efbf8602fe12f50c7ff195ed735a584beaf9735c4850d72c5"
+        );
+        assert_eq!(fingerprint("fourier"), &hash("fourier")[..8]);
+
+        let t = generate();
+        assert_eq!(t.len(), TOKEN_LENGTH);
+        assert_ne!(t, generate());
+    }
+
+    #[test]
+    fn token_active() {
+        let mut t = Token {
+            id: 1,
+            label: "test".into(),
+            kind: TokenKind::Read,
+            buoys: vec![],
+            products: vec![],
+            created: 0,
+            expires: Some(100),
+            revoked: None,
+        };
+
+        assert!(t.active(99));
+        assert!(!t.active(100));
+        assert_eq!(t.scope(), Scope::All);
+
+        t.expires = None;
+        assert!(t.active(1000));
+
+        t.revoked = Some(10);
+        assert!(!t.active(0));
+    }
+}
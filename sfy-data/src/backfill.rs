@@ -0,0 +1,43 @@
+//! Filling in the buoy status for events stored before it was kept up to date at ingest, through
+//! the `backfill-status` command. The migration that added the status only fills in the last
+//! received event, the position fixes have to be decoded by the family of the buoy.
+
+use argh::FromArgs;
+use eyre::Result;
+
+use crate::database::Database;
+
+#[derive(FromArgs, Debug)]
+/// Fill in the last position fix of the buoy status from the stored events.
+#[argh(subcommand, name = "backfill-status")]
+pub struct BackfillCmd {
+    /// buoy (dev), may be repeated (default: all buoys).
+    #[argh(option)]
+    buoy: Vec<String>,
+}
+
+impl BackfillCmd {
+    pub async fn run(&self, db: &Database) -> Result<()> {
+        let devs = if self.buoy.is_empty() {
+            db.devs().await?
+        } else {
+            self.buoy.clone()
+        };
+
+        for dev in devs {
+            let buoy = db.buoy(&dev).await?;
+
+            if buoy.family().is_none() {
+                warn!("{}: unknown buoy or buoy type, skipping", dev);
+                continue;
+            }
+
+            match buoy.backfill_fix().await? {
+                Some(p) => info!("{}: last fix at {} ({}, {})", dev, p.t, p.lat, p.lon),
+                None => info!("{}: no position fixes", dev),
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,26 +1,74 @@
 //! End-points for buoys.
 
+use crate::auth::{self, Scope};
+use crate::database::{self, Appended, Buoy, Cursor, Page, TrackPoint};
+use crate::family::{self, Family, Parsed};
+use crate::notify::Ingested;
+use crate::track::{Area, Cleaning, Track, TrackFormat};
+use crate::webhooks;
 use crate::State;
-use futures_util::future;
+use futures_util::TryStreamExt;
 use sanitize_filename::sanitize;
 use serde::{Deserialize, Serialize};
 use serde_json as json;
 use std::convert::Infallible;
 use std::sync::Arc;
-use warp::{http::Response, http::StatusCode, reject, Filter, Rejection, Reply};
+use warp::filters::BoxedFilter;
+use warp::{http::Response, http::StatusCode, hyper::Body, reject, Filter, Rejection, Reply};
 
 pub fn filters(
     state: State,
 ) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    ingest(state.clone())
+        .or(read(state.clone()))
+        .or(decoded(state))
+        .recover(handle_reject)
+}
+
+// The groups of end-points are boxed, a single chain of all the end-points is too deep for the
+// compiler (and for the stack in debug builds).
+
+/// Posting events.
+fn ingest(state: State) -> BoxedFilter<(warp::reply::Response,)> {
     append(state.clone())
+        .or(append_batch(state.clone()))
         .or(append_omb(state.clone()))
-        .or(list(state.clone()))
+        .or(append_sbd(state.clone()))
+        .or(append_spotter(state))
+        .map(Reply::into_response)
+        .boxed()
+}
+
+/// Events, tracks and positions.
+fn read(state: State) -> BoxedFilter<(warp::reply::Response,)> {
+    list(state.clone())
+        .or(status(state.clone()))
+        .or(positions(state.clone()))
+        .or(area(state.clone()))
         .or(entries(state.clone()))
         .or(last(state.clone()))
+        .or(tracks(state.clone()))
         .or(range(state.clone()))
+        .or(deployment_range(state.clone()))
+        .or(deployment_track(state.clone()))
         .or(list_range(state.clone()))
         .or(track(state.clone()))
-        .or(entry(state.clone()))
+        .or(conflicts(state.clone()))
+        .or(entry(state))
+        .map(Reply::into_response)
+        .boxed()
+}
+
+/// Decoded packages and quantities computed from them.
+fn decoded(state: State) -> BoxedFilter<(warp::reply::Response,)> {
+    axl(state.clone())
+        .or(egps(state.clone()))
+        .or(spec(state.clone()))
+        .or(waves(state.clone()))
+        .or(stitch(state.clone()))
+        .or(thermistors(state))
+        .map(Reply::into_response)
+        .boxed()
 }
 
 pub fn append(
@@ -32,11 +80,23 @@ pub fn append(
         .and(warp::post())
         .and(check_token(state.clone()))
         .and(warp::body::content_length_limit(50 * 1024 * 1024))
-        .and(warp::body::bytes())
+        .and(signed_body(state.clone()))
         .and(with_state(state.clone()))
         .and_then(handlers::append)
 }
 
+pub fn append_batch(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("buoy" / "batch")
+        .and(warp::post())
+        .and(check_token(state.clone()))
+        .and(warp::body::content_length_limit(200 * 1024 * 1024))
+        .and(signed_body(state.clone()))
+        .and(with_state(state))
+        .and_then(handlers::append_batch)
+}
+
 pub fn append_omb(
     state: State,
 ) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -46,11 +106,50 @@ pub fn append_omb(
         .and(warp::post())
         .and(check_token(state.clone()))
         .and(warp::body::content_length_limit(50 * 1024 * 1024))
-        .and(warp::body::bytes())
+        .and(signed_body(state.clone()))
         .and(with_state(state.clone()))
         .and_then(handlers::append_omb)
 }
 
+/// Raw Iridium SBD messages from the RockBLOCK web service, which cannot set headers: the token
+/// may be given as the `token` query parameter, and the web service cannot sign its requests so
+/// the route secret is given as the `secret` query parameter.
+pub fn append_sbd(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoy" / "omb" / "sbd")
+        .and(warp::post())
+        .and(check_token_or_query(state.clone()))
+        .and(check_route_secret(state.clone()))
+        .and(warp::query::<SbdQuery>())
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::form())
+        .and(with_state(state.clone()))
+        .and_then(handlers::append_sbd)
+}
+
+/// RockBLOCK account of the SBD messages, otherwise taken from the configured modem.
+#[derive(Debug, Deserialize)]
+pub struct SbdQuery {
+    account: Option<String>,
+}
+
+pub fn append_spotter(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoy" / "spotter")
+        .and(warp::post())
+        .and(check_token(state.clone()))
+        .and(warp::body::content_length_limit(50 * 1024 * 1024))
+        .and(signed_body(state.clone()))
+        .and(with_state(state.clone()))
+        .and_then(handlers::append_spotter)
+}
+
 pub fn list(
     state: State,
 ) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -63,6 +162,19 @@ pub fn list(
         .and_then(handlers::list)
 }
 
+/// Must come before `entries`, which would take `status` for a buoy.
+pub fn status(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoys" / "status")
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(with_state(state.clone()))
+        .and_then(handlers::status)
+}
+
 pub fn entries(
     state: State,
 ) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -87,6 +199,16 @@ pub fn entry(
         .and_then(handlers::entry)
 }
 
+pub fn conflicts(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("buoys" / String / "conflicts")
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(with_state(state))
+        .and_then(handlers::conflicts)
+}
+
 pub fn last(
     state: State,
 ) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -107,10 +229,22 @@ pub fn range(
     warp::path!("buoys" / String / "from" / i64 / "to" / i64)
         .and(warp::get())
         .and(check_read_token(state.clone()))
+        .and(warp::query::<RangeQuery>())
         .and(with_state(state.clone()))
         .and_then(handlers::range)
 }
 
+pub fn deployment_range(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("buoys" / String / "deployment" / i32)
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<RangeQuery>())
+        .and(with_state(state.clone()))
+        .and_then(handlers::deployment_range)
+}
+
 pub fn list_range(
     state: State,
 ) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -119,6 +253,7 @@ pub fn list_range(
     warp::path!("buoys" / "list" / String / "from" / i64 / "to" / i64)
         .and(warp::get())
         .and(check_read_token(state.clone()))
+        .and(warp::query::<RangeQuery>())
         .and(with_state(state.clone()))
         .and_then(handlers::list_range)
 }
@@ -131,94 +266,277 @@ pub fn track(
     warp::path!("buoys" / String / "track" / "from" / i64 / "to" / i64)
         .and(warp::get())
         .and(check_read_token(state.clone()))
+        .and(warp::query::<TrackQuery>())
         .and(with_state(state.clone()))
         .and_then(handlers::track)
 }
 
-fn with_state(state: State) -> impl Filter<Extract = (State,), Error = Infallible> + Clone {
+pub fn deployment_track(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("buoys" / String / "track" / "deployment" / i32)
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<TrackQuery>())
+        .and(with_state(state.clone()))
+        .and_then(handlers::deployment_track)
+}
+
+pub fn tracks(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoys" / "tracks" / "from" / i64 / "to" / i64)
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<TracksQuery>())
+        .and(with_state(state.clone()))
+        .and_then(handlers::tracks)
+}
+
+/// Must come before `entries`, which would take `positions` for a buoy.
+pub fn positions(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoys" / "positions")
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<TracksQuery>())
+        .and(with_state(state.clone()))
+        .and_then(handlers::positions)
+}
+
+/// Must come before `range`, which would take `area` for a buoy.
+pub fn area(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoys" / "area" / "from" / i64 / "to" / i64)
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<AreaQuery>())
+        .and(with_state(state.clone()))
+        .and_then(handlers::area)
+}
+
+pub fn axl(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoys" / String / "axl" / "from" / i64 / "to" / i64)
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<FormatQuery>())
+        .and(with_state(state.clone()))
+        .and_then(handlers::axl)
+}
+
+pub fn egps(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoys" / String / "egps" / "from" / i64 / "to" / i64)
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<FormatQuery>())
+        .and(with_state(state.clone()))
+        .and_then(handlers::egps)
+}
+
+pub fn spec(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoys" / String / "spec" / "from" / i64 / "to" / i64)
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<SpecQuery>())
+        .and(with_state(state.clone()))
+        .and_then(handlers::spec)
+}
+
+pub fn waves(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoys" / String / "waves" / "from" / i64 / "to" / i64)
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<WavesQuery>())
+        .and(with_state(state.clone()))
+        .and_then(handlers::waves)
+}
+
+pub fn stitch(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoys" / String / "stitch" / "from" / i64 / "to" / i64)
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(with_state(state.clone()))
+        .and_then(handlers::stitch)
+}
+
+pub fn thermistors(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let state = state.clone();
+
+    warp::path!("buoys" / String / "thermistors" / "from" / i64 / "to" / i64)
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<FormatQuery>())
+        .and(with_state(state.clone()))
+        .and_then(handlers::thermistors)
+}
+
+pub(crate) fn with_state(
+    state: State,
+) -> impl Filter<Extract = (State,), Error = Infallible> + Clone {
     warp::any().map(move || Arc::clone(&state))
 }
 
-fn check_token(state: State) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+pub(crate) fn check_token(state: State) -> impl Filter<Extract = (), Error = Rejection> + Clone {
     warp::header::<String>("SFY_AUTH_TOKEN")
-        .and_then(move |v: String| {
-            if state.config.tokens.contains(&v) {
-                future::ok(())
+        .and(with_state(state))
+        .and_then(|v: String, state: State| async move {
+            if auth::write(&state, &v).await {
+                Ok(())
             } else {
-                warn!("rejected token: {}", v);
-                future::err(reject::not_found())
+                warn!("rejected token: {}", auth::fingerprint(&v));
+                Err(reject::not_found())
             }
         })
         .untuple_one()
 }
 
-fn check_read_token(state: State) -> impl Filter<Extract = (), Error = Rejection> + Clone {
-    warp::header::<String>("SFY_AUTH_TOKEN")
-        .and_then(move |v: String| {
-            if state.config.read_tokens.contains(&v) {
-                future::ok(())
-            } else {
-                warn!("rejected token: {}", v);
-                future::err(reject::not_found())
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// Write token in the `SFY_AUTH_TOKEN` header, or in the `token` query parameter.
+pub(crate) fn check_token_or_query(
+    state: State,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("SFY_AUTH_TOKEN")
+        .and(warp::query::<TokenQuery>())
+        .and(with_state(state))
+        .and_then(
+            |header: Option<String>, query: TokenQuery, state: State| async move {
+                let v = header.or(query.token).ok_or_else(reject::not_found)?;
+
+                if auth::write(&state, &v).await {
+                    Ok(())
+                } else {
+                    warn!("rejected token: {}", auth::fingerprint(&v));
+                    Err(reject::not_found())
+                }
+            },
+        )
+        .untuple_one()
+}
+
+/// The body of the request, verifying the route signature if route secrets are configured.
+pub(crate) fn signed_body(
+    state: State,
+) -> impl Filter<Extract = (bytes::Bytes,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(auth::TIMESTAMP)
+        .and(warp::header::optional::<String>(auth::SIGNATURE))
+        .and(warp::body::bytes())
+        .and(with_state(state))
+        .and_then(
+            |timestamp: Option<String>,
+             signature: Option<String>,
+             body: bytes::Bytes,
+             state: State| async move {
+                let secrets = &state.config.route_secrets;
+                if secrets.is_empty() {
+                    return Ok(body);
+                }
+
+                let now = chrono::Utc::now().timestamp();
+                let verified = match (timestamp, signature) {
+                    (Some(t), Some(s)) => {
+                        auth::verify(secrets, state.config.signature_window, now, &t, &s, &body)
+                    }
+                    _ => Err(eyre::eyre!("missing signature")),
+                };
+
+                match verified {
+                    Ok(route) => {
+                        trace!("signature verified, route: {}", route.route);
+                        Ok(body)
+                    }
+                    Err(e) => {
+                        warn!("rejected event signature: {}", e);
+                        Err(reject::not_found())
+                    }
+                }
+            },
+        )
+}
+
+#[derive(Debug, Deserialize)]
+struct SecretQuery {
+    secret: Option<String>,
+}
+
+/// Route secret in the `secret` query parameter, if route secrets are configured. For services
+/// that cannot sign their requests.
+pub(crate) fn check_route_secret(
+    state: State,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::query::<SecretQuery>()
+        .and(with_state(state))
+        .and_then(|query: SecretQuery, state: State| async move {
+            let secrets = &state.config.route_secrets;
+            if secrets.is_empty() {
+                return Ok(());
+            }
+
+            match query
+                .secret
+                .as_deref()
+                .and_then(|s| auth::route(secrets, s))
+            {
+                Some(route) => {
+                    trace!("route secret verified, route: {}", route.route);
+                    Ok(())
+                }
+                None => {
+                    warn!("rejected route secret");
+                    Err(reject::not_found())
+                }
             }
         })
         .untuple_one()
 }
 
-#[derive(Debug)]
-struct OmbEvent {
-    device: String,
-    account: String,
-    received: u64,
-    message_type: crate::database::OmbMessageType,
-    #[allow(unused)]
-    body: json::Value,
-}
-
-fn parse_omb_data(body: &[u8]) -> eyre::Result<OmbEvent> {
-    let body: json::Value = json::from_slice(&body)?;
-
-    let device = body
-        .get("device")
-        .and_then(json::Value::as_str)
-        .map(String::from)
-        .ok_or(eyre!("no dev field"))?;
-
-    let account = body
-        .get("account")
-        .and_then(json::Value::as_str)
-        .map(String::from)
-        .ok_or(eyre!("no account field"))?;
-
-    let received = body
-        .get("datetime")
-        .and_then(json::Value::as_f64)
-        .ok_or(eyre!("no datetime field"))?;
-    let received = received.trunc() as u64;
-
-    let message_type = body
-        .get("type")
-        .and_then(json::Value::as_str)
-        .ok_or(eyre!("no type field"))?
-        .into();
-
-    Ok(OmbEvent {
-        device,
-        received,
-        message_type,
-        account,
-        body,
-    })
-}
-
-struct Event {
-    event: String,
-    device: String,
-    received: u64,
-    name: Option<String>,
-    file: Option<String>,
-    #[allow(unused)]
-    body: json::Value,
+pub(crate) fn check_read_token(
+    state: State,
+) -> impl Filter<Extract = (Scope,), Error = Rejection> + Clone {
+    warp::header::<String>("SFY_AUTH_TOKEN")
+        .and(with_state(state))
+        .and_then(|v: String, state: State| async move {
+            match auth::read(&state, &v).await {
+                Some(scope) => Ok(scope),
+                None => {
+                    warn!("rejected token: {}", auth::fingerprint(&v));
+                    Err(reject::not_found())
+                }
+            }
+        })
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -228,698 +546,3447 @@ pub struct B64Event {
     pub data: Option<String>,
 }
 
-fn parse_data(body: &[u8]) -> eyre::Result<Event> {
-    let body: json::Value = json::from_slice(&body)?;
+impl From<database::Event> for B64Event {
+    fn from(e: database::Event) -> B64Event {
+        B64Event {
+            event: e.event,
+            received: e.received,
+            data: e.data.map(base64::encode),
+        }
+    }
+}
 
-    let event = body
-        .get("event")
-        .and_then(json::Value::as_str)
-        .map(String::from)
-        .ok_or(eyre!("no event field"))?;
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct B64Conflict {
+    pub received: i64,
+    pub event: String,
+    pub message_type: String,
+    pub data: String,
+    pub reported: i64,
+}
 
-    let device = body
-        .get("device")
-        .and_then(json::Value::as_str)
-        .map(String::from)
-        .ok_or(eyre!("no dev field"))?;
+impl From<database::Conflict> for B64Conflict {
+    fn from(c: database::Conflict) -> B64Conflict {
+        B64Conflict {
+            received: c.received,
+            event: c.event,
+            message_type: c.message_type,
+            data: base64::encode(c.data),
+            reported: c.reported,
+        }
+    }
+}
 
-    let name = body
-        .get("sn")
-        .and_then(json::Value::as_str)
-        .map(String::from);
+/// Events in a batch are inserted in transactions of this many events.
+pub const BATCH_CHUNK: usize = 500;
 
-    let received = body
-        .get("received")
-        .and_then(json::Value::as_f64)
-        .unwrap_or(0.0);
-    let received = (received * 1000.).trunc() as u64;
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchStatus {
+    Inserted,
+    Duplicate,
+    Conflict,
 
-    let file = body
-        .get("file")
-        .and_then(json::Value::as_str)
-        .map(String::from);
+    /// The event could not be parsed, and was stored in lost+found.
+    Error,
+}
 
-    Ok(Event {
-        event,
-        device,
-        received,
-        name,
-        file,
-        body,
-    })
+impl From<Appended> for BatchStatus {
+    fn from(a: Appended) -> BatchStatus {
+        match a {
+            Appended::New => BatchStatus::Inserted,
+            Appended::Duplicate => BatchStatus::Duplicate,
+            Appended::Conflict => BatchStatus::Conflict,
+        }
+    }
 }
 
-// async fn handle_reject(err: Rejection) -> Result<impl Reply, Infallible> {}
+/// Outcome of an event in a batch, `index` is the position in the batch.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct BatchReport {
+    pub index: usize,
+    pub dev: Option<String>,
+    pub event: Option<String>,
+    pub status: BatchStatus,
 
-#[derive(Debug)]
-pub enum AppendErrors {
-    Database,
-    Internal,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
-impl reject::Reject for AppendErrors {}
-
-impl Into<AppendErrors> for eyre::ErrReport {
-    fn into(self: eyre::ErrReport) -> AppendErrors {
-        AppendErrors::Internal
+/// Split a batch into events: either a JSON array, or one event per line (NDJSON).
+fn split_batch(body: &[u8]) -> eyre::Result<Vec<&[u8]>> {
+    let first = body.iter().find(|c| !c.is_ascii_whitespace());
+
+    if first == Some(&b'[') {
+        let events: Vec<&json::value::RawValue> = json::from_slice(body)?;
+        Ok(events.into_iter().map(|e| e.get().as_bytes()).collect())
+    } else {
+        Ok(body
+            .split(|c| *c == b'\n')
+            .filter(|l| !l.iter().all(u8::is_ascii_whitespace))
+            .collect())
     }
 }
 
-pub mod handlers {
-    use super::*;
+/// Name of the stored event.
+fn event_file(event: &Parsed) -> String {
+    sanitize(format!(
+        "{}_{}.json",
+        event.event.as_deref().unwrap_or_default(),
+        event.message_type.as_deref().unwrap_or("__unnamed__")
+    ))
+}
 
-    pub async fn list(state: State) -> Result<impl warp::Reply, warp::Rejection> {
-        let buoys = state
-            .db
-            .buoys()
-            .await
-            .map_err(|_| reject::custom(AppendErrors::Internal))?;
-        Ok(warp::reply::json(&buoys))
-    }
+/// Output format of decoded data.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+}
 
-    pub async fn entries(buoy: String, state: State) -> Result<impl warp::Reply, warp::Rejection> {
-        let buoy = sanitize(buoy);
+/// Output format of event ranges.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RangeFormat {
+    /// A JSON array with a page of events.
+    #[default]
+    Json,
 
-        let entries = state
-            .db
-            .buoy(&buoy)
-            .await
-            .map_err(|_| reject::custom(AppendErrors::Internal))?
-            .entries()
-            .await
-            .map_err(|_| reject::custom(AppendErrors::Internal))?;
-        Ok(warp::reply::json(&entries))
-    }
+    /// Stream all events as newline-delimited JSON.
+    NdJson,
+}
 
-    pub async fn entry(
-        buoy: String,
-        entry: String,
-        state: State,
-    ) -> Result<impl warp::Reply, warp::Rejection> {
-        let buoy = sanitize(buoy);
-        let entry = sanitize(entry);
+/// Pagination of event ranges. When a page is full the cursor of the next page is returned in the
+/// `SFY-Next-After` header.
+#[derive(Debug, Deserialize, Default)]
+pub struct RangeQuery {
+    /// Maximum number of events.
+    limit: Option<i64>,
 
-        let entry = state
-            .db
-            .buoy(&buoy)
-            .await
-            .map_err(|_| reject::custom(AppendErrors::Internal))?
-            .get(entry)
-            .await
-            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+    /// Only events after this event (`received-event`).
+    after: Option<String>,
 
-        Ok(Response::builder()
-            .status(200)
-            .header("Content-Type", "application/json")
-            .body(entry))
+    #[serde(default)]
+    format: RangeFormat,
+}
+
+impl RangeQuery {
+    fn page(&self) -> Result<Page, Rejection> {
+        page(self.limit, self.after.as_deref())
     }
+}
 
-    pub async fn last(buoy: String, state: State) -> Result<impl warp::Reply, warp::Rejection> {
-        let buoy = sanitize(buoy);
+/// Parse the `limit` and `after` query parameters of a paged endpoint.
+fn page(limit: Option<i64>, after: Option<&str>) -> Result<Page, Rejection> {
+    let after = after.map(str::parse::<Cursor>).transpose().map_err(|e| {
+        warn!("invalid cursor: {:?}", e);
+        reject::custom(BadRequest)
+    })?;
+
+    if let Some(limit) = limit {
+        if limit < 1 {
+            warn!("invalid limit: {}", limit);
+            return Err(reject::custom(BadRequest));
+        }
+    }
 
-        let entry = state
-            .db
-            .buoy(&buoy)
-            .await
-            .map_err(|_| reject::custom(AppendErrors::Internal))?
-            .last()
-            .await
-            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+    Ok(Page::new(after, limit))
+}
 
-        Ok(Response::builder()
-            .status(200)
-            .header("Content-Type", "application/json")
-            .body(entry))
-    }
+/// Header with the cursor of the next page.
+pub const NEXT_AFTER: &str = "SFY-Next-After";
 
-    pub async fn range(
-        buoy: String,
-        from: i64,
-        to: i64,
-        state: State,
-    ) -> Result<impl warp::Reply, warp::Rejection> {
-        let buoy = sanitize(buoy);
+/// Number of events read from the database at a time when streaming.
+const STREAM_CHUNK: i64 = 500;
 
-        let entries: Vec<_> = state
-            .db
-            .buoy(&buoy)
-            .await
-            .map_err(|_| reject::custom(AppendErrors::Internal))?
-            .get_range(from, to)
-            .await
-            .map_err(|_| reject::custom(AppendErrors::Internal))?
-            .into_iter()
-            .map(|e| B64Event {
-                event: e.event,
-                received: e.received,
-                data: e.data.map(base64::encode),
-            })
-            .collect();
+type StreamError = Box<dyn std::error::Error + Send + Sync>;
 
-        Ok(warp::reply::json(&entries))
-    }
+/// Decoded packages of a range of events, paged like `RangeQuery`.
+#[derive(Debug, Deserialize)]
+pub struct FormatQuery {
+    #[serde(default)]
+    format: Format,
 
-    pub async fn list_range(
-        buoy: String,
-        from: i64,
-        to: i64,
-        state: State,
-    ) -> Result<impl warp::Reply, warp::Rejection> {
-        let buoy = sanitize(buoy);
+    /// Maximum number of events.
+    limit: Option<i64>,
+
+    /// Only events after this event (`received-event`).
+    after: Option<String>,
+}
+
+impl FormatQuery {
+    fn page(&self) -> Result<Page, Rejection> {
+        page(self.limit, self.after.as_deref())
+    }
+}
+
+/// Parameters of the spectra, defaults to the standard firmware build. OpenMetBuoy spectra are
+/// always computed by the firmware of the OpenMetBuoy.
+#[derive(Debug, Deserialize)]
+pub struct SpecQuery {
+    #[serde(default)]
+    format: Format,
+
+    /// Output frequency of the buoy [Hz].
+    freq: Option<f32>,
+
+    /// Segment length, defaults to the one used by the firmware for `freq`.
+    nseg: Option<usize>,
+
+    /// Maximum number of events.
+    limit: Option<i64>,
+
+    /// Only events after this event (`received-event`).
+    after: Option<String>,
+}
+
+impl SpecQuery {
+    fn page(&self) -> Result<Page, Rejection> {
+        page(self.limit, self.after.as_deref())
+    }
+
+    /// Output frequency [Hz] and segment length of the spectra.
+    fn params(&self) -> Result<(f32, usize), Rejection> {
+        use crate::decode::spec;
+
+        let freq = self.freq.unwrap_or(spec::OUTPUT_FREQ);
+
+        if freq.is_nan() || freq <= 0. {
+            warn!("invalid freq: {}", freq);
+            return Err(reject::custom(BadRequest));
+        }
+
+        let nseg = self.nseg.unwrap_or_else(|| spec::nseg(freq));
+
+        if nseg <= 2 * spec::FI1 {
+            warn!("invalid nseg: {}", nseg);
+            return Err(reject::custom(BadRequest));
+        }
+
+        Ok((freq, nseg))
+    }
+}
+
+/// Wave parameters computed from the acceleration packages.
+#[derive(Debug, Deserialize)]
+pub struct WavesQuery {
+    #[serde(default)]
+    format: Format,
+
+    /// Length of windows [s].
+    window: Option<i64>,
+
+    /// Output frequency of the buoy [Hz], defaults to the frequency of the packages.
+    freq: Option<f32>,
+
+    /// Segment length, defaults to the one used by the firmware for `freq`.
+    nseg: Option<usize>,
+}
+
+impl WavesQuery {
+    /// Length of windows [ms] and segment length, `None` for the segment length of the firmware
+    /// for the frequency of the packages.
+    fn params(&self) -> Result<(i64, Option<usize>), Rejection> {
+        use crate::decode::spec;
+        use crate::waves::series;
+
+        let window = self
+            .window
+            .unwrap_or(series::DEFAULT_WINDOW)
+            .checked_mul(1000)
+            .filter(|w| *w > 0);
+
+        let window = window.ok_or_else(|| {
+            warn!("invalid window: {:?}", self.window);
+            reject::custom(BadRequest)
+        })?;
+
+        if let Some(freq) = self.freq.filter(|f| f.is_nan() || *f <= 0.) {
+            warn!("invalid freq: {}", freq);
+            return Err(reject::custom(BadRequest));
+        }
+
+        let nseg = self.nseg.or_else(|| self.freq.map(spec::nseg));
+
+        if let Some(nseg) = nseg.filter(|n| !series::valid_nseg(*n)) {
+            warn!("invalid nseg: {}", nseg);
+            return Err(reject::custom(BadRequest));
+        }
+
+        Ok((window, nseg))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TrackQuery {
+    #[serde(default)]
+    format: TrackFormat,
+
+    /// Remove repeated fixes.
+    #[serde(default)]
+    dedup: bool,
+
+    /// Remove isolated fixes that imply a speed above this [m/s].
+    max_speed: Option<f64>,
+
+    /// Simplify the track to at most this number of fixes.
+    simplify: Option<usize>,
+
+    /// Include drift speed and heading between fixes.
+    #[serde(default)]
+    drift: bool,
+}
+
+impl TrackQuery {
+    fn cleaning(&self) -> Result<Cleaning, Rejection> {
+        if let Some(max_speed) = self.max_speed.filter(|s| s.is_nan() || *s <= 0.) {
+            warn!("invalid max_speed: {}", max_speed);
+            return Err(reject::custom(BadRequest));
+        }
+
+        Ok(Cleaning {
+            dedup: self.dedup,
+            max_speed: self.max_speed,
+            simplify: self.simplify,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TracksQuery {
+    /// Comma-separated list of buoys, defaults to all buoys.
+    buoys: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AreaQuery {
+    /// `min_lon,min_lat,max_lon,max_lat`.
+    bbox: Option<String>,
+
+    /// `lon,lat,lon,lat,..`.
+    polygon: Option<String>,
+
+    /// Comma-separated list of buoys, defaults to all buoys.
+    buoys: Option<String>,
+}
+
+impl AreaQuery {
+    fn area(&self) -> Result<Area, Rejection> {
+        let area = match (&self.bbox, &self.polygon) {
+            (Some(bbox), None) => Area::bbox(bbox),
+            (None, Some(polygon)) => Area::polygon(polygon),
+            _ => Err(eyre!("either bbox or polygon is required")),
+        };
+
+        area.map_err(|e| {
+            warn!("invalid area: {:?}", e);
+            reject::custom(BadRequest)
+        })
+    }
+}
+
+/// CSV reply with `header` and a record for each of `rows`.
+fn csv_reply<R>(
+    header: &[&str],
+    rows: impl IntoIterator<Item = R>,
+) -> Result<warp::reply::Response, Rejection>
+where
+    R: IntoIterator,
+    R::Item: AsRef<[u8]>,
+{
+    let mut w = csv::Writer::from_writer(Vec::new());
+    let err = |e| {
+        warn!("failed to write csv: {:?}", e);
+        reject::custom(AppendErrors::Internal)
+    };
+
+    w.write_record(header).map_err(err)?;
+    for row in rows {
+        w.write_record(row).map_err(err)?;
+    }
+
+    let body = w.into_inner().map_err(|e| {
+        warn!("failed to write csv: {:?}", e);
+        reject::custom(AppendErrors::Internal)
+    })?;
+
+    Ok(Response::builder()
+        .status(200)
+        .header("Content-Type", "text/csv")
+        .body(body.into())
+        .unwrap())
+}
+
+#[derive(Debug)]
+pub enum AppendErrors {
+    Database,
+    Internal,
+}
+
+impl reject::Reject for AppendErrors {}
+
+/// Invalid request, e.g. malformed query parameters.
+#[derive(Debug)]
+pub struct BadRequest;
+
+impl reject::Reject for BadRequest {}
+
+impl From<eyre::ErrReport> for AppendErrors {
+    fn from(_: eyre::ErrReport) -> AppendErrors {
+        AppendErrors::Internal
+    }
+}
+
+/// Reply `400 Bad Request` to invalid requests, other rejections are passed on.
+pub(crate) async fn handle_reject(err: Rejection) -> Result<StatusCode, Rejection> {
+    if err.find::<BadRequest>().is_some() {
+        Ok(StatusCode::BAD_REQUEST)
+    } else {
+        Err(err)
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    /// Open buoy if permitted by `scope`.
+    async fn open(state: &State, scope: &Scope, buoy: &str) -> Result<Buoy, warp::Rejection> {
+        let buoy = state
+            .db
+            .buoy(buoy)
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+        if scope.permits(&buoy) {
+            Ok(buoy)
+        } else {
+            warn!("buoy {} not permitted by token scope", buoy.dev());
+            Err(reject::not_found())
+        }
+    }
 
-        let entries = state
+    pub async fn list(scope: Scope, state: State) -> Result<impl warp::Reply, warp::Rejection> {
+        let buoys = state
             .db
-            .buoy(&buoy)
+            .buoys(|dev, product| scope.permits_dev(dev, product))
             .await
-            .map_err(|_| reject::custom(AppendErrors::Internal))?
-            .list_range(from, to)
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+        Ok(warp::reply::json(&buoys))
+    }
+
+    pub async fn status(scope: Scope, state: State) -> Result<impl warp::Reply, warp::Rejection> {
+        let status = state
+            .db
+            .status(|dev, product| scope.permits_dev(dev, product))
             .await
             .map_err(|_| reject::custom(AppendErrors::Internal))?;
+        Ok(warp::reply::json(&status))
+    }
 
-        let entries: Vec<(String, String)> = entries
-            .into_iter()
-            .map(|e| (format!("{}-{}", e.0, e.1), e.2))
-            .collect();
+    pub async fn entries(
+        buoy: String,
+        scope: Scope,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let buoy = sanitize(buoy);
 
+        let entries = open(&state, &scope, &buoy)
+            .await?
+            .entries()
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
         Ok(warp::reply::json(&entries))
     }
 
-    pub async fn track(
+    pub async fn conflicts(
         buoy: String,
-        from: i64,
-        to: i64,
+        scope: Scope,
         state: State,
     ) -> Result<impl warp::Reply, warp::Rejection> {
         let buoy = sanitize(buoy);
 
-        let points = state
-            .db
-            .buoy(&buoy)
-            .await
-            .map_err(|_| reject::custom(AppendErrors::Internal))?
-            .track(from, to)
+        let conflicts = open(&state, &scope, &buoy)
+            .await?
+            .conflicts()
             .await
             .map_err(|_| reject::custom(AppendErrors::Internal))?;
+        let conflicts: Vec<B64Conflict> = conflicts.into_iter().map(B64Conflict::from).collect();
 
-        Ok(warp::reply::json(&points))
+        Ok(warp::reply::json(&conflicts))
     }
 
-    pub async fn append(
-        body: bytes::Bytes,
+    pub async fn entry(
+        buoy: String,
+        entry: String,
+        scope: Scope,
         state: State,
     ) -> Result<impl warp::Reply, warp::Rejection> {
-        trace!("got message: {:#?}", body);
+        let buoy = sanitize(buoy);
+        let entry = sanitize(entry);
 
-        match parse_data(&body) {
-            Ok(event) => {
-                let device = sanitize(&event.device);
+        let entry = open(&state, &scope, &buoy)
+            .await?
+            .get(entry)
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
 
-                info!(
-                    "event: {} from {}({}) to file: {:?}",
-                    event.event, event.device, device, event.file
-                );
+        Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(entry))
+    }
 
-                let mut b = state.db.buoy(&device).await.map_err(|e| {
-                    error!("failed to open database for device: {}: {:?}", &device, e);
-                    reject::custom(AppendErrors::Database)
-                })?;
+    pub async fn last(
+        buoy: String,
+        scope: Scope,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let buoy = sanitize(buoy);
 
-                let file = &format!(
-                    "{}_{}.json",
-                    event.event,
-                    event.file.clone().unwrap_or_else(|| "__unnamed__".into())
-                );
-                let file = sanitize(&file);
-                debug!("writing to: {}", file);
+        let entry = open(&state, &scope, &buoy)
+            .await?
+            .last()
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
 
-                b.append(event.name, &file, event.received, event.file, &body)
-                    .await
-                    .map_err(|e| {
-                        error!("failed to write file: {:?}", e);
-                        reject::custom(AppendErrors::Database)
-                    })?;
+        Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json")
+            .body(entry))
+    }
 
-                Ok("".into_response())
-            }
+    pub async fn range(
+        buoy: String,
+        from: i64,
+        to: i64,
+        scope: Scope,
+        query: RangeQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let buoy = sanitize(buoy);
+        let page = query.page()?;
 
-            Err(e) => {
-                warn!(
-                    "could not parse event, error: {:?}, storing event in lost+found",
-                    e
-                );
-                debug!("event: {:?}", &body);
+        let buoy = open(&state, &scope, &buoy).await?;
 
-                let mut b = state.db.buoy("lost+found").await.map_err(|e| {
-                    error!("failed to open database for lost+found: {:?}", e);
-                    reject::custom(AppendErrors::Database)
-                })?;
+        if query.format == RangeFormat::NdJson {
+            return Ok(Response::builder()
+                .status(200)
+                .header("Content-Type", "application/x-ndjson")
+                .body(Body::wrap_stream(stream_range(buoy, from, to, page)))
+                .unwrap());
+        }
 
-                use std::time::{SystemTime, UNIX_EPOCH};
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .map_err(|_| reject::custom(AppendErrors::Internal))?;
+        let entries = buoy
+            .get_range_page(from, to, &page)
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
 
-                let now = if cfg!(test) { 0 } else { now.as_millis() };
+        let next = next_after(&page, entries.len(), || {
+            entries.last().map(|e| Cursor::after(e.received, &e.event))
+        });
 
-                let file = &format!("{}.json", now,);
-                let file = sanitize(&file);
-                debug!("writing to: {}", file);
+        let entries: Vec<B64Event> = entries.into_iter().map(B64Event::from).collect();
+        let body = json::to_vec(&entries).map_err(|_| reject::custom(AppendErrors::Internal))?;
 
-                b.append(None, &file, now as u64, None, &body)
-                    .await
-                    .map_err(|e| {
-                        error!("failed to write file: {:?}", e);
-                        reject::custom(AppendErrors::Database)
-                    })?;
+        Ok(paged(next).body(Body::from(body)).unwrap())
+    }
 
-                Ok(StatusCode::BAD_REQUEST.into_response())
-            }
+    /// Cursor of the next page, if the page is full.
+    fn next_after(
+        page: &Page,
+        len: usize,
+        last: impl FnOnce() -> Option<Cursor>,
+    ) -> Option<Cursor> {
+        if len as i64 >= page.limit {
+            last()
+        } else {
+            None
         }
     }
 
-    pub async fn append_omb(
-        body: bytes::Bytes,
-        state: State,
-    ) -> Result<impl warp::Reply, warp::Rejection> {
-        trace!("got message: {:#?}", body);
+    fn paged(next: Option<Cursor>) -> warp::http::response::Builder {
+        let response = Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json");
 
-        let event = parse_omb_data(&body);
-        if let Ok(event) = event {
-            let device = sanitize(&event.device);
+        match next {
+            Some(next) => response.header(NEXT_AFTER, next.to_string()),
+            None => response,
+        }
+    }
 
-            info!("omb event: {:?}", event);
+    /// Add the cursor of the next page to a response of decoded packages.
+    fn with_next(
+        mut response: warp::reply::Response,
+        next: Option<Cursor>,
+    ) -> warp::reply::Response {
+        if let Some(next) = next {
+            response.headers_mut().insert(
+                NEXT_AFTER,
+                warp::http::HeaderValue::from_str(&next.to_string()).unwrap(),
+            );
+        }
 
-            let mut b = state.db.buoy(&device).await.map_err(|e| {
-                error!("failed to open database for device: {}: {:?}", &device, e);
-                reject::custom(AppendErrors::Database)
-            })?;
+        response
+    }
 
-            b.append_omb(event.account, event.received, event.message_type, &body)
-                .await
-                .map_err(|e| {
-                    error!("failed to write file: {:?}", e);
-                    reject::custom(AppendErrors::Database)
-                })?;
+    /// Stream events as JSON lines, reading `STREAM_CHUNK` events at a time.
+    fn stream_range(
+        buoy: Buoy,
+        from: i64,
+        to: i64,
+        page: Page,
+    ) -> impl futures_util::Stream<Item = Result<Vec<u8>, StreamError>> {
+        futures_util::stream::try_unfold(
+            (buoy, page.after, page.limit),
+            move |(buoy, after, remaining)| async move {
+                if remaining <= 0 {
+                    return Ok(None);
+                }
+
+                let page = Page {
+                    after,
+                    limit: remaining.min(STREAM_CHUNK),
+                };
+
+                let events = buoy.get_range_page(from, to, &page).await?;
+                let remaining = if (events.len() as i64) < page.limit {
+                    0
+                } else {
+                    remaining - page.limit
+                };
+
+                let after = match events.last() {
+                    Some(e) => Cursor::after(e.received, &e.event),
+                    None => return Ok(None),
+                };
+
+                let mut lines = Vec::new();
+                for e in events {
+                    json::to_writer(&mut lines, &B64Event::from(e))?;
+                    lines.push(b'\n');
+                }
+
+                Ok(Some((lines, (buoy, after, remaining))))
+            },
+        )
+        .map_err(|e: eyre::ErrReport| {
+            error!("failed to stream range: {:?}", e);
+            e.to_string().into()
+        })
+    }
 
-            return Ok("".into_response());
+    /// Time range of deployment, which must be of `buoy`.
+    async fn deployment_bounds(
+        state: &State,
+        scope: &Scope,
+        buoy: &str,
+        id: i32,
+    ) -> Result<(i64, i64), warp::Rejection> {
+        let deployment = crate::deployments::deployment(state, scope, id).await?;
+
+        if deployment.dev == sanitize(buoy) {
+            Ok(deployment.range())
         } else {
-            error!("failed to parse omb event: {:?}: {:?}", event, body);
+            Err(reject::not_found())
         }
-
-        Ok(StatusCode::BAD_REQUEST.into_response())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[test]
-    fn test_parse_event_sensor_db() {
-        let event = std::fs::read("tests/events/sensor.db_01.json").unwrap();
-        let parsed = parse_data(&event).unwrap();
-
-        assert_eq!(parsed.event, "9ef2e080-f0b4-4036-8ccc-ec4206553537");
-        assert_eq!(parsed.device, "dev:864475044203262");
-        assert_eq!(parsed.file, Some(String::from("sensor.db")));
+    pub async fn deployment_range(
+        buoy: String,
+        deployment: i32,
+        scope: Scope,
+        query: RangeQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let (from, to) = deployment_bounds(&state, &scope, &buoy, deployment).await?;
+        range(buoy, from, to, scope, query, state).await
     }
 
-    #[test]
-    fn test_parse_omb_event() {
-        let event = std::fs::read("tests/events/01-omb.json").unwrap();
-        let parsed = parse_omb_data(&event).unwrap();
-        println!("{:?}", parsed);
+    pub async fn list_range(
+        buoy: String,
+        from: i64,
+        to: i64,
+        scope: Scope,
+        query: RangeQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let buoy = sanitize(buoy);
 
-        let event = br##"{"account": "gauteh@met.no", "datetime": 1654003378000.0, "device": "NOFO-OPV-2022-01", "type": "gps", "body": {"iridium_pos": {"lat": 58.92556666666667, "lon": 5.987166666666667}, "messages": [{"datetime_fix": 1654003274.0, "latitude": 58.8867932, "longitude": 5.7136341, "is_valid": true}]}}"##;
-        let parsed = parse_omb_data(event).unwrap();
-        println!("{:?}", parsed);
-    }
+        let page = query.page()?;
 
-    #[test]
-    fn test_parse_omb_imu_event() {
-        let event = std::fs::read("tests/events/02-omb-imu.json").unwrap();
-        let parsed = parse_omb_data(&event).unwrap();
-        println!("{:?}", parsed);
-    }
+        let entries = open(&state, &scope, &buoy)
+            .await?
+            .list_range_page(from, to, &page)
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+        let next = next_after(&page, entries.len(), || {
+            entries.last().map(|e| Cursor::after(e.0, &e.1))
+        });
+
+        let entries: Vec<(String, String)> = entries
+            .into_iter()
+            .map(|e| (format!("{}-{}", e.0, e.1), e.2))
+            .collect();
+        let body = json::to_vec(&entries).map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+        Ok(paged(next).body(Body::from(body)).unwrap())
+    }
+
+    pub async fn track(
+        buoy: String,
+        from: i64,
+        to: i64,
+        scope: Scope,
+        query: TrackQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let buoy = sanitize(buoy);
+
+        let cleaning = query.cleaning()?;
+        let buoy = open(&state, &scope, &buoy).await?;
+
+        let points = buoy
+            .track(from, to)
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+        let track = Track {
+            dev: buoy.dev().to_string(),
+            name: buoy.name().map(String::from),
+            points: cleaning.apply(points),
+            drift: query.drift,
+        };
+
+        let body = track
+            .format(query.format)
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+        Ok(Response::builder()
+            .status(200)
+            .header("Content-Type", query.format.content_type())
+            .body(body)
+            .unwrap())
+    }
+
+    pub async fn deployment_track(
+        buoy: String,
+        deployment: i32,
+        scope: Scope,
+        query: TrackQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let (from, to) = deployment_bounds(&state, &scope, &buoy, deployment).await?;
+        track(buoy, from, to, scope, query, state).await
+    }
+
+    /// Buoys in a comma-separated list.
+    fn buoy_list(buoys: &str) -> Vec<String> {
+        buoys
+            .split(',')
+            .filter(|b| !b.is_empty())
+            .map(sanitize)
+            .collect()
+    }
+
+    /// The comma-separated list of buoys, or all buoys.
+    async fn devs(state: &State, buoys: Option<String>) -> Result<Vec<String>, warp::Rejection> {
+        match buoys {
+            Some(buoys) => Ok(buoy_list(&buoys)),
+            None => state
+                .db
+                .devs()
+                .await
+                .map_err(|_| reject::custom(AppendErrors::Internal)),
+        }
+    }
+
+    /// The tracks of the permitted buoys in `devs`, with the fixes that `keep`.
+    async fn tracks_of(
+        state: &State,
+        scope: &Scope,
+        devs: Vec<String>,
+        from: i64,
+        to: i64,
+        keep: impl Fn(&TrackPoint) -> bool,
+    ) -> Result<Vec<Track>, warp::Rejection> {
+        let mut tracks = Vec::with_capacity(devs.len());
+
+        for dev in devs {
+            let buoy = state
+                .db
+                .buoy(&dev)
+                .await
+                .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+            // Unknown buoys have no family, and no track.
+            if buoy.family().is_none() || !scope.permits(&buoy) {
+                continue;
+            }
+
+            let mut points = buoy
+                .track(from, to)
+                .await
+                .map_err(|_| reject::custom(AppendErrors::Internal))?;
+            points.retain(&keep);
+
+            tracks.push(Track {
+                dev: buoy.dev().to_string(),
+                name: buoy.name().map(String::from),
+                points,
+                drift: false,
+            });
+        }
+
+        Ok(tracks)
+    }
+
+    fn geojson_reply(tracks: &[Track]) -> impl warp::Reply {
+        warp::reply::with_header(
+            warp::reply::json(&crate::track::geojson(tracks)),
+            "Content-Type",
+            TrackFormat::GeoJson.content_type(),
+        )
+    }
+
+    pub async fn tracks(
+        from: i64,
+        to: i64,
+        scope: Scope,
+        query: TracksQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let devs = devs(&state, query.buoys).await?;
+        let tracks = tracks_of(&state, &scope, devs, from, to, |_| true).await?;
+
+        Ok(geojson_reply(&tracks))
+    }
+
+    /// Fixes of the buoys inside an area.
+    pub async fn area(
+        from: i64,
+        to: i64,
+        scope: Scope,
+        query: AreaQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let area = query.area()?;
+        let devs = devs(&state, query.buoys).await?;
+
+        let mut tracks = tracks_of(&state, &scope, devs, from, to, |p| area.contains(p)).await?;
+        tracks.retain(|t| !t.points.is_empty());
+
+        Ok(geojson_reply(&tracks))
+    }
+
+    /// The latest position of each buoy, from the buoy status.
+    pub async fn positions(
+        scope: Scope,
+        query: TracksQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let devs = query.buoys.as_deref().map(buoy_list);
+
+        let status = state
+            .db
+            .status(|dev, product| {
+                scope.permits_dev(dev, product)
+                    && devs
+                        .as_ref()
+                        .map(|devs| devs.iter().any(|d| d == dev))
+                        .unwrap_or(true)
+            })
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+        let tracks: Vec<Track> = status
+            .into_iter()
+            .filter_map(|s| {
+                let point = TrackPoint {
+                    t: s.fix_time? as f64 / 1000.,
+                    lat: s.lat?,
+                    lon: s.lon?,
+                };
+
+                Some(Track {
+                    dev: s.dev,
+                    name: s.name,
+                    points: vec![point],
+                    drift: false,
+                })
+            })
+            .collect();
+
+        Ok(geojson_reply(&tracks))
+    }
+
+    pub async fn axl(
+        buoy: String,
+        from: i64,
+        to: i64,
+        scope: Scope,
+        query: FormatQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        use crate::decode::{axl::AxlPacket, decode_events};
+
+        let buoy = sanitize(buoy);
+        let page = query.page()?;
+
+        let events = open(&state, &scope, &buoy)
+            .await?
+            .axl_range(from, to, &page)
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+        let next = next_after(&page, events.len(), || {
+            events.last().map(|e| Cursor::after(e.received, &e.event))
+        });
+
+        let packets = decode_events(events, AxlPacket::decode);
+
+        let response = match query.format {
+            Format::Json => warp::reply::json(&packets).into_response(),
+            Format::Csv => {
+                let rows = packets.iter().flat_map(|p| {
+                    (0..p.packet.time.len()).map(move |i| {
+                        [
+                            p.received.to_string(),
+                            p.event.clone(),
+                            p.packet.time[i].to_string(),
+                            p.packet.x[i].to_string(),
+                            p.packet.y[i].to_string(),
+                            p.packet.z[i].to_string(),
+                        ]
+                    })
+                });
+
+                csv_reply(&["received", "event", "time", "x", "y", "z"], rows)?
+            }
+        };
+
+        Ok(with_next(response, next))
+    }
+
+    pub async fn egps(
+        buoy: String,
+        from: i64,
+        to: i64,
+        scope: Scope,
+        query: FormatQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        use crate::decode::{decode_events, egps::EgpsPacket};
+
+        let buoy = sanitize(buoy);
+        let page = query.page()?;
+
+        let events = open(&state, &scope, &buoy)
+            .await?
+            .egps_range(from, to, &page)
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+        let next = next_after(&page, events.len(), || {
+            events.last().map(|e| Cursor::after(e.received, &e.event))
+        });
+
+        let packets = decode_events(events, EgpsPacket::decode);
+
+        let response = match query.format {
+            Format::Json => warp::reply::json(&packets).into_response(),
+            Format::Csv => {
+                let rows = packets.iter().flat_map(|p| {
+                    (0..p.packet.time.len()).map(move |i| {
+                        [
+                            p.received.to_string(),
+                            p.event.clone(),
+                            p.packet.time[i].to_string(),
+                            p.packet.lon[i].to_string(),
+                            p.packet.lat[i].to_string(),
+                            p.packet.msl[i].to_string(),
+                            p.packet.vn[i].to_string(),
+                            p.packet.ve[i].to_string(),
+                            p.packet.vd[i].to_string(),
+                        ]
+                    })
+                });
+
+                csv_reply(
+                    &[
+                        "received", "event", "time", "lon", "lat", "msl", "vn", "ve", "vd",
+                    ],
+                    rows,
+                )?
+            }
+        };
+
+        Ok(with_next(response, next))
+    }
+
+    pub async fn spec(
+        buoy: String,
+        from: i64,
+        to: i64,
+        scope: Scope,
+        query: SpecQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        use crate::database::OmbMessageType;
+        use crate::decode::{decode_events, spec};
+
+        let buoy = sanitize(buoy);
+        let (freq, nseg) = query.params()?;
+
+        let page = query.page()?;
+
+        let buoy = open(&state, &scope, &buoy).await?;
+        let omb = buoy.buoy_type() == family::Omb.name();
+
+        let events = if omb {
+            buoy.omb_range(from, to, OmbMessageType::Imu, &page).await
+        } else {
+            buoy.spec_range(from, to, &page).await
+        }
+        .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+        let next = next_after(&page, events.len(), || {
+            events.last().map(|e| Cursor::after(e.received, &e.event))
+        });
+
+        let packets = if omb {
+            decode_events(events, spec::SpecPacket::decode_omb)
+        } else {
+            decode_events(events, |data, message_type| {
+                spec::SpecPacket::decode(data, message_type, freq, nseg)
+            })
+        };
+
+        let response = match query.format {
+            Format::Json => warp::reply::json(&packets).into_response(),
+            Format::Csv => {
+                let rows = packets.iter().map(|p| {
+                    let s = &p.packet.stats;
+
+                    [
+                        p.received.to_string(),
+                        p.event.clone(),
+                        p.packet.timestamp.to_string(),
+                        s.hm0.to_string(),
+                        s.tp.to_string(),
+                        s.tm01.to_string(),
+                        s.tm02.to_string(),
+                    ]
+                });
+
+                csv_reply(
+                    &[
+                        "received",
+                        "event",
+                        "timestamp",
+                        "hm0",
+                        "tp",
+                        "tm01",
+                        "tm02",
+                    ],
+                    rows,
+                )?
+            }
+        };
+
+        Ok(with_next(response, next))
+    }
+
+    pub async fn waves(
+        buoy: String,
+        from: i64,
+        to: i64,
+        scope: Scope,
+        query: WavesQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        use crate::waves::series;
+
+        let buoy = sanitize(buoy);
+        let (window, nseg) = query.params()?;
+
+        let buoy = open(&state, &scope, &buoy).await?;
+
+        let stats = series::wave_stats(&buoy, from, to, window, nseg)
+            .await
+            .map_err(|e| {
+                warn!("{}: failed to compute wave parameters: {:?}", buoy.dev(), e);
+                reject::custom(AppendErrors::Internal)
+            })?;
+
+        match query.format {
+            Format::Json => Ok(warp::reply::json(&stats).into_response()),
+            Format::Csv => {
+                let rows = stats.iter().map(|s| {
+                    let field = |f: fn(&crate::waves::Stats) -> f64| {
+                        s.stats
+                            .as_ref()
+                            .map(|st| f(st).to_string())
+                            .unwrap_or_default()
+                    };
+
+                    [
+                        s.start.to_string(),
+                        s.window.to_string(),
+                        s.segments.to_string(),
+                        s.gaps.to_string(),
+                        s.coverage.to_string(),
+                        field(|st| st.hm0),
+                        field(|st| st.tp),
+                        field(|st| st.tm01),
+                        field(|st| st.tm02),
+                    ]
+                });
+
+                csv_reply(
+                    &[
+                        "start", "window", "segments", "gaps", "coverage", "hm0", "tp", "tm01",
+                        "tm02",
+                    ],
+                    rows,
+                )
+            }
+        }
+    }
+
+    /// Continuous intervals of the acceleration packages with the first sample in the range.
+    pub async fn stitch(
+        buoy: String,
+        from: i64,
+        to: i64,
+        scope: Scope,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        use crate::decode::{axl::AxlPacket, decode_events};
+        use crate::stitch::Report;
+        use crate::waves::series::MAX_DELAY;
+
+        let buoy = sanitize(buoy);
+
+        // Packages are received after they are sampled, but not more than `MAX_DELAY` after.
+        let events = open(&state, &scope, &buoy)
+            .await?
+            .axl_range(from, to.saturating_add(MAX_DELAY), &Page::all())
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+        let (from, to) = (from as f64, to as f64);
+        let packets = decode_events(events, AxlPacket::decode)
+            .into_iter()
+            .map(|d| d.packet)
+            .filter(|p| matches!(p.time.first(), Some(t) if *t >= from && *t < to))
+            .collect();
+
+        Ok(warp::reply::json(&Report::new(packets)))
+    }
+
+    pub async fn thermistors(
+        buoy: String,
+        from: i64,
+        to: i64,
+        scope: Scope,
+        query: FormatQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        use crate::database::OmbMessageType;
+        use crate::decode::{decode_events, omb::Thermistors};
+
+        let buoy = sanitize(buoy);
+        let page = query.page()?;
+
+        let events = open(&state, &scope, &buoy)
+            .await?
+            .omb_range(from, to, OmbMessageType::Thermistor, &page)
+            .await
+            .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+        let next = next_after(&page, events.len(), || {
+            events.last().map(|e| Cursor::after(e.received, &e.event))
+        });
+
+        let packets = decode_events(events, Thermistors::decode);
+
+        let response = match query.format {
+            Format::Json => warp::reply::json(&packets).into_response(),
+            Format::Csv => {
+                let rows = packets.iter().flat_map(|p| {
+                    p.packet.profiles.iter().flat_map(move |profile| {
+                        profile.temperatures.iter().enumerate().map(move |(i, t)| {
+                            [
+                                p.received.to_string(),
+                                p.event.clone(),
+                                profile.datetime_packet.to_string(),
+                                profile.mean_pitch.to_string(),
+                                profile.mean_roll.to_string(),
+                                i.to_string(),
+                                t.to_string(),
+                            ]
+                        })
+                    })
+                });
+
+                csv_reply(
+                    &[
+                        "received",
+                        "event",
+                        "time",
+                        "mean_pitch",
+                        "mean_roll",
+                        "sensor",
+                        "temperature",
+                    ],
+                    rows,
+                )?
+            }
+        };
+
+        Ok(with_next(response, next))
+    }
+
+    pub async fn append(
+        body: bytes::Bytes,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        trace!("got message: {:#?}", body);
+
+        match family::Sfy.parse(&body) {
+            Ok(event) => {
+                let device = sanitize(&event.device);
+
+                info!(
+                    "event: {:?} from {}({}) to file: {:?}",
+                    event.event, event.device, device, event.message_type
+                );
+
+                let mut b = state.db.buoy(&device).await.map_err(|e| {
+                    error!("failed to open database for device: {}: {:?}", &device, e);
+                    reject::custom(AppendErrors::Database)
+                })?;
+
+                let file = event_file(&event);
+                debug!("writing to: {}", file);
+
+                let message_type = event
+                    .message_type
+                    .clone()
+                    .unwrap_or_else(|| "unknown".into());
+
+                let db_error = |e: eyre::Report| {
+                    error!("failed to write file: {:?}", e);
+                    reject::custom(AppendErrors::Database)
+                };
+
+                let mut tx = state.db.begin().await.map_err(db_error)?;
+                let appended = b
+                    .append_in(
+                        &mut tx,
+                        event.name,
+                        &file,
+                        event.received,
+                        event.message_type,
+                        &body,
+                    )
+                    .await
+                    .map_err(db_error)?;
+
+                // Retried events are acknowledged, so that Notehub stops retrying them.
+                let reply = match appended {
+                    Appended::New => None,
+                    Appended::Duplicate => Some("duplicate"),
+                    Appended::Conflict => Some("conflict"),
+                };
+                if let Some(reply) = reply {
+                    tx.commit().await.map_err(|e| db_error(e.into()))?;
+                    return Ok(reply.into_response());
+                }
+
+                let ingested = Ingested {
+                    dev: device,
+                    product: event
+                        .product
+                        .clone()
+                        .or_else(|| b.product().map(String::from)),
+                    buoy_type: b.buoy_type(),
+                    event: file,
+                    received: event.received as i64,
+                    message_type,
+                    data: body,
+                };
+
+                webhooks::enqueue(&state, &mut tx, &ingested)
+                    .await
+                    .map_err(db_error)?;
+                tx.commit().await.map_err(|e| db_error(e.into()))?;
+
+                if let Some(product) = event.product {
+                    b.set_product(&product).await.map_err(|e| {
+                        error!("failed to update product: {:?}", e);
+                        reject::custom(AppendErrors::Database)
+                    })?;
+                }
+
+                state.notifier.notify(ingested);
+
+                Ok("".into_response())
+            }
+
+            Err(e) => {
+                warn!(
+                    "could not parse event, error: {:?}, storing event in lost+found",
+                    e
+                );
+                debug!("event: {:?}", &body);
+
+                let mut b = state.db.buoy("lost+found").await.map_err(|e| {
+                    error!("failed to open database for lost+found: {:?}", e);
+                    reject::custom(AppendErrors::Database)
+                })?;
+
+                use std::time::{SystemTime, UNIX_EPOCH};
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_err(|_| reject::custom(AppendErrors::Internal))?;
+
+                let now = if cfg!(test) { 0 } else { now.as_millis() };
+
+                let file = &format!("{}.json", now,);
+                let file = sanitize(file);
+                debug!("writing to: {}", file);
+
+                b.append(None, &file, now as u64, None, &body)
+                    .await
+                    .map_err(|e| {
+                        error!("failed to write file: {:?}", e);
+                        reject::custom(AppendErrors::Database)
+                    })?;
+
+                Ok(StatusCode::BAD_REQUEST.into_response())
+            }
+        }
+    }
+
+    /// Buoy opened once per batch chunk.
+    async fn cached<'a>(
+        buoys: &'a mut std::collections::HashMap<String, Buoy>,
+        db: &database::Database,
+        dev: &str,
+    ) -> eyre::Result<&'a mut Buoy> {
+        if !buoys.contains_key(dev) {
+            let b = db.buoy(dev).await?;
+            buoys.insert(dev.to_string(), b);
+        }
+
+        Ok(buoys.get_mut(dev).unwrap())
+    }
+
+    pub async fn append_batch(
+        body: bytes::Bytes,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        use std::collections::HashMap;
+
+        let events = match split_batch(&body) {
+            Ok(events) => events,
+            Err(e) => {
+                warn!("could not parse batch: {:?}", e);
+                return Ok(StatusCode::BAD_REQUEST.into_response());
+            }
+        };
+
+        info!("batch of {} events", events.len());
+
+        let db_error = |e: eyre::Report| {
+            error!("failed to append batch: {:?}", e);
+            reject::custom(AppendErrors::Database)
+        };
+
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut report = Vec::with_capacity(events.len());
+
+        for (c, chunk) in events.chunks(BATCH_CHUNK).enumerate() {
+            let mut tx = state.db.begin().await.map_err(db_error)?;
+            let mut buoys: HashMap<String, Buoy> = HashMap::new();
+            let mut products = Vec::new();
+            let mut ingested = Vec::new();
+
+            for (i, data) in chunk.iter().enumerate() {
+                let index = c * BATCH_CHUNK + i;
+
+                match family::Sfy.parse(data) {
+                    Ok(event) => {
+                        let device = sanitize(&event.device);
+                        let file = event_file(&event);
+                        let message_type = event
+                            .message_type
+                            .clone()
+                            .unwrap_or_else(|| "unknown".into());
+
+                        let b = cached(&mut buoys, &state.db, &device)
+                            .await
+                            .map_err(db_error)?;
+                        let appended = b
+                            .append_in(
+                                &mut tx,
+                                event.name,
+                                &file,
+                                event.received,
+                                event.message_type,
+                                data,
+                            )
+                            .await
+                            .map_err(db_error)?;
+
+                        if appended == Appended::New {
+                            if let Some(product) = &event.product {
+                                products.push((device.clone(), product.clone()));
+                            }
+
+                            ingested.push(Ingested {
+                                dev: device.clone(),
+                                product: event.product.or_else(|| b.product().map(String::from)),
+                                buoy_type: b.buoy_type(),
+                                event: file.clone(),
+                                received: event.received as i64,
+                                message_type,
+                                data: body.slice_ref(data),
+                            });
+                        }
+
+                        report.push(BatchReport {
+                            index,
+                            dev: Some(device),
+                            event: Some(file),
+                            status: appended.into(),
+                            error: None,
+                        });
+                    }
+                    Err(e) => {
+                        warn!(
+                            "could not parse event {} in batch, storing in lost+found: {:?}",
+                            index, e
+                        );
+
+                        let file = sanitize(format!("{}-{}.json", now, index));
+                        cached(&mut buoys, &state.db, "lost+found")
+                            .await
+                            .map_err(db_error)?
+                            .append_in(&mut tx, None, &file, now as u64, None, data)
+                            .await
+                            .map_err(db_error)?;
+
+                        report.push(BatchReport {
+                            index,
+                            dev: None,
+                            event: None,
+                            status: BatchStatus::Error,
+                            error: Some(e.to_string()),
+                        });
+                    }
+                }
+            }
+
+            for ingested in &ingested {
+                webhooks::enqueue(&state, &mut tx, ingested)
+                    .await
+                    .map_err(db_error)?;
+            }
+
+            tx.commit().await.map_err(|e| db_error(e.into()))?;
+
+            for (device, product) in products {
+                if let Some(b) = buoys.get_mut(&device) {
+                    b.set_product(&product).await.map_err(db_error)?;
+                }
+            }
+
+            for ingested in ingested {
+                state.notifier.notify(ingested);
+            }
+        }
+
+        Ok(warp::reply::json(&report).into_response())
+    }
+
+    pub async fn append_omb(
+        body: bytes::Bytes,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        trace!("got message: {:#?}", body);
+
+        let event = family::Omb.parse(&body);
+        if let Ok(event) = event {
+            let device = sanitize(&event.device);
+
+            info!("omb event: {:?}", event);
+
+            let mut b = state.db.buoy(&device).await.map_err(|e| {
+                error!("failed to open database for device: {}: {:?}", &device, e);
+                reject::custom(AppendErrors::Database)
+            })?;
+
+            let account = event.product.unwrap_or_default();
+            let message_type = event.message_type.as_deref().unwrap_or_default().into();
+
+            let db_error = |e: eyre::Report| {
+                error!("failed to write file: {:?}", e);
+                reject::custom(AppendErrors::Database)
+            };
+
+            let mut tx = state.db.begin().await.map_err(db_error)?;
+            let id = b
+                .append_omb_in(
+                    &mut tx,
+                    account.clone(),
+                    event.received,
+                    message_type,
+                    &body,
+                )
+                .await
+                .map_err(db_error)?;
+
+            let message_type = message_type.to_str();
+            let ingested = Ingested {
+                dev: device,
+                product: Some(account.clone()),
+                buoy_type: b.buoy_type(),
+                event: format!("{}-{}", id, message_type),
+                received: event.received as i64,
+                message_type: message_type.into(),
+                data: body,
+            };
+
+            webhooks::enqueue(&state, &mut tx, &ingested)
+                .await
+                .map_err(db_error)?;
+            tx.commit().await.map_err(|e| db_error(e.into()))?;
+
+            b.set_product(&account).await.map_err(|e| {
+                error!("failed to update product: {:?}", e);
+                reject::custom(AppendErrors::Database)
+            })?;
+
+            state.notifier.notify(ingested);
+
+            return Ok("".into_response());
+        } else {
+            error!("failed to parse omb event: {:?}: {:?}", event, body);
+        }
+
+        Ok(StatusCode::BAD_REQUEST.into_response())
+    }
+
+    pub async fn append_sbd(
+        query: SbdQuery,
+        sbd: family::Sbd,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        trace!("got SBD message: {:?}", sbd);
+
+        let modem = state.config.modems.iter().find(|m| m.imei == sbd.imei);
+        let device = modem.map_or(sbd.imei.as_str(), |m| m.device.as_str());
+
+        // Without a known account the product of the buoy is kept.
+        let account = match query
+            .account
+            .or_else(|| modem.and_then(|m| m.account.clone()))
+        {
+            Some(account) => account,
+            None => {
+                let b = state.db.buoy(&sanitize(device)).await.map_err(|e| {
+                    error!("failed to open database for device: {}: {:?}", device, e);
+                    reject::custom(AppendErrors::Database)
+                })?;
+                b.product().unwrap_or(family::Sbd::ACCOUNT).to_string()
+            }
+        };
+
+        match sbd.event(device, &account) {
+            Ok(event) => {
+                let body = json::to_vec(&event).map_err(|e| {
+                    error!("failed to serialize SBD event: {:?}", e);
+                    reject::custom(AppendErrors::Internal)
+                })?;
+
+                append_omb(body.into(), state)
+                    .await
+                    .map(Reply::into_response)
+            }
+            Err(e) => {
+                error!("failed to parse SBD message: {:?}: {:?}", e, sbd);
+                Ok(StatusCode::BAD_REQUEST.into_response())
+            }
+        }
+    }
+
+    pub async fn append_spotter(
+        body: bytes::Bytes,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        trace!("got message: {:#?}", body);
+
+        let events = match family::Spotter.split(&body) {
+            Ok(events) => events,
+            Err(e) => {
+                error!("failed to parse spotter payload: {:?}: {:?}", e, body);
+                return Ok(StatusCode::BAD_REQUEST.into_response());
+            }
+        };
+
+        // Nothing is stored unless every event parses.
+        let parsed = match events
+            .iter()
+            .map(|data| family::Spotter.parse(data))
+            .collect::<eyre::Result<Vec<_>>>()
+        {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                error!("failed to parse spotter event: {:?}: {:?}", e, body);
+                return Ok(StatusCode::BAD_REQUEST.into_response());
+            }
+        };
+
+        let db_error = |e: eyre::Report| {
+            error!("failed to write file: {:?}", e);
+            reject::custom(AppendErrors::Database)
+        };
+
+        // The events of a payload are from the same Spotter.
+        let device = match parsed.first() {
+            Some(event) => sanitize(&event.device),
+            None => return Ok("".into_response()),
+        };
+
+        let mut b = state.db.buoy(&device).await.map_err(|e| {
+            error!("failed to open database for device: {}: {:?}", &device, e);
+            reject::custom(AppendErrors::Database)
+        })?;
+        b.set_family(&family::Spotter);
+
+        let mut tx = state.db.begin().await.map_err(db_error)?;
+        let mut ingested = Vec::new();
+
+        for (event, data) in parsed.into_iter().zip(events) {
+            let file = event_file(&event);
+            let message_type = event.message_type.clone().unwrap_or_default();
+
+            info!("spotter event: {} from {}", file, device);
+
+            let appended = b
+                .append_in(
+                    &mut tx,
+                    event.name,
+                    &file,
+                    event.received,
+                    event.message_type,
+                    &data,
+                )
+                .await
+                .map_err(db_error)?;
+
+            if appended != Appended::New {
+                continue;
+            }
+
+            let e = Ingested {
+                dev: device.clone(),
+                product: b.product().map(String::from),
+                buoy_type: b.buoy_type(),
+                event: file,
+                received: event.received as i64,
+                message_type,
+                data: data.into(),
+            };
+
+            webhooks::enqueue(&state, &mut tx, &e)
+                .await
+                .map_err(db_error)?;
+            ingested.push(e);
+        }
+
+        tx.commit().await.map_err(|e| db_error(e.into()))?;
+
+        for e in ingested {
+            state.notifier.notify(e);
+        }
+
+        Ok("".into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn check_token_ok() {
+        let state = crate::test_state().await;
+
+        let f = check_token(state);
+
+        assert!(warp::test::request()
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "wrong-token")
+            .filter(&f)
+            .await
+            .is_err());
+
+        assert!(warp::test::request()
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .filter(&f)
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn append_to_database() {
+        let state = crate::test_state().await;
+        let event = std::fs::read("tests/events/sensor.db_01.json").unwrap();
+
+        let f = filters(state.clone());
+
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "wrong-token")
+            .body(&event)
+            .reply(&f)
+            .await;
+
+        assert!(res.status() != 200);
+
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(&event)
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let e = state
+            .db
+            .buoy("dev864475044203262")
+            .await
+            .unwrap()
+            .get("1639059643089-9ef2e080-f0b4-4036-8ccc-ec4206553537_sensor.db.json")
+            .await
+            .unwrap();
+
+        assert_eq!(&e, &event);
+    }
+
+    #[tokio::test]
+    async fn append_batch() {
+        let state = crate::test_state().await;
+        let f = filters(state.clone());
+
+        let egps = testing::event("sfy4-egpsb.qo.json", "dev:batch");
+        let axl = testing::event("sfy4-axlb.qo.json", "dev:batch");
+
+        let batch = json::json!([egps, egps, { "not": "an event" }]);
+
+        let res = warp::test::request()
+            .path("/buoy/batch")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(json::to_vec(&batch).unwrap())
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let report: Vec<BatchReport> = json::from_slice(res.body()).unwrap();
+        let status: Vec<_> = report.iter().map(|r| r.status).collect();
+        assert_eq!(
+            status,
+            [
+                BatchStatus::Inserted,
+                BatchStatus::Duplicate,
+                BatchStatus::Error
+            ]
+        );
+        assert_eq!(report[0].dev.as_deref(), Some("devbatch"));
+        assert_eq!(report[2].index, 2);
+        assert!(report[2].error.is_some());
+
+        // NDJSON
+        let mut lines = Vec::new();
+        for e in [&egps, &axl] {
+            json::to_writer(&mut lines, e).unwrap();
+            lines.extend_from_slice(b"\n\n");
+        }
+
+        let res = warp::test::request()
+            .path("/buoy/batch")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(lines)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let report: Vec<BatchReport> = json::from_slice(res.body()).unwrap();
+        let status: Vec<_> = report.iter().map(|r| r.status).collect();
+        assert_eq!(status, [BatchStatus::Duplicate, BatchStatus::Inserted]);
+
+        let b = state.db.buoy("devbatch").await.unwrap();
+        assert_eq!(b.entries().await.unwrap().len(), 2);
+        assert_eq!(b.product(), Some("product:no.met.gauteh:sfy"));
+
+        let res = warp::test::request()
+            .path("/buoy/batch")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body("[{]")
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn signed_event() {
+        let mut state = crate::test_state().await;
+        Arc::get_mut(&mut state).unwrap().config.route_secrets = vec![auth::RouteSecret {
+            route: "test".into(),
+            secret: "route-secret".into(),
+        }];
+        let f = filters(state.clone());
+
+        let event = testing::event("sfy4-egpsb.qo.json", "dev:signed");
+        let event = json::to_vec(&event).unwrap();
+
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(&event)
+            .reply(&f)
+            .await;
+        assert!(res.status().is_client_error());
+
+        let old = (chrono::Utc::now().timestamp() - 3600).to_string();
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .header(auth::TIMESTAMP, &old)
+            .header(auth::SIGNATURE, auth::sign("route-secret", &old, &event))
+            .body(&event)
+            .reply(&f)
+            .await;
+        assert!(res.status().is_client_error());
+
+        let now = chrono::Utc::now().timestamp().to_string();
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .header(auth::TIMESTAMP, &now)
+            .header(auth::SIGNATURE, auth::sign("wrong-secret", &now, &event))
+            .body(&event)
+            .reply(&f)
+            .await;
+        assert!(res.status().is_client_error());
+
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .header(auth::TIMESTAMP, &now)
+            .header(auth::SIGNATURE, auth::sign("route-secret", &now, &event))
+            .body(&event)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn signed_routes() {
+        let mut state = crate::test_state().await;
+        Arc::get_mut(&mut state).unwrap().config.route_secrets = vec![auth::RouteSecret {
+            route: "test".into(),
+            secret: "route-secret".into(),
+        }];
+        let f = filters(state.clone());
+
+        let mut omb: json::Value =
+            json::from_slice(&std::fs::read("tests/events/01-omb.json").unwrap()).unwrap();
+        omb["device"] = "OMB-SIGNED-1".into();
+        let omb = json::to_vec(&omb).unwrap();
+        let spotter = std::fs::read("tests/events/spotter-01.json").unwrap();
+
+        for (path, body) in [("/buoy/omb", &omb), ("/buoy/spotter", &spotter)] {
+            let res = warp::test::request()
+                .path(path)
+                .method("POST")
+                .header("SFY_AUTH_TOKEN", "token1")
+                .body(body)
+                .reply(&f)
+                .await;
+            assert!(res.status().is_client_error(), "{}", path);
+
+            let now = chrono::Utc::now().timestamp().to_string();
+            let res = warp::test::request()
+                .path(path)
+                .method("POST")
+                .header("SFY_AUTH_TOKEN", "token1")
+                .header(auth::TIMESTAMP, &now)
+                .header(auth::SIGNATURE, auth::sign("route-secret", &now, body))
+                .body(body)
+                .reply(&f)
+                .await;
+            assert_eq!(res.status(), 200, "{}", path);
+        }
+
+        let gps = "47".to_string()
+            + &hex::encode(1742754000u32.to_le_bytes())
+            + &hex::encode(338623160i32.to_le_bytes())
+            + &hex::encode(177100660i32.to_le_bytes())
+            + "45";
+        let form = format!(
+            "imei=300434065196262&momsn=1&transmit_time=25-03-23%2018%3A22%3A01&iridium_latitude=33.8623&iridium_longitude=17.7101&iridium_cep=3.0&data={}",
+            gps
+        );
+
+        for (query, ok) in [
+            ("token=token1", false),
+            ("token=token1&secret=wrong", false),
+            ("token=token1&secret=route-secret", true),
+        ] {
+            let res = warp::test::request()
+                .path(&format!("/buoy/omb/sbd?{}", query))
+                .method("POST")
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(&form)
+                .reply(&f)
+                .await;
+            assert_eq!(res.status() == 200, ok, "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicate_event() {
+        let state = crate::test_state().await;
+        let f = filters(state.clone());
+
+        let mut event = testing::event("sfy4-egpsb.qo.json", "dev:duplicate");
+
+        let mut rx = state.notifier.subscribe();
+
+        for reply in ["", "duplicate"] {
+            let res = testing::post(&f, &event).await;
+            assert_eq!(res.status(), 200);
+            assert_eq!(res.body(), reply);
+        }
+
+        // Retries are not passed on.
+        assert_eq!(rx.recv().await.unwrap().dev, "devduplicate");
+        assert!(rx.try_recv().is_err());
+
+        event["body"]["lat"] = 0.into();
+        let res = testing::post(&f, &event).await;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.body(), "conflict");
+
+        let res = warp::test::request()
+            .path("/buoys/devduplicate/conflicts")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let conflicts: Vec<B64Conflict> = json::from_slice(res.body()).unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].message_type, "egpsb.qo");
+
+        let data: json::Value =
+            json::from_slice(&base64::decode(&conflicts[0].data).unwrap()).unwrap();
+        assert_eq!(data["body"]["lat"], 0);
+    }
+
+    #[tokio::test]
+    async fn bad_event() {
+        let state = crate::test_state().await;
+        let event = br#"{ "noevent": "asdf", "something": "hey", bad json even }"#;
+
+        let f = filters(state);
+
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(event)
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn list_buoys() {
+        let state = crate::test_state().await;
+        let event = std::fs::read("tests/events/sensor.db_02.json").unwrap();
+
+        let f = filters(state);
+
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(&event)
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        // Missing token
+        let res = warp::test::request()
+            .path("/buoys")
+            .method("GET")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 400);
+
+        let res = warp::test::request()
+            .path("/buoys")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        unsafe {
+            println!("{}", std::str::from_utf8_unchecked(res.body()));
+            assert!(std::str::from_utf8_unchecked(res.body())
+                .contains("[\"dev864475044203262\",\"cain\",\"sfy\",\"\"]"));
+        }
+    }
+
+    #[tokio::test]
+    async fn list_entries() {
+        let state = crate::test_state().await;
+        let event = std::fs::read("tests/events/sensor.db_03.json").unwrap();
+
+        let f = filters(state);
+
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(&event)
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        // Missing token
+        let res = warp::test::request()
+            .path("/buoys/dev864475044203262")
+            .method("GET")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 400);
+
+        let res = warp::test::request()
+            .path("/buoys/dev864475044203262")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(
+            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
+            "application/json"
+        );
+        assert_eq!(res.status(), 200);
+
+        unsafe {
+            println!("{}", std::str::from_utf8_unchecked(res.body()));
+            assert!(std::str::from_utf8_unchecked(res.body())
+                .contains("[\"1639059643089-9ef2e080-f0b4-4036-8ccc-ec4206553539_sensor.db.json\",\"sensor.db\"]"));
+        }
+    }
+
+    #[tokio::test]
+    async fn get_entry() {
+        let state = crate::test_state().await;
+        let event = std::fs::read("tests/events/sensor.db_04.json").unwrap();
+
+        let f = filters(state);
+
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(&event)
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        // Missing token
+        let res = warp::test::request()
+            .path("/buoys/dev864475044203262/1639059643089-9ef2e080-f0b4-4036-8ccc-ec4206553540_sensor.db.json")
+            .method("GET")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 400);
+
+        let res = warp::test::request()
+            .path("/buoys/dev864475044203262/1639059643089-9ef2e080-f0b4-4036-8ccc-ec4206553540_sensor.db.json")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
+            "application/json"
+        );
+        assert_eq!(res.body(), &event);
+    }
+
+    #[tokio::test]
+    async fn last_entry() {
+        let state = crate::test_state().await;
+
+        let f = filters(state);
+
+        let event = std::fs::read("tests/events/sensor.db_05.json").unwrap();
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(&event)
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path("/buoys/dev864475044203262/last")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 500);
+
+        let event = std::fs::read(
+            "tests/events/1647870799330-1876870b-4708-4366-8db5-68f872cc4e6d_axl.qo.json",
+        )
+        .unwrap();
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(&event)
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path("/buoys/dev867730051260788/last")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        assert_eq!(
+            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
+            "application/json"
+        );
+        assert_eq!(res.body(), &event);
+    }
+
+    #[tokio::test]
+    async fn range() {
+        let state = crate::test_state().await;
+
+        let f = filters(state);
+
+        let events = [
+            r#"{"received": 0, "event": "event-0", "device": "0", "name": "0", "file": "axl.qo" }"#,
+            r#"{"received": 1, "event": "event-1", "device": "0", "name": "0", "file": "axl.qo" }"#,
+            r#"{"received": 2, "event": "event-2", "device": "0", "name": "0", "file": "axl.qo" }"#,
+            r#"{"received": 3, "event": "event-3", "device": "0", "name": "0", "file": "axl.qo" }"#,
+        ];
+
+        for e0 in events {
+            println!("posting: {}", e0);
+            let res = warp::test::request()
+                .path("/buoy")
+                .method("POST")
+                .header("SFY_AUTH_TOKEN", "token1")
+                .body(e0)
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 200);
+        }
+
+        // Received is multiplied to 1000 when parsed.
+
+        let res = warp::test::request()
+            .path("/buoys/0/from/0/to/2000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let revents: Vec<B64Event> = json::from_slice(res.body()).unwrap();
+        println!("{:?}", revents);
+
+        assert_eq!(revents.len(), 3);
+
+        assert_eq!(
+            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
+            "application/json"
+        );
+
+        assert_eq!(revents[2].received, 2000);
+
+        let res = warp::test::request()
+            .path("/buoys/0/from/2000/to/3000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let revents: Vec<B64Event> = json::from_slice(res.body()).unwrap();
+        println!("{:?}", revents);
+
+        assert_eq!(revents.len(), 2);
+
+        assert_eq!(
+            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
+            "application/json"
+        );
+
+        assert_eq!(revents[1].received, 3000);
+
+        // List
+        let res = warp::test::request()
+            .path("/buoys/list/0/from/2000/to/3000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let revents: Vec<(String, String)> = json::from_slice(res.body()).unwrap();
+
+        assert_eq!(revents.len(), 2);
+
+        assert_eq!(
+            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
+            "application/json"
+        );
+
+        println!("{revents:?}");
+        assert_eq!(
+            &revents,
+            &[
+                ("2000-event-2_axl.qo.json".into(), "axl.qo".into()),
+                ("3000-event-3_axl.qo.json".into(), "axl.qo".into())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn axl_decoded() {
+        let state = crate::test_state().await;
+
+        let f = filters(state);
+
+        let event = std::fs::read("tests/events/sfy4-axlb.qo.json").unwrap();
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(&event)
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path("/buoys/dev860264050364604/axl/from/0/to/1779179083941")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let packets: json::Value = json::from_slice(res.body()).unwrap();
+        let packets = packets.as_array().unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0]["received"], 1779179083941i64);
+        assert_eq!(packets[0]["z"].as_array().unwrap().len(), 1024);
+        assert_eq!(packets[0]["time"][13], 1779178986910.);
+
+        let res = warp::test::request()
+            .path("/buoys/dev860264050364604/axl/from/0/to/1779179083941?format=csv")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
+            "text/csv"
+        );
+
+        let body = std::str::from_utf8(res.body()).unwrap();
+        let lines: Vec<_> = body.lines().collect();
+        assert_eq!(lines.len(), 1025);
+        assert_eq!(lines[0], "received,event,time,x,y,z");
+
+        let res = warp::test::request()
+            .path("/buoys/dev860264050364604/axl/from/0/to/1000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.body(), "[]");
+    }
+
+    #[tokio::test]
+    async fn egps_decoded() {
+        let state = crate::test_state().await;
+
+        let f = filters(state);
+
+        let event = std::fs::read("tests/events/sfy4-egpsb.qo.json").unwrap();
+        let res = warp::test::request()
+            .path("/buoy")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(&event)
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path("/buoys/dev860264050364604/egps/from/0/to/1779179083941")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let packets: json::Value = json::from_slice(res.body()).unwrap();
+        let packets = packets.as_array().unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0]["lat"].as_array().unwrap().len(), 256);
+        assert_eq!(packets[0]["fix"][3], 256);
+        assert_eq!(packets[0]["filled"], 7);
+        assert_eq!(packets[0]["ha_max"], 118.);
+
+        let res = warp::test::request()
+            .path("/buoys/dev860264050364604/egps/from/0/to/1779179083941?format=csv")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let body = std::str::from_utf8(res.body()).unwrap();
+        let lines: Vec<_> = body.lines().collect();
+        assert_eq!(lines.len(), 257);
+        assert_eq!(lines[0], "received,event,time,lon,lat,msl,vn,ve,vd");
+    }
+
+    #[tokio::test]
+    async fn spec_decoded() {
+        let state = crate::test_state().await;
+
+        let f = filters(state);
+
+        for (t, peak) in [(1779178945405, 20), (1779180166329, 24)] {
+            let event = testing::spec_event(t, peak);
+            let res = testing::post(&f, &event).await;
+
+            assert_eq!(res.status(), 200);
+        }
+
+        let res = warp::test::request()
+            .path("/buoys/dev860264050364604/spec/from/0/to/1779190000000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let packets: json::Value = json::from_slice(res.body()).unwrap();
+        let packets = packets.as_array().unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0]["f"].as_array().unwrap().len(), 77);
+        assert_eq!(packets[0]["nseg"], 2048);
+        assert!((packets[0]["tp"].as_f64().unwrap() - 2048. / 52. / 20.).abs() < 1e-3);
+        assert!((packets[1]["tp"].as_f64().unwrap() - 2048. / 52. / 24.).abs() < 1e-3);
+
+        let res = warp::test::request()
+            .path("/buoys/dev860264050364604/spec/from/0/to/1779190000000?freq=20&format=csv")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+
+        let body = std::str::from_utf8(res.body()).unwrap();
+        let lines: Vec<_> = body.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "received,event,timestamp,hm0,tp,tm01,tm02");
+
+        let tp: f64 = lines[1].split(',').nth(4).unwrap().parse().unwrap();
+        assert!((tp - 1024. / 20. / 20.).abs() < 1e-3);
+
+        // Paged, one event at a time.
+        let mut after = String::new();
+        let mut timestamps = Vec::new();
+        loop {
+            let res = warp::test::request()
+                .path(&format!(
+                    "/buoys/dev860264050364604/spec/from/0/to/1779190000000?limit=1{}",
+                    after
+                ))
+                .method("GET")
+                .header("SFY_AUTH_TOKEN", "r-token1")
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 200);
+
+            let packets: json::Value = json::from_slice(res.body()).unwrap();
+            let packets = packets.as_array().unwrap();
+            assert!(packets.len() <= 1);
+            timestamps.extend(packets.iter().map(|p| p["timestamp"].as_i64().unwrap()));
+
+            match res.headers().get(NEXT_AFTER) {
+                Some(next) => after = format!("&after={}", next.to_str().unwrap()),
+                None => break,
+            }
+        }
+        assert_eq!(timestamps, [1779178945405, 1779180166329]);
+
+        let res = warp::test::request()
+            .path("/buoys/dev860264050364604/spec/from/0/to/1779190000000?limit=0")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn omb_spec_and_thermistors() {
+        let state = crate::test_state().await;
+
+        let f = filters(state);
+
+        let imu = testing::event("02-omb-imu.json", "OMB-SPEC-1");
+
+        let thermistors = json::json!({
+            "account": "gauteh@met.no",
+            "datetime": 1742754200000u64,
+            "device": "OMB-SPEC-1",
+            "type": "thermistor",
+            "payload": hex::encode(testing::thermistor_message(
+                1742754100,
+                &[-150, -120, 210]
+            )),
+        });
+
+        for event in [&imu, &thermistors] {
+            let res = warp::test::request()
+                .path("/buoy/omb")
+                .method("POST")
+                .header("SFY_AUTH_TOKEN", "token1")
+                .body(json::to_vec(event).unwrap())
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 200);
+        }
+
+        let res = warp::test::request()
+            .path("/buoys/OMB-SPEC-1/spec/from/0/to/1779190000000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        let packets: json::Value = json::from_slice(res.body()).unwrap();
+        let packets = packets.as_array().unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0]["timestamp"], 1742754106000i64);
+        assert_eq!(packets[0]["f"].as_array().unwrap().len(), 55);
+        assert!(packets[0]["hm0"].as_f64().unwrap() > 2.);
+
+        let res = warp::test::request()
+            .path("/buoys/OMB-SPEC-1/thermistors/from/0/to/1779190000000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        let packets: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(packets.as_array().unwrap().len(), 1);
+        assert_eq!(
+            packets[0]["profiles"][0]["temperatures"],
+            json::json!([-1.5, -1.2, 2.1])
+        );
+
+        let res = warp::test::request()
+            .path("/buoys/OMB-SPEC-1/thermistors/from/0/to/1779190000000?format=csv")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert_eq!(body.lines().count(), 4);
+    }
+
+    #[tokio::test]
+    async fn status() {
+        let state = crate::test_state().await;
+
+        let f = filters(state);
+
+        let imu = testing::event("02-omb-imu.json", "OMB-STATUS-1");
+
+        let gps = |datetime: u64, lat: f64| {
+            json::json!({
+                "account": "gauteh@met.no",
+                "datetime": datetime,
+                "device": "OMB-STATUS-1",
+                "type": "gps",
+                "body": { "messages": [
+                    { "is_valid": true, "datetime_fix": (datetime / 1000) as f64, "latitude": lat, "longitude": 17.71 }
+                ]},
+            })
+        };
+
+        // The older position is received last.
+        for event in [&gps(1742754100000, 33.87), &imu, &gps(1742754000000, 33.86)] {
+            let res = warp::test::request()
+                .path("/buoy/omb")
+                .method("POST")
+                .header("SFY_AUTH_TOKEN", "token1")
+                .body(json::to_vec(event).unwrap())
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 200);
+        }
+
+        let res = warp::test::request()
+            .path("/buoys/status")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        let status: Vec<database::BuoyStatus> = json::from_slice(res.body()).unwrap();
+        let status = status.iter().find(|s| s.dev == "OMB-STATUS-1").unwrap();
+
+        assert_eq!(status.buoy_type, "omb");
+        assert_eq!(status.lat, Some(33.87));
+        assert_eq!(status.fix_time, Some(1742754100000));
+        assert_eq!(status.last_received, Some(1742754139000));
+        assert_eq!(status.last_message_type.as_deref(), Some("imu"));
+        assert!(status.hs.unwrap() > 2.);
+        assert_eq!(status.message_counts["gps"], 2);
+        assert_eq!(status.message_counts["imu"], 1);
+
+        let res = warp::test::request()
+            .path("/buoys/status")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "s-token1")
+            .reply(&f)
+            .await;
+
+        let status: Vec<database::BuoyStatus> = json::from_slice(res.body()).unwrap();
+        assert!(status.iter().all(|s| s.dev != "OMB-STATUS-1"));
+    }
 
     #[tokio::test]
-    async fn check_token_ok() {
+    async fn area_and_positions() {
         let state = crate::test_state().await;
 
-        let f = check_token(state);
+        let f = filters(state);
 
-        assert!(warp::test::request()
+        for (dev, datetime, lat, lon) in [
+            ("OMB-AREA-1", 1742754000000u64, -50.5, 100.5),
+            ("OMB-AREA-1", 1742757600000, -50.5, 101.5),
+            ("OMB-AREA-2", 1742754000000, -55.5, 100.5),
+        ] {
+            let event = json::json!({
+                "account": "gauteh@met.no",
+                "datetime": datetime,
+                "device": dev,
+                "type": "gps",
+                "body": { "messages": [
+                    { "is_valid": true, "datetime_fix": (datetime / 1000) as f64, "latitude": lat, "longitude": lon }
+                ]},
+            });
+
+            let res = warp::test::request()
+                .path("/buoy/omb")
+                .method("POST")
+                .header("SFY_AUTH_TOKEN", "token1")
+                .body(json::to_vec(&event).unwrap())
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 200);
+        }
+
+        let res = warp::test::request()
+            .path("/buoys/area/from/0/to/1779190000000?bbox=100,-51,101,-50")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        let fc: json::Value = json::from_slice(res.body()).unwrap();
+        let features = fc["features"].as_array().unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(features[0]["properties"]["dev"], "OMB-AREA-1");
+        assert_eq!(features[0]["geometry"]["coordinates"][0], 100.5);
+
+        let res = warp::test::request()
+            .path("/buoys/area/from/0/to/1779190000000?polygon=100,-60,102,-60,102,-50,100,-50&buoys=OMB-AREA-1,OMB-AREA-2")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        let fc: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(fc["features"].as_array().unwrap().len(), 3);
+
+        let res = warp::test::request()
+            .path("/buoys/area/from/1742757000000/to/1779190000000?bbox=100,-60,102,-50")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        let fc: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(fc["features"].as_array().unwrap().len(), 1);
+
+        let res = warp::test::request()
+            .path("/buoys/area/from/0/to/1779190000000?bbox=100,-60,102")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 400);
+
+        let res = warp::test::request()
+            .path("/buoys/positions?buoys=OMB-AREA-1,OMB-AREA-2")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        let fc: json::Value = json::from_slice(res.body()).unwrap();
+        let features = fc["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+        assert_eq!(features[0]["properties"]["dev"], "OMB-AREA-1");
+        assert_eq!(features[0]["geometry"]["coordinates"][0], 101.5);
+        assert_eq!(features[1]["properties"]["t"], 1742754000.);
+    }
+
+    #[tokio::test]
+    async fn waves_from_axl() {
+        let state = crate::test_state().await;
+
+        let f = filters(state);
+
+        let event = testing::event("sfy4-axlb.qo.json", "dev:waves-axl");
+
+        let waves = || {
+            warp::test::request()
+                .path("/buoys/devwaves-axl/waves/from/0/to/1779190000000")
+                .method("GET")
+                .header("SFY_AUTH_TOKEN", "r-token1")
+                .reply(&f)
+        };
+
+        assert_eq!(testing::post(&f, &event).await.status(), 200);
+
+        // One package is too short for a segment.
+        for _ in 0..2 {
+            let res = waves().await;
+            assert_eq!(res.status(), 200);
+            let stats: json::Value = json::from_slice(res.body()).unwrap();
+            assert_eq!(stats.as_array().unwrap().len(), 1);
+            assert_eq!(stats[0]["start"], 1779178800000i64);
+            assert_eq!(stats[0]["segments"], 0);
+            assert!(stats[0].get("hm0").is_none());
+        }
+
+        // The next package fills a segment, and the stored window is computed again.
+        let mut next = event.clone();
+        next["event"] = "f0a9c9f7-bebd-8f94-84c3-08cdbe01a7d8".into();
+        next["body"]["timestamp"] = (1779178986910i64 + 19692).into();
+        next["received"] = (1779179083.941283 + 20.).into();
+        assert_eq!(testing::post(&f, &next).await.status(), 200);
+
+        let res = waves().await;
+        let stats: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(stats.as_array().unwrap().len(), 1);
+        assert_eq!(stats[0]["segments"], 1);
+        assert_eq!(stats[0]["gaps"], 0);
+        assert!(stats[0]["hm0"].is_f64());
+
+        let res = warp::test::request()
+            .path("/buoys/devwaves-axl/waves/from/0/to/1779190000000?format=csv&window=600")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert_eq!(body.lines().count(), 2);
+        assert!(body
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("1779178800000,600000,1,0,"));
+
+        for query in ["window=0", "window=-600", "nseg=16", "freq=0"] {
+            let res = warp::test::request()
+                .path(&format!(
+                    "/buoys/devwaves-axl/waves/from/0/to/1779190000000?{}",
+                    query
+                ))
+                .method("GET")
+                .header("SFY_AUTH_TOKEN", "r-token1")
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 400, "{}", query);
+        }
+
+        for query in ["nseg=16", "freq=0", "freq=-1"] {
+            let res = warp::test::request()
+                .path(&format!(
+                    "/buoys/devwaves-axl/spec/from/0/to/1779190000000?{}",
+                    query
+                ))
+                .method("GET")
+                .header("SFY_AUTH_TOKEN", "r-token1")
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 400, "{}", query);
+        }
+
+        // The segment length follows the frequency of the packages.
+        let mut slow = event.clone();
+        slow["device"] = "dev:waves-axl-20hz".into();
+        slow["body"]["freq"] = 20.into();
+        assert_eq!(testing::post(&f, &slow).await.status(), 200);
+
+        let res = warp::test::request()
+            .path("/buoys/devwaves-axl-20hz/waves/from/0/to/1779190000000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        let stats: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(stats.as_array().unwrap().len(), 1);
+        assert_eq!(stats[0]["freq"], 20.);
+        assert_eq!(stats[0]["nseg"], 1024);
+        assert_eq!(stats[0]["segments"], 1);
+    }
+
+    #[tokio::test]
+    async fn stitch_report() {
+        let state = crate::test_state().await;
+
+        let f = filters(state);
+
+        let event = testing::event("sfy4-axlb.qo.json", "dev:stitch-report");
+
+        // The second package follows the first, the third is a minute later.
+        for (i, delay) in [0, 19692, 79692 + 19692].into_iter().enumerate() {
+            let mut event = event.clone();
+            event["event"] = format!("f0a9c9f7-bebd-8f94-84c3-08cdbe01a7d{}", i).into();
+            event["body"]["timestamp"] = (1779178986910i64 + delay).into();
+            event["body"]["storage_id"] = (10 + 2 * i).into();
+            event["received"] = (1779179083.941283 + delay as f64 / 1000.).into();
+
+            let res = testing::post(&f, &event).await;
+
+            assert_eq!(res.status(), 200);
+        }
+
+        let res = warp::test::request()
+            .path("/buoys/devstitch-report/stitch/from/0/to/1779190000000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        let report: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(report["segments"].as_array().unwrap().len(), 2);
+        assert_eq!(report["segments"][0]["samples"], 2048);
+        assert_eq!(report["breaks"][0]["kind"], "gap");
+        assert_eq!(report["duplicates"], 0);
+        assert_eq!(
+            report["missing_storage_ids"],
+            json::json!([[11, 11], [13, 13]])
+        );
+
+        // The range is of sample time: the first package starts before the range.
+        let res = warp::test::request()
+            .path("/buoys/devstitch-report/stitch/from/1779178990000/to/1779190000000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        let report: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(report["segments"].as_array().unwrap().len(), 2);
+        assert_eq!(report["segments"][0]["samples"], 1024);
+        assert_eq!(report["missing_storage_ids"], json::json!([[13, 13]]));
+    }
+
+    #[tokio::test]
+    async fn append_sbd() {
+        let state = crate::test_state().await;
+
+        let f = filters(state);
+
+        let imu: json::Value =
+            json::from_slice(&std::fs::read("tests/events/02-omb-imu.json").unwrap()).unwrap();
+
+        let form = |momsn: u32, data: &str| {
+            format!(
+                "imei=300434065196261&momsn={}&transmit_time=25-03-23%2018%3A22%3A{:02}&iridium_latitude=33.8623&iridium_longitude=17.7101&iridium_cep=3.0&data={}",
+                momsn, momsn, data
+            )
+        };
+
+        let gps = "47" // G
+            .to_string()
+            + &hex::encode(1742754000u32.to_le_bytes())
+            + &hex::encode(338623160i32.to_le_bytes())
+            + &hex::encode(177100660i32.to_le_bytes())
+            + "45"; // E
+
+        for (momsn, data) in [(1, imu["payload"].as_str().unwrap()), (2, &gps)] {
+            let res = warp::test::request()
+                .path("/buoy/omb/sbd?token=token1")
+                .method("POST")
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(form(momsn, data))
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 200);
+        }
+
+        let res = warp::test::request()
+            .path("/buoy/omb/sbd?token=wrong")
             .method("POST")
-            .header("SFY_AUTH_TOKEN", "wrong-token")
-            .filter(&f)
-            .await
-            .is_err());
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(form(3, &gps))
+            .reply(&f)
+            .await;
 
-        assert!(warp::test::request()
+        assert!(res.status().is_client_error());
+
+        let res = warp::test::request()
+            .path("/buoys/300434065196261")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        let entries: Vec<(String, String)> = json::from_slice(res.body()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].1, "imu");
+        assert_eq!(entries[1].1, "gps");
+
+        let res = warp::test::request()
+            .path(&format!("/buoys/300434065196261/{}", entries[0].0))
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        let event: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(event["payload"], imu["payload"]);
+        assert_eq!(event["momsn"], 1);
+        assert_eq!(
+            event["body"]["messages"][0]["spectrum_number"],
+            imu["body"]["messages"][0]["spectrum_number"]
+        );
+
+        let res = warp::test::request()
+            .path("/buoys/300434065196261/track/from/0/to/1779190000000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        let points: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(points.as_array().unwrap().len(), 1);
+        assert_eq!(points[0]["lat"], 33.862316);
+    }
+
+    #[tokio::test]
+    async fn sbd_modems() {
+        let mut state = crate::test_state().await;
+        Arc::get_mut(&mut state).unwrap().config.modems = vec![family::Modem {
+            imei: "300434065196264".into(),
+            device: "OMB-SBD-1".into(),
+            account: None,
+        }];
+        let f = filters(state.clone());
+
+        let mut omb: json::Value =
+            json::from_slice(&std::fs::read("tests/events/01-omb.json").unwrap()).unwrap();
+        omb["device"] = "OMB-SBD-1".into();
+        let res = warp::test::request()
+            .path("/buoy/omb")
             .method("POST")
             .header("SFY_AUTH_TOKEN", "token1")
-            .filter(&f)
+            .body(json::to_vec(&omb).unwrap())
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let gps = "47".to_string()
+            + &hex::encode(1742754000u32.to_le_bytes())
+            + &hex::encode(338623160i32.to_le_bytes())
+            + &hex::encode(177100660i32.to_le_bytes())
+            + "45";
+        let form = |imei: &str| {
+            format!(
+                "imei={}&momsn=1&transmit_time=25-03-23%2018%3A22%3A01&iridium_latitude=33.8623&iridium_longitude=17.7101&iridium_cep=3.0&data={}",
+                imei, gps
+            )
+        };
+
+        for (query, imei) in [
+            ("token=token1", "300434065196264"),
+            ("token=token1&account=other%40met.no", "300434065196265"),
+        ] {
+            let res = warp::test::request()
+                .path(&format!("/buoy/omb/sbd?{}", query))
+                .method("POST")
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(form(imei))
+                .reply(&f)
+                .await;
+            assert_eq!(res.status(), 200);
+        }
+
+        // The modem is mapped to the buoy, which keeps its account.
+        let b = state.db.buoy("OMB-SBD-1").await.unwrap();
+        assert_eq!(b.product(), Some("gauteh@met.no"));
+        assert!(state
+            .db
+            .buoy("300434065196264")
             .await
-            .is_ok());
+            .unwrap()
+            .family()
+            .is_none());
+
+        let res = warp::test::request()
+            .path("/buoys/OMB-SBD-1")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+        let entries: Vec<(String, String)> = json::from_slice(res.body()).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let b = state.db.buoy("300434065196265").await.unwrap();
+        assert_eq!(b.product(), Some("other@met.no"));
     }
 
-    #[tokio::test]
-    async fn append_to_database() {
-        let state = crate::test_state().await;
-        let event = std::fs::read("tests/events/sensor.db_01.json").unwrap();
+    #[tokio::test]
+    async fn append_spotter() {
+        let state = crate::test_state().await;
+
+        let f = filters(state.clone());
+
+        let payload = std::fs::read("tests/events/spotter-01.json").unwrap();
+        for _ in 0..2 {
+            let res = warp::test::request()
+                .path("/buoy/spotter")
+                .method("POST")
+                .header("SFY_AUTH_TOKEN", "token1")
+                .body(&payload)
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 200);
+        }
+
+        let res = warp::test::request()
+            .path("/buoys")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        let buoys: json::Value = json::from_slice(res.body()).unwrap();
+        assert!(buoys
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|b| b[0] == "SPOT-30152C" && b[2] == "spotter"));
+
+        let res = warp::test::request()
+            .path("/buoys/SPOT-30152C")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        let entries: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(entries.as_array().unwrap().len(), 3);
+
+        let res = warp::test::request()
+            .path("/buoys/SPOT-30152C/track/from/0/to/1779190000000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+
+        assert_eq!(res.status(), 200);
+        let points: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(points.as_array().unwrap().len(), 3);
+
+        let res = warp::test::request()
+            .path("/buoys/SPOT-30152C/last")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
 
-        let f = filters(state.clone());
+        assert_eq!(res.status(), 200);
+        let last: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(last["spotterId"], "SPOT-30152C");
 
         let res = warp::test::request()
-            .path("/buoy")
+            .path("/buoy/spotter")
             .method("POST")
-            .header("SFY_AUTH_TOKEN", "wrong-token")
-            .body(&event)
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(r#"{"data": {"spotterId": "SPOT-30152C"}}"#)
             .reply(&f)
             .await;
 
-        assert!(res.status() != 200);
+        assert_eq!(res.status(), 400);
 
+        // The track has no timestamps: none of the events are stored.
+        let payload = std::fs::read("tests/events/spotter-02-no-timestamp.json").unwrap();
         let res = warp::test::request()
-            .path("/buoy")
+            .path("/buoy/spotter")
             .method("POST")
             .header("SFY_AUTH_TOKEN", "token1")
-            .body(&event)
+            .body(&payload)
             .reply(&f)
             .await;
 
-        assert_eq!(res.status(), 200);
-
-        let e = state
+        assert_eq!(res.status(), 400);
+        assert!(state
             .db
-            .buoy("dev864475044203262")
+            .buoy("SPOT-30153A")
             .await
             .unwrap()
-            .get("1639059643089-9ef2e080-f0b4-4036-8ccc-ec4206553537_sensor.db.json")
-            .await
-            .unwrap();
-
-        assert_eq!(&e, &event);
+            .family()
+            .is_none());
     }
 
     #[tokio::test]
-    async fn bad_event() {
+    async fn track_formats() {
         let state = crate::test_state().await;
-        let event = br#"{ "noevent": "asdf", "something": "hey", bad json even }"#;
 
         let f = filters(state);
 
+        for fixture in ["sfy4-axlb.qo.json", "sfy4-egpsb.qo.json"] {
+            let event = testing::event(fixture, "dev:track-formats");
+
+            let res = testing::post(&f, &event).await;
+
+            assert_eq!(res.status(), 200);
+        }
+
         let res = warp::test::request()
-            .path("/buoy")
-            .method("POST")
-            .header("SFY_AUTH_TOKEN", "token1")
-            .body(&event)
+            .path("/buoys/devtrack-formats/track/from/0/to/1779190000000")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
-        assert_eq!(res.status(), 400);
-    }
-
-    #[tokio::test]
-    async fn list_buoys() {
-        let state = crate::test_state().await;
-        let event = std::fs::read("tests/events/sensor.db_02.json").unwrap();
-
-        let f = filters(state);
+        assert_eq!(res.status(), 200);
+        let points: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(points.as_array().unwrap().len(), 2);
 
         let res = warp::test::request()
-            .path("/buoy")
-            .method("POST")
-            .header("SFY_AUTH_TOKEN", "token1")
-            .body(&event)
+            .path("/buoys/devtrack-formats/track/from/0/to/1779190000000?dedup=true&max_speed=10&simplify=2&drift=true")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
         assert_eq!(res.status(), 200);
+        let points: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(points.as_array().unwrap().len(), 2);
+        assert!(points[0]["speed"].is_null());
+        assert!(points[1]["heading"].is_f64());
 
-        // Missing token
         let res = warp::test::request()
-            .path("/buoys")
+            .path("/buoys/devtrack-formats/track/from/0/to/1779190000000?max_speed=-1")
             .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
         assert_eq!(res.status(), 400);
 
         let res = warp::test::request()
-            .path("/buoys")
+            .path("/buoys/devtrack-formats/track/from/0/to/1779190000000?format=gpx")
             .method("GET")
             .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
         assert_eq!(res.status(), 200);
-        unsafe {
-            println!("{}", std::str::from_utf8_unchecked(res.body()));
-            assert!(std::str::from_utf8_unchecked(res.body())
-                .contains("[\"dev864475044203262\",\"cain\",\"sfy\",\"\"]"));
-        }
-    }
-
-    #[tokio::test]
-    async fn list_entries() {
-        let state = crate::test_state().await;
-        let event = std::fs::read("tests/events/sensor.db_03.json").unwrap();
-
-        let f = filters(state);
+        assert_eq!(
+            res.headers().get("Content-Type").unwrap(),
+            "application/gpx+xml"
+        );
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert_eq!(body.matches("<trkpt ").count(), 2);
+        assert!(body.contains("<name>SFY4-01</name>"));
 
         let res = warp::test::request()
-            .path("/buoy")
-            .method("POST")
-            .header("SFY_AUTH_TOKEN", "token1")
-            .body(&event)
+            .path("/buoys/devtrack-formats/track/from/0/to/1779190000000?format=kml")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
         assert_eq!(res.status(), 200);
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert_eq!(body.matches("<gx:coord>").count(), 2);
 
-        // Missing token
         let res = warp::test::request()
-            .path("/buoys/dev864475044203262")
+            .path("/buoys/tracks/from/0/to/1779190000000?buoys=devtrack-formats")
             .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
-        assert_eq!(res.status(), 400);
+        assert_eq!(res.status(), 200);
+        assert_eq!(
+            res.headers().get("Content-Type").unwrap(),
+            "application/geo+json"
+        );
+        let fc: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(fc["type"], "FeatureCollection");
+        assert_eq!(fc["features"].as_array().unwrap().len(), 2);
+        assert_eq!(fc["features"][0]["properties"]["name"], "SFY4-01");
+        assert_eq!(fc["features"][0]["properties"]["dev"], "devtrack-formats");
 
         let res = warp::test::request()
-            .path("/buoys/dev864475044203262")
+            .path("/buoys/tracks/from/0/to/1779190000000?buoys=devtrack-formats,devno-such-buoy")
             .method("GET")
             .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
-        assert_eq!(
-            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
-            "application/json"
-        );
         assert_eq!(res.status(), 200);
-
-        unsafe {
-            println!("{}", std::str::from_utf8_unchecked(res.body()));
-            assert!(std::str::from_utf8_unchecked(res.body())
-                .contains("[\"1639059643089-9ef2e080-f0b4-4036-8ccc-ec4206553539_sensor.db.json\",\"sensor.db\"]"));
-        }
+        let unknown: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(unknown, fc);
     }
 
     #[tokio::test]
-    async fn get_entry() {
+    async fn scoped_tokens() {
         let state = crate::test_state().await;
-        let event = std::fs::read("tests/events/sensor.db_04.json").unwrap();
 
         let f = filters(state);
 
-        let res = warp::test::request()
-            .path("/buoy")
-            .method("POST")
-            .header("SFY_AUTH_TOKEN", "token1")
-            .body(&event)
-            .reply(&f)
-            .await;
+        for (dev, product) in [
+            ("dev:scoped-a", "product:other"),
+            ("dev:scoped-b", "product:test:scoped"),
+            ("dev:scoped-c", "product:other"),
+        ] {
+            let mut event = testing::event("sfy4-egpsb.qo.json", dev);
+            event["product"] = product.into();
 
-        assert_eq!(res.status(), 200);
+            let res = testing::post(&f, &event).await;
+
+            assert_eq!(res.status(), 200);
+        }
 
-        // Missing token
         let res = warp::test::request()
-            .path("/buoys/dev864475044203262/1639059643089-9ef2e080-f0b4-4036-8ccc-ec4206553540_sensor.db.json")
+            .path("/buoys")
             .method("GET")
+            .header("SFY_AUTH_TOKEN", "s-token1")
             .reply(&f)
             .await;
 
-        assert_eq!(res.status(), 400);
+        assert_eq!(res.status(), 200);
+        let buoys: Vec<(String, String, String, String)> = json::from_slice(res.body()).unwrap();
+        let devs: Vec<_> = buoys.iter().map(|b| b.0.as_str()).collect();
+        assert!(devs.contains(&"devscoped-a"));
+        assert!(devs.contains(&"devscoped-b"));
+        assert!(!devs.contains(&"devscoped-c"));
+
+        for path in [
+            "/buoys/devscoped-c",
+            "/buoys/devscoped-c/last",
+            "/buoys/devscoped-c/track/from/0/to/1779190000000",
+        ] {
+            let res = warp::test::request()
+                .path(path)
+                .method("GET")
+                .header("SFY_AUTH_TOKEN", "s-token1")
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 404, "{}", path);
+
+            let res = warp::test::request()
+                .path(path)
+                .method("GET")
+                .header("SFY_AUTH_TOKEN", "r-token1")
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 200, "{}", path);
+        }
+
+        for path in ["/buoys/devscoped-a", "/buoys/devscoped-b/last"] {
+            let res = warp::test::request()
+                .path(path)
+                .method("GET")
+                .header("SFY_AUTH_TOKEN", "s-token1")
+                .reply(&f)
+                .await;
+
+            assert_eq!(res.status(), 200, "{}", path);
+        }
 
         let res = warp::test::request()
-            .path("/buoys/dev864475044203262/1639059643089-9ef2e080-f0b4-4036-8ccc-ec4206553540_sensor.db.json")
+            .path("/buoys/tracks/from/0/to/1779190000000?buoys=devscoped-b,devscoped-c")
             .method("GET")
-            .header("SFY_AUTH_TOKEN", "r-token1")
+            .header("SFY_AUTH_TOKEN", "s-token1")
             .reply(&f)
             .await;
 
         assert_eq!(res.status(), 200);
-        assert_eq!(
-            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
-            "application/json"
-        );
-        assert_eq!(res.body(), &event);
+        let fc: json::Value = json::from_slice(res.body()).unwrap();
+        assert!(fc["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|f| f["properties"]["dev"] == "devscoped-b"));
     }
 
     #[tokio::test]
-    async fn last_entry() {
+    async fn deployment_range_and_track() {
         let state = crate::test_state().await;
 
-        let f = filters(state);
+        let f = filters(state.clone());
 
-        let event = std::fs::read("tests/events/sensor.db_05.json").unwrap();
-        let res = warp::test::request()
-            .path("/buoy")
-            .method("POST")
-            .header("SFY_AUTH_TOKEN", "token1")
-            .body(&event)
-            .reply(&f)
-            .await;
+        for fixture in ["sfy4-axlb.qo.json", "sfy4-egpsb.qo.json"] {
+            let event = testing::event(fixture, "dev:deployment-range");
 
-        assert_eq!(res.status(), 200);
+            let res = testing::post(&f, &event).await;
+
+            assert_eq!(res.status(), 200);
+        }
+
+        let mut deployment = crate::deployments::Deployment {
+            id: 0,
+            dev: "devdeployment-range".into(),
+            start: 1779179000000,
+            end: Some(1779179080000),
+            site: None,
+            lat: None,
+            lon: None,
+            hull: None,
+            mooring: None,
+            notes: None,
+            features: vec![],
+        };
+        let id = state.db.add_deployment(&deployment).await.unwrap();
+
+        deployment.dev = "devdeployment-other".into();
+        let other = state.db.add_deployment(&deployment).await.unwrap();
 
         let res = warp::test::request()
-            .path("/buoys/dev864475044203262/last")
+            .path(&format!("/buoys/devdeployment-range/deployment/{}", id))
             .method("GET")
             .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
-        assert_eq!(res.status(), 500);
+        assert_eq!(res.status(), 200);
+        let events: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 1);
 
-        let event = std::fs::read(
-            "tests/events/1647870799330-1876870b-4708-4366-8db5-68f872cc4e6d_axl.qo.json",
-        )
-        .unwrap();
         let res = warp::test::request()
-            .path("/buoy")
-            .method("POST")
-            .header("SFY_AUTH_TOKEN", "token1")
-            .body(&event)
+            .path(&format!(
+                "/buoys/devdeployment-range/track/deployment/{}?format=csv",
+                id
+            ))
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
         assert_eq!(res.status(), 200);
+        assert_eq!(std::str::from_utf8(res.body()).unwrap().lines().count(), 2);
 
         let res = warp::test::request()
-            .path("/buoys/dev867730051260788/last")
+            .path(&format!("/buoys/devdeployment-range/deployment/{}", other))
             .method("GET")
             .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
-        assert_eq!(res.status(), 200);
-
-        assert_eq!(
-            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
-            "application/json"
-        );
-        assert_eq!(res.body(), &event);
+        assert_eq!(res.status(), 404);
     }
 
     #[tokio::test]
-    async fn range() {
+    async fn range_pages() {
         let state = crate::test_state().await;
 
         let f = filters(state);
 
-        let events = [
-            r#"{"received": 0, "event": "event-0", "device": "0", "name": "0", "file": "axl.qo" }"#,
-            r#"{"received": 1, "event": "event-1", "device": "0", "name": "0", "file": "axl.qo" }"#,
-            r#"{"received": 2, "event": "event-2", "device": "0", "name": "0", "file": "axl.qo" }"#,
-            r#"{"received": 3, "event": "event-3", "device": "0", "name": "0", "file": "axl.qo" }"#,
-        ];
+        for fixture in ["sfy4-axlb.qo.json", "sfy4-egpsb.qo.json"] {
+            let event = testing::event(fixture, "dev:range-pages");
 
-        for e0 in events {
-            println!("posting: {}", e0);
-            let res = warp::test::request()
-                .path("/buoy")
-                .method("POST")
-                .header("SFY_AUTH_TOKEN", "token1")
-                .body(&e0)
-                .reply(&f)
-                .await;
+            let res = testing::post(&f, &event).await;
 
             assert_eq!(res.status(), 200);
         }
 
-        // Received is multiplied to 1000 in parse_data.
-
-        let res = warp::test::request()
-            .path("/buoys/0/from/0/to/2000")
-            .method("GET")
-            .header("SFY_AUTH_TOKEN", "r-token1")
-            .reply(&f)
-            .await;
-
-        assert_eq!(res.status(), 200);
-
-        let revents: Vec<B64Event> = json::from_slice(res.body()).unwrap();
-        println!("{:?}", revents);
+        let mut path = String::from("/buoys/devrange-pages/from/0/to/1779190000000?limit=1");
+        let mut events = Vec::new();
 
-        assert_eq!(revents.len(), 3);
+        loop {
+            let res = warp::test::request()
+                .path(&path)
+                .method("GET")
+                .header("SFY_AUTH_TOKEN", "r-token1")
+                .reply(&f)
+                .await;
 
-        assert_eq!(
-            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
-            "application/json"
-        );
+            assert_eq!(res.status(), 200);
+            let page: Vec<B64Event> = json::from_slice(res.body()).unwrap();
+            assert!(page.len() <= 1);
+            events.extend(page);
+
+            match res.headers().get(NEXT_AFTER) {
+                Some(next) => {
+                    path = format!(
+                        "/buoys/devrange-pages/from/0/to/1779190000000?limit=1&after={}",
+                        next.to_str().unwrap()
+                    )
+                }
+                None => break,
+            }
+        }
 
-        assert_eq!(revents[2].received, 2000);
+        assert_eq!(events.len(), 2);
+        assert!(events[0].received < events[1].received);
 
         let res = warp::test::request()
-            .path("/buoys/0/from/2000/to/3000")
+            .path("/buoys/list/devrange-pages/from/0/to/1779190000000?limit=1")
             .method("GET")
             .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
         assert_eq!(res.status(), 200);
+        let list: Vec<(String, String)> = json::from_slice(res.body()).unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(res.headers().get(NEXT_AFTER).unwrap(), list[0].0.as_str());
 
-        let revents: Vec<B64Event> = json::from_slice(res.body()).unwrap();
-        println!("{:?}", revents);
-
-        assert_eq!(revents.len(), 2);
-
-        assert_eq!(
-            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
-            "application/json"
-        );
-
-        assert_eq!(revents[1].received, 3000);
-
-        // List
         let res = warp::test::request()
-            .path("/buoys/list/0/from/2000/to/3000")
+            .path("/buoys/devrange-pages/from/0/to/1779190000000?format=ndjson")
             .method("GET")
             .header("SFY_AUTH_TOKEN", "r-token1")
             .reply(&f)
             .await;
 
         assert_eq!(res.status(), 200);
+        let lines: Vec<B64Event> = std::str::from_utf8(res.body())
+            .unwrap()
+            .lines()
+            .map(|l| json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines, events);
 
-        let revents: Vec<(String, String)> = json::from_slice(res.body()).unwrap();
-
-        assert_eq!(revents.len(), 2);
-
-        assert_eq!(
-            res.headers().get("Content-Type").unwrap().to_str().unwrap(),
-            "application/json"
-        );
+        for query in ["limit=0", "after=nonsense"] {
+            let res = warp::test::request()
+                .path(&format!(
+                    "/buoys/devrange-pages/from/0/to/1779190000000?{}",
+                    query
+                ))
+                .method("GET")
+                .header("SFY_AUTH_TOKEN", "r-token1")
+                .reply(&f)
+                .await;
 
-        println!("{revents:?}");
-        assert_eq!(
-            &revents,
-            &[
-                ("2000-event-2_axl.qo.json".into(), "axl.qo".into()),
-                ("3000-event-3_axl.qo.json".into(), "axl.qo".into())
-            ]
-        );
+            assert_eq!(res.status(), 400);
+        }
     }
 }
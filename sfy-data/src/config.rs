@@ -4,15 +4,52 @@ use std::net::SocketAddr;
 use std::path::Path;
 use std::path::PathBuf;
 
+use crate::alerts::Alerts;
+use crate::auth::{RouteSecret, ScopedToken};
+use crate::family::Modem;
+use crate::mqtt::Mqtt;
+use crate::webhooks::Webhook;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
     pub address: SocketAddr,
     pub database: Option<String>,
     pub tokens: Vec<String>,
     pub read_tokens: Vec<String>,
+
+    /// Read tokens that only give access to some buoys or products.
+    #[serde(default)]
+    pub scoped_tokens: Vec<ScopedToken>,
+
+    /// Secrets of Notehub routes. If set, events must be signed by one of the routes.
+    #[serde(default)]
+    pub route_secrets: Vec<RouteSecret>,
+
+    /// Signatures older or newer than this are rejected [s].
+    #[serde(default = "default_signature_window")]
+    pub signature_window: i64,
+
+    /// Targets that ingested events are posted to.
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+
+    /// Iridium modems of OpenMetBuoys posting raw SBD messages.
+    #[serde(default)]
+    pub modems: Vec<Modem>,
+
+    /// MQTT broker that ingested events are published to (requires the `mqtt` feature).
+    pub mqtt: Option<Mqtt>,
+
+    /// Rules for alerts on buoys, and where to send them.
+    pub alerts: Option<Alerts>,
+
     pub files: Option<PathBuf>,
 }
 
+fn default_signature_window() -> i64 {
+    300
+}
+
 impl Config {
     pub fn default() -> Config {
         Config {
@@ -20,6 +57,13 @@ impl Config {
             database: None,
             tokens: Vec::new(),
             read_tokens: Vec::new(),
+            scoped_tokens: Vec::new(),
+            route_secrets: Vec::new(),
+            signature_window: default_signature_window(),
+            webhooks: Vec::new(),
+            modems: Vec::new(),
+            mqtt: None,
+            alerts: None,
             files: None,
         }
     }
@@ -31,6 +75,17 @@ impl Config {
             database: None,
             tokens: vec!["token1".into()],
             read_tokens: vec!["r-token1".into()],
+            scoped_tokens: vec![ScopedToken {
+                token: "s-token1".into(),
+                buoys: vec!["devscoped-a".into()],
+                products: vec!["test:scoped".into()],
+            }],
+            route_secrets: Vec::new(),
+            signature_window: default_signature_window(),
+            webhooks: Vec::new(),
+            modems: Vec::new(),
+            mqtt: None,
+            alerts: None,
             files: None,
         }
     }
@@ -1,68 +1,52 @@
 use eyre::Result;
 use serde::{Deserialize, Serialize};
 use serde_json as json;
+use std::collections::BTreeMap;
 use std::path::Path;
 
+use crate::alerts::Alert;
+use crate::auth::Token;
+use crate::decode::axl::AxlPacket;
+use crate::deployments::Deployment;
+use crate::family::{self, Family, Table};
+use crate::waves::{series::WaveStats, Stats};
+use crate::webhooks::Delivery;
+
 #[cfg(feature = "sqlite")]
 use sqlx::sqlite::{
     SqliteConnectOptions, SqliteJournalMode, SqlitePool as Pool, SqlitePoolOptions,
     SqliteSynchronous,
 };
 
+#[cfg(feature = "sqlite")]
+use sqlx::{Sqlite as Db, SqliteConnection as Connection};
+
 #[cfg(feature = "postgres")]
 use sqlx::PgPool as Pool;
 
+#[cfg(feature = "postgres")]
+use sqlx::{PgConnection as Connection, Postgres as Db};
+
+pub type Transaction = sqlx::Transaction<'static, Db>;
+
 #[derive(Debug)]
 pub struct Database {
     db: Pool,
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-pub enum BuoyType {
-    SFY,
-    OMB,
-    Unknown,
-}
-
-impl BuoyType {
-    pub fn to_str(&self) -> &'static str {
-        match self {
-            BuoyType::SFY => "sfy",
-            BuoyType::OMB => "omb",
-            BuoyType::Unknown => "unknown",
-        }
-    }
-}
-
-impl From<&str> for BuoyType {
-    fn from(s: &str) -> BuoyType {
-        match s {
-            "sfy" => BuoyType::SFY,
-            "omb" => BuoyType::OMB,
-            _ => BuoyType::Unknown,
-        }
-    }
-}
-
-impl Into<String> for BuoyType {
-    fn into(self) -> String {
-        self.to_str().into()
-    }
-}
-
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum OmbMessageType {
-    GPS,
-    IMU,
+    Gps,
+    Imu,
     Thermistor,
     Unknown,
 }
 
 impl OmbMessageType {
-    pub fn to_str(&self) -> &'static str {
+    pub fn to_str(self) -> &'static str {
         match self {
-            OmbMessageType::GPS => "gps",
-            OmbMessageType::IMU => "imu",
+            OmbMessageType::Gps => "gps",
+            OmbMessageType::Imu => "imu",
             OmbMessageType::Thermistor => "thermistor",
             OmbMessageType::Unknown => "unknown",
         }
@@ -72,17 +56,17 @@ impl OmbMessageType {
 impl From<&str> for OmbMessageType {
     fn from(s: &str) -> OmbMessageType {
         match s {
-            "gps" => OmbMessageType::GPS,
-            "imu" => OmbMessageType::IMU,
+            "gps" => OmbMessageType::Gps,
+            "imu" => OmbMessageType::Imu,
             "thermistor" => OmbMessageType::Thermistor,
             _ => OmbMessageType::Unknown,
         }
     }
 }
 
-impl Into<String> for OmbMessageType {
-    fn into(self: Self) -> String {
-        self.to_str().into()
+impl From<OmbMessageType> for String {
+    fn from(m: OmbMessageType) -> String {
+        m.to_str().into()
     }
 }
 
@@ -126,9 +110,12 @@ impl Database {
         let dev = percent_encoding::percent_decode_str(dev)
             .decode_utf8_lossy()
             .to_string();
-        let buoy = sqlx::query!("SELECT dev, name, buoy_type FROM buoys where dev = $1", dev)
-            .fetch_optional(&self.db)
-            .await?;
+        let buoy = sqlx::query!(
+            "SELECT dev, name, buoy_type, product FROM buoys where dev = $1",
+            dev
+        )
+        .fetch_optional(&self.db)
+        .await?;
 
         let known = buoy.is_some();
 
@@ -136,36 +123,52 @@ impl Database {
             debug!("Unknown buoy: {}", dev);
         }
 
-        let name = buoy.as_ref().map(|b| b.name.clone()).flatten();
-        let buoy_type = buoy
-            .as_ref()
-            .map(|b| b.buoy_type.as_str().into())
-            .unwrap_or(BuoyType::Unknown);
+        let name = buoy.as_ref().and_then(|b| b.name.clone());
+        let product = buoy.as_ref().and_then(|b| b.product.clone());
+        let family = buoy.as_ref().and_then(|b| family::get(&b.buoy_type));
 
         Ok(Buoy {
-            dev: String::from(dev),
+            dev,
             known,
             name,
-            buoy_type,
+            product,
+            family,
             db: self.db.clone().clone(),
         })
     }
 
-    /// Get list of buoys.
-    pub async fn buoys(&self) -> eyre::Result<Vec<(String, String, String, String)>> {
-        let buoys: Vec<_> = sqlx::query!("SELECT dev, name, buoy_type FROM buoys ORDER BY dev")
+    /// Get list of buoy devs.
+    pub async fn devs(&self) -> eyre::Result<Vec<String>> {
+        let devs = sqlx::query!("SELECT dev FROM buoys ORDER BY dev")
             .fetch_all(&self.db)
             .await?
-            .iter()
-            .map(move |r| {
-                (
-                    r.dev.clone(),
-                    r.name.clone().unwrap_or(String::new()),
-                    r.buoy_type.clone(),
-                )
-            })
+            .into_iter()
+            .map(|r| r.dev)
             .collect();
 
+        Ok(devs)
+    }
+
+    /// Get list of buoys, `permits` filters on dev and product.
+    pub async fn buoys(
+        &self,
+        permits: impl Fn(&str, Option<&str>) -> bool,
+    ) -> eyre::Result<Vec<(String, String, String, String)>> {
+        let buoys: Vec<_> =
+            sqlx::query!("SELECT dev, name, buoy_type, product FROM buoys ORDER BY dev")
+                .fetch_all(&self.db)
+                .await?
+                .iter()
+                .filter(|r| permits(&r.dev, r.product.as_deref()))
+                .map(move |r| {
+                    (
+                        r.dev.clone(),
+                        r.name.clone().unwrap_or(String::new()),
+                        r.buoy_type.clone(),
+                    )
+                })
+                .collect();
+
         let mut last = Vec::new();
 
         for r in &buoys {
@@ -183,6 +186,323 @@ impl Database {
         Ok(buoys)
     }
 
+    /// Status of each buoy, as kept up to date at ingest, `permits` filters on dev and product.
+    pub async fn status(
+        &self,
+        permits: impl Fn(&str, Option<&str>) -> bool,
+    ) -> eyre::Result<Vec<BuoyStatus>> {
+        let mut counts: BTreeMap<String, BTreeMap<String, i64>> = BTreeMap::new();
+        for r in sqlx::query!("SELECT dev, message_type, count FROM buoy_message_counts")
+            .fetch_all(&self.db)
+            .await?
+        {
+            counts
+                .entry(r.dev)
+                .or_default()
+                .insert(r.message_type, r.count);
+        }
+
+        let status = sqlx::query!(
+            r#"SELECT b.dev, b.name, b.buoy_type, b.product,
+                s.last_received AS "last_received?", s.last_message_type AS "last_message_type?",
+                s.lat AS "lat?", s.lon AS "lon?", s.fix_time AS "fix_time?",
+                s.hs AS "hs?", s.hs_received AS "hs_received?"
+            FROM buoys b LEFT JOIN buoy_status s ON s.dev = b.dev ORDER BY b.dev"#
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .filter(|r| permits(&r.dev, r.product.as_deref()))
+        .map(|r| BuoyStatus {
+            message_counts: counts.remove(&r.dev).unwrap_or_default(),
+            dev: r.dev,
+            name: r.name,
+            buoy_type: r.buoy_type,
+            product: r.product,
+            last_received: r.last_received,
+            last_message_type: r.last_message_type,
+            lat: r.lat,
+            lon: r.lon,
+            fix_time: r.fix_time,
+            hs: r.hs,
+            hs_received: r.hs_received,
+        })
+        .collect();
+
+        Ok(status)
+    }
+
+    /// Store a new token by its hash, returns the id of the token.
+    pub async fn add_token(&self, hash: &str, token: &Token) -> eyre::Result<i32> {
+        let kind = token.kind.to_str();
+        let buoys = json_list(&token.buoys)?;
+        let products = json_list(&token.products)?;
+
+        let r = sqlx::query!(
+            "INSERT INTO tokens (hash, label, kind, buoys, products, created, expires) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id",
+            hash,
+            token.label,
+            kind,
+            buoys,
+            products,
+            token.created,
+            token.expires
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(r.id)
+    }
+
+    /// Get list of all tokens, including expired and revoked.
+    pub async fn tokens(&self) -> eyre::Result<Vec<Token>> {
+        sqlx::query_as!(
+            TokenRow,
+            "SELECT id, label, kind, buoys, products, created, expires, revoked FROM tokens ORDER BY id"
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(TokenRow::into_token)
+        .collect()
+    }
+
+    /// Get token by its hash.
+    pub async fn token(&self, hash: &str) -> eyre::Result<Option<Token>> {
+        sqlx::query_as!(
+            TokenRow,
+            "SELECT id, label, kind, buoys, products, created, expires, revoked FROM tokens WHERE hash = $1",
+            hash
+        )
+        .fetch_optional(&self.db)
+        .await?
+        .map(TokenRow::into_token)
+        .transpose()
+    }
+
+    /// Set (or clear) the expiry time of token, returns false if there is no such token.
+    pub async fn expire_token(&self, id: i32, expires: Option<i64>) -> eyre::Result<bool> {
+        let r = sqlx::query!("UPDATE tokens SET expires = $1 WHERE id = $2", expires, id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(r.rows_affected() > 0)
+    }
+
+    /// Revoke token, returns false if there is no such token or it is already revoked.
+    pub async fn revoke_token(&self, id: i32, revoked: i64) -> eyre::Result<bool> {
+        let r = sqlx::query!(
+            "UPDATE tokens SET revoked = $1 WHERE id = $2 AND revoked IS NULL",
+            revoked,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(r.rows_affected() > 0)
+    }
+
+    /// Add deployment, returns the id of the deployment.
+    pub async fn add_deployment(&self, d: &Deployment) -> eyre::Result<i32> {
+        let features = json_list(&d.features)?;
+
+        let r = sqlx::query!(
+            "INSERT INTO deployments (dev, start_time, end_time, site, lat, lon, hull, mooring, notes, features) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING id",
+            d.dev,
+            d.start,
+            d.end,
+            d.site,
+            d.lat,
+            d.lon,
+            d.hull,
+            d.mooring,
+            d.notes,
+            features
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(r.id)
+    }
+
+    /// Get deployments, optionally only for buoy `dev`.
+    pub async fn deployments(&self, dev: Option<&str>) -> eyre::Result<Vec<Deployment>> {
+        sqlx::query_as!(
+            DeploymentRow,
+            "SELECT id, dev, start_time, end_time, site, lat, lon, hull, mooring, notes, features FROM deployments ORDER BY dev, start_time"
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .filter(|r| dev.is_none() || dev == Some(r.dev.as_str()))
+        .map(DeploymentRow::into_deployment)
+        .collect()
+    }
+
+    pub async fn deployment(&self, id: i32) -> eyre::Result<Option<Deployment>> {
+        sqlx::query_as!(
+            DeploymentRow,
+            "SELECT id, dev, start_time, end_time, site, lat, lon, hull, mooring, notes, features FROM deployments WHERE id = $1",
+            id
+        )
+        .fetch_optional(&self.db)
+        .await?
+        .map(DeploymentRow::into_deployment)
+        .transpose()
+    }
+
+    /// Update deployment, returns false if there is no such deployment.
+    pub async fn update_deployment(&self, d: &Deployment) -> eyre::Result<bool> {
+        let features = json_list(&d.features)?;
+
+        let r = sqlx::query!(
+            "UPDATE deployments SET dev = $1, start_time = $2, end_time = $3, site = $4, lat = $5, lon = $6, hull = $7, mooring = $8, notes = $9, features = $10 WHERE id = $11",
+            d.dev,
+            d.start,
+            d.end,
+            d.site,
+            d.lat,
+            d.lon,
+            d.hull,
+            d.mooring,
+            d.notes,
+            features,
+            d.id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(r.rows_affected() > 0)
+    }
+
+    /// Remove deployment, returns false if there is no such deployment.
+    pub async fn remove_deployment(&self, id: i32) -> eyre::Result<bool> {
+        let r = sqlx::query!("DELETE FROM deployments WHERE id = $1", id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(r.rows_affected() > 0)
+    }
+
+    /// Queue delivery of `payload` to webhook `url`.
+    pub async fn enqueue_webhook(&self, url: &str, payload: &[u8], now: i64) -> eyre::Result<i32> {
+        let mut conn = self.db.acquire().await?;
+        Database::enqueue_webhook_in(&mut conn, url, payload, now).await
+    }
+
+    /// Queue delivery of `payload` to webhook `url` on `conn`, e.g. in the transaction that
+    /// stores the event.
+    pub async fn enqueue_webhook_in(
+        conn: &mut Connection,
+        url: &str,
+        payload: &[u8],
+        now: i64,
+    ) -> eyre::Result<i32> {
+        let r = sqlx::query!(
+            "INSERT INTO webhook_queue (url, payload, created, next_attempt) VALUES ($1, $2, $3, $3) RETURNING id",
+            url,
+            payload,
+            now
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        Ok(r.id)
+    }
+
+    /// Webhook deliveries that are due at `now`, oldest first.
+    pub async fn due_webhooks(&self, now: i64, limit: i64) -> eyre::Result<Vec<Delivery>> {
+        let deliveries = sqlx::query_as!(
+            Delivery,
+            "SELECT id, url, payload, attempts FROM webhook_queue WHERE failed IS NULL AND next_attempt <= $1 ORDER BY next_attempt, id LIMIT $2",
+            now,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(deliveries)
+    }
+
+    /// Remove delivered webhook from queue.
+    pub async fn webhook_delivered(&self, id: i32) -> eyre::Result<()> {
+        sqlx::query!("DELETE FROM webhook_queue WHERE id = $1", id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Record failed webhook delivery, to be retried at `next_attempt` or given up if `failed`
+    /// is set.
+    pub async fn webhook_attempted(
+        &self,
+        id: i32,
+        error: &str,
+        next_attempt: i64,
+        failed: Option<i64>,
+    ) -> eyre::Result<()> {
+        sqlx::query!(
+            "UPDATE webhook_queue SET attempts = attempts + 1, last_error = $1, next_attempt = $2, failed = $3 WHERE id = $4",
+            error,
+            next_attempt,
+            failed,
+            id
+        )
+        .execute(&self.db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Begin a transaction, events can be appended in it with [`Buoy::append_in`].
+    pub async fn begin(&self) -> eyre::Result<Transaction> {
+        Ok(self.db.begin().await?)
+    }
+
+    /// The active alert of `kind` for buoy, if any.
+    pub async fn active_alert(&self, dev: &str, kind: &str) -> eyre::Result<Option<Alert>> {
+        let alert = sqlx::query_as!(
+            Alert,
+            "SELECT id, dev, kind, message, raised, resolved FROM alerts WHERE dev = $1 AND kind = $2 AND resolved IS NULL ORDER BY raised DESC LIMIT 1",
+            dev,
+            kind
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        Ok(alert)
+    }
+
+    /// Store a newly raised alert, returns the id of the alert.
+    pub async fn raise_alert(
+        &self,
+        dev: &str,
+        kind: &str,
+        message: &str,
+        now: i64,
+    ) -> eyre::Result<i32> {
+        let r = sqlx::query!(
+            "INSERT INTO alerts (dev, kind, message, raised) VALUES ($1, $2, $3, $4) RETURNING id",
+            dev,
+            kind,
+            message,
+            now
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(r.id)
+    }
+
+    pub async fn resolve_alert(&self, id: i32, now: i64) -> eyre::Result<()> {
+        sqlx::query!("UPDATE alerts SET resolved = $1 WHERE id = $2", now, id)
+            .execute(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
     #[cfg(test)]
     pub async fn temporary() -> Database {
         #[cfg(feature = "sqlite")]
@@ -195,13 +515,96 @@ impl Database {
     }
 }
 
+struct TokenRow {
+    id: i32,
+    label: String,
+    kind: String,
+    buoys: Option<String>,
+    products: Option<String>,
+    created: i64,
+    expires: Option<i64>,
+    revoked: Option<i64>,
+}
+
+impl TokenRow {
+    fn into_token(self) -> eyre::Result<Token> {
+        Ok(Token {
+            id: self.id,
+            label: self.label,
+            kind: self.kind.parse()?,
+            buoys: parse_json_list(self.buoys)?,
+            products: parse_json_list(self.products)?,
+            created: self.created,
+            expires: self.expires,
+            revoked: self.revoked,
+        })
+    }
+}
+
+struct DeploymentRow {
+    id: i32,
+    dev: String,
+    start_time: i64,
+    end_time: Option<i64>,
+    site: Option<String>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    hull: Option<String>,
+    mooring: Option<String>,
+    notes: Option<String>,
+    features: Option<String>,
+}
+
+impl DeploymentRow {
+    fn into_deployment(self) -> eyre::Result<Deployment> {
+        Ok(Deployment {
+            id: self.id,
+            dev: self.dev,
+            start: self.start_time,
+            end: self.end_time,
+            site: self.site,
+            lat: self.lat,
+            lon: self.lon,
+            hull: self.hull,
+            mooring: self.mooring,
+            notes: self.notes,
+            features: parse_json_list(self.features)?,
+        })
+    }
+}
+
+fn json_list(l: &[String]) -> eyre::Result<Option<String>> {
+    Ok(if l.is_empty() {
+        None
+    } else {
+        Some(json::to_string(l)?)
+    })
+}
+
+/// Numbered query parameters `$first, ..` for an `IN` list of `n` values.
+fn placeholders(first: usize, n: usize) -> String {
+    (first..first + n)
+        .map(|i| format!("${}", i))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn parse_json_list(l: Option<String>) -> eyre::Result<Vec<String>> {
+    Ok(match l {
+        Some(l) => json::from_str(&l)?,
+        None => Vec::new(),
+    })
+}
+
 #[derive(Debug)]
 pub struct Buoy {
     dev: String,
     /// Does the buoy exist in the database already.
     known: bool,
     name: Option<String>,
-    buoy_type: BuoyType,
+    /// Notehub product or Rockblock account.
+    product: Option<String>,
+    family: Option<&'static dyn Family>,
     db: Pool,
 }
 
@@ -212,8 +615,122 @@ pub struct Event {
     pub data: Option<Vec<u8>>,
 }
 
+/// Outcome of appending an event.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Appended {
+    New,
+
+    /// The event has already been stored with the same payload.
+    Duplicate,
+
+    /// The event has already been stored with a different payload. The new payload is stored as
+    /// a conflict.
+    Conflict,
+}
+
+/// An event received with the same name as a stored event, but with a different payload.
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub received: i64,
+    pub event: String,
+    pub message_type: String,
+    pub data: Vec<u8>,
+
+    /// Time the conflict was received [ms].
+    pub reported: i64,
+}
+
+/// Position in a range of events: after the event named `received-event`, as in the list of
+/// events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub received: i64,
+    pub event: String,
+}
+
+impl Cursor {
+    /// Before the first event.
+    pub fn start() -> Cursor {
+        Cursor {
+            received: i64::MIN,
+            event: String::new(),
+        }
+    }
+
+    pub fn after(received: i64, event: &str) -> Cursor {
+        Cursor {
+            received,
+            event: event.into(),
+        }
+    }
+
+    /// Event id of OpenMetBuoy events, which are named `event-message_type`.
+    fn omb_event(&self) -> Result<i32> {
+        if self.received == i64::MIN {
+            Ok(i32::MIN)
+        } else {
+            let event = self.event.split('-').next().unwrap_or_default();
+            Ok(event.parse::<i32>()?)
+        }
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = eyre::ErrReport;
+
+    fn from_str(s: &str) -> Result<Cursor> {
+        let (received, event) = s
+            .split_once('-')
+            .ok_or(eyre!("incorrect format of cursor"))?;
+
+        Ok(Cursor {
+            received: received.parse()?,
+            event: event.into(),
+        })
+    }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.received, self.event)
+    }
+}
+
+/// At most `limit` events following `after`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub after: Cursor,
+    pub limit: i64,
+}
+
+impl Page {
+    /// All events.
+    pub fn all() -> Page {
+        Page {
+            after: Cursor::start(),
+            limit: i64::MAX,
+        }
+    }
+
+    pub fn new(after: Option<Cursor>, limit: Option<i64>) -> Page {
+        Page {
+            after: after.unwrap_or_else(Cursor::start),
+            limit: limit.unwrap_or(i64::MAX),
+        }
+    }
+}
+
+/// An event with its message type, for decoding the payload.
+#[derive(Debug)]
+pub struct TypedEvent {
+    pub received: i64,
+    pub event: String,
+    pub message_type: String,
+    pub data: Option<Vec<u8>>,
+}
+
 /// A single position fix for the track endpoint.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TrackPoint {
     /// Unix timestamp in seconds.
     pub t: f64,
@@ -221,65 +738,91 @@ pub struct TrackPoint {
     pub lon: f64,
 }
 
-fn extract_sfy_point(data: &json::Value, message_type: &str) -> Option<TrackPoint> {
-    let body = data.get("body")?;
-    match message_type {
-        "axl.qo" => {
-            let lat = body.get("lat")?.as_f64()?;
-            let lon = body.get("lon")?.as_f64()?;
-            let t = body.get("timestamp")?.as_f64()? / 1000.0;
-            Some(TrackPoint { t, lat, lon })
-        }
-        "_track.qo" => {
-            let lat = data.get("best_lat")?.as_f64()?;
-            let lon = data.get("best_lon")?.as_f64()?;
-            let t = data.get("best_location_when")?.as_f64()?;
-            Some(TrackPoint { t, lat, lon })
-        }
-        "axlb.qo" => {
-            let lat = body.get("lat")?.as_f64()?;
-            let lon = body.get("lon")?.as_f64()?;
-            let t = body.get("position_time")?.as_f64()?;
-            Some(TrackPoint { t, lat, lon })
-        }
-        "egpsb.qo" => {
-            let lat = body.get("lat")?.as_f64()? / 1e7;
-            let lon = body.get("lon")?.as_f64()? / 1e7;
-            let t = body.get("timestamp")?.as_f64()? / 1000.0;
-            Some(TrackPoint { t, lat, lon })
+/// Latest state of a buoy, updated as its events are stored.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuoyStatus {
+    pub dev: String,
+    pub name: Option<String>,
+    pub buoy_type: String,
+    pub product: Option<String>,
+
+    /// Time of the last received event [ms].
+    pub last_received: Option<i64>,
+    pub last_message_type: Option<String>,
+
+    /// Latest position fix, and its time [ms].
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub fix_time: Option<i64>,
+
+    /// Latest significant wave height [m], and the time the event was received [ms].
+    pub hs: Option<f64>,
+    pub hs_received: Option<i64>,
+
+    /// Number of events of each message type.
+    pub message_counts: BTreeMap<String, i64>,
+}
+
+impl Buoy {
+    pub fn dev(&self) -> &str {
+        &self.dev
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn product(&self) -> Option<&str> {
+        self.product.as_deref()
+    }
+
+    pub fn family(&self) -> Option<&'static dyn Family> {
+        self.family
+    }
+
+    /// Name of the family of the buoy, `unknown` if it has not been stored yet.
+    pub fn buoy_type(&self) -> &'static str {
+        self.family.map_or("unknown", |f| f.name())
+    }
+
+    /// Set the family of a new buoy, before its first event is appended. The family of a known
+    /// buoy is not changed.
+    pub fn set_family(&mut self, family: &'static dyn Family) {
+        if !self.known {
+            self.family = Some(family);
         }
-        _ => None,
     }
-}
 
-fn extract_omb_points(data: &json::Value) -> Vec<TrackPoint> {
-    let mut points = Vec::new();
-    let messages = data
-        .get("body")
-        .and_then(|b| b.get("messages"))
-        .and_then(|m| m.as_array());
-    if let Some(messages) = messages {
-        for msg in messages {
-            let valid = msg
-                .get("is_valid")
-                .and_then(|v| v.as_bool())
-                .unwrap_or(false);
-            if !valid {
-                continue;
-            }
-            let lat = msg.get("latitude").and_then(|v| v.as_f64());
-            let lon = msg.get("longitude").and_then(|v| v.as_f64());
-            let t = msg.get("datetime_fix").and_then(|v| v.as_f64());
-            if let (Some(lat), Some(lon), Some(t)) = (lat, lon, t) {
-                points.push(TrackPoint { t, lat, lon });
-            }
+    fn known_family(&self) -> Result<&'static dyn Family> {
+        self.family.ok_or(eyre!("Unknown buoy type"))
+    }
+
+    /// Update the product of the buoy if it has changed.
+    pub async fn set_product(&mut self, product: &str) -> eyre::Result<()> {
+        ensure!(self.known, "No such buoy");
+
+        if self.product.as_deref() != Some(product) {
+            debug!("Updating product for: {} to {}", self.dev, product);
+            let buoy_type = self.buoy_type();
+            sqlx::query!(
+                "UPDATE buoys SET product = $1 where dev = $2 AND buoy_type = $3",
+                product,
+                self.dev,
+                buoy_type
+            )
+            .execute(&self.db)
+            .await?;
+
+            self.product = Some(product.to_string());
         }
+
+        Ok(())
     }
-    points
-}
 
-impl Buoy {
     /// Append new event to SFY buoy, `name` is parsed serial number of buoy.
+    ///
+    /// Appending an event that is already stored (e.g. retried by Notehub) is not an error. If
+    /// the payload differs from the stored event it is recorded as a conflict.
     pub async fn append(
         &mut self,
         name: Option<String>,
@@ -287,17 +830,38 @@ impl Buoy {
         received: u64,
         file: Option<String>,
         data: impl AsRef<[u8]>,
-    ) -> eyre::Result<()> {
+    ) -> eyre::Result<Appended> {
+        let mut conn = self.db.acquire().await?;
+        self.append_in(&mut conn, name, event, received, file, data)
+            .await
+    }
+
+    /// Append new event to SFY buoy using `conn`, e.g. in a transaction.
+    pub async fn append_in(
+        &mut self,
+        conn: &mut Connection,
+        name: Option<String>,
+        event: impl AsRef<Path>,
+        received: u64,
+        file: Option<String>,
+        data: impl AsRef<[u8]>,
+    ) -> eyre::Result<Appended> {
         let data = data.as_ref();
         let event = event.as_ref().to_string_lossy().into_owned();
 
-        self.buoy_type = BuoyType::SFY;
+        // Buoys of other families stored in `events` keep their family.
+        let family = match self.family {
+            Some(f) if f.table() == Table::Events => f,
+            _ => &family::Sfy,
+        };
+        self.family = Some(family);
+        let buoy_type = family.name();
 
         if let Some(ref name) = name {
-            if self.name.as_ref() != Some(&name) {
+            if self.name.as_ref() != Some(name) {
                 debug!("Updating name for: {} to {}", self.dev, name);
                 sqlx::query!("UPDATE buoys SET name = $1 where dev = $2", name, self.dev,)
-                    .execute(&self.db)
+                    .execute(&mut *conn)
                     .await?;
             }
         }
@@ -309,59 +873,178 @@ impl Buoy {
             );
 
             sqlx::query!(
-                "INSERT INTO buoys (dev, name, buoy_type) VALUES ( $1, $2, 'sfy' ) ON CONFLICT (dev, buoy_type) DO UPDATE SET name = excluded.name, buoy_type = excluded.buoy_type",
+                "INSERT INTO buoys (dev, name, buoy_type) VALUES ( $1, $2, $3 ) ON CONFLICT (dev, buoy_type) DO UPDATE SET name = excluded.name, buoy_type = excluded.buoy_type",
+                self.dev,
+                name,
+                buoy_type
+            )
+            .execute(&mut *conn)
+            .await?;
+
+            self.known = true;
+        }
+
+        debug!(
+            "buoy ({}): {} ({:?}): appending event: {:?}, received: {}, size: {}",
+            buoy_type,
+            self.dev,
+            self.name,
+            event,
+            received,
+            data.len()
+        );
+
+        let r = received as i64;
+        let file = file.unwrap_or_else(|| "unknown".into());
+        let inserted = sqlx::query!(
+            "INSERT INTO events (dev, received, event, message_type, data) VALUES ( $1, $2, $3, $4, $5 ) ON CONFLICT (dev, event) DO NOTHING",
+            self.dev,
+            r,
+            event,
+            file,
+            data
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+
+        if inserted > 0 {
+            self.update_status(&mut *conn, &file, r, data).await?;
+            self.remove_wave_stats(&mut *conn, &file, data).await?;
+            return Ok(Appended::New);
+        }
+
+        let existing = sqlx::query!(
+            "SELECT data FROM events WHERE dev = $1 AND event = $2",
+            self.dev,
+            event
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if existing.data.as_deref() == Some(data) {
+            debug!(
+                "buoy ({}): {}: duplicate event: {:?}",
+                buoy_type, self.dev, event
+            );
+            return Ok(Appended::Duplicate);
+        }
+
+        warn!(
+            "buoy ({}): {}: event {:?} received with conflicting payload",
+            buoy_type, self.dev, event
+        );
+
+        let known = sqlx::query!(
+            "SELECT id FROM event_conflicts WHERE dev = $1 AND event = $2 AND data = $3",
+            self.dev,
+            event,
+            data
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if known.is_none() {
+            let now = chrono::Utc::now().timestamp_millis();
+            sqlx::query!(
+                "INSERT INTO event_conflicts (dev, event, received, message_type, data, reported) VALUES ( $1, $2, $3, $4, $5, $6 )",
+                self.dev,
+                event,
+                r,
+                file,
+                data,
+                now
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(Appended::Conflict)
+    }
+
+    /// Remove the stored wave parameters of the windows with samples in an appended acceleration
+    /// package, so that they are computed again.
+    async fn remove_wave_stats(
+        &self,
+        conn: &mut Connection,
+        message_type: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        if message_type != "axl.qo" && message_type != "axlb.qo" {
+            return Ok(());
+        }
+
+        let packet = match AxlPacket::decode(data, message_type) {
+            Ok(packet) => packet,
+            Err(e) => {
+                warn!(
+                    "{}: could not decode acceleration package: {:?}",
+                    self.dev, e
+                );
+                return Ok(());
+            }
+        };
+
+        if let (Some(t0), Some(t1)) = (packet.time.first(), packet.time.last()) {
+            let (t0, t1) = (t0.floor() as i64, t1.ceil() as i64);
+            sqlx::query!(
+                "DELETE FROM wave_stats WHERE dev = $1 AND start <= $3 AND start + window_length > $2",
                 self.dev,
-                name
+                t0,
+                t1
             )
-            .execute(&self.db)
+            .execute(&mut *conn)
             .await?;
-
-            self.known = true;
         }
 
-        debug!(
-            "buoy (sfy): {} ({:?}): appending event: {:?}, received: {}, size: {}",
-            self.dev,
-            self.name,
-            event,
-            received,
-            data.len()
-        );
+        Ok(())
+    }
 
-        let r = received as i64;
-        let file = file.unwrap_or_else(|| "unknown".into());
-        sqlx::query!(
-            "INSERT INTO events (dev, received, event, message_type, data) VALUES ( $1, $2, $3, $4, $5 )",
-            self.dev,
-            r,
-            event,
-            file,
-            data
+    /// Events received with conflicting payloads.
+    pub async fn conflicts(&self) -> Result<Vec<Conflict>> {
+        let conflicts = sqlx::query_as!(
+            Conflict,
+            "SELECT received, event, message_type, data, reported FROM event_conflicts WHERE dev = $1 ORDER BY reported, id",
+            self.dev
         )
-        .execute(&self.db)
+        .fetch_all(&self.db)
         .await?;
 
-        Ok(())
+        Ok(conflicts)
     }
 
-    /// Append to OpenMetBuoy (OMB)
+    /// Append to OpenMetBuoy (OMB), returns the id of the event.
     pub async fn append_omb(
         &mut self,
         account: String,
         received: u64,
         message_type: OmbMessageType,
         data: impl AsRef<[u8]>,
-    ) -> eyre::Result<()> {
+    ) -> eyre::Result<i32> {
+        let mut conn = self.db.acquire().await?;
+        self.append_omb_in(&mut conn, account, received, message_type, data)
+            .await
+    }
+
+    /// Append OMB event on `conn`, e.g. in a transaction.
+    pub async fn append_omb_in(
+        &mut self,
+        conn: &mut Connection,
+        account: String,
+        received: u64,
+        message_type: OmbMessageType,
+        data: impl AsRef<[u8]>,
+    ) -> eyre::Result<i32> {
         let data = data.as_ref();
 
-        self.buoy_type = BuoyType::OMB;
+        self.family = Some(&family::Omb);
 
         if !self.known {
             sqlx::query!(
                 "INSERT INTO buoys (dev, buoy_type) VALUES ( $1, 'omb' )",
                 self.dev,
             )
-            .execute(&self.db)
+            .execute(&mut *conn)
             .await?;
 
             self.known = true;
@@ -378,25 +1061,179 @@ impl Buoy {
 
         let message_type = message_type.to_str();
         let r = received as i64;
-        sqlx::query!(
-            "INSERT INTO omb_events (dev, received, account, message_type, data) VALUES ( $1, $2, $3, $4, $5 )",
+        let event = sqlx::query!(
+            "INSERT INTO omb_events (dev, received, account, message_type, data) VALUES ( $1, $2, $3, $4, $5 ) RETURNING event",
             self.dev,
             r,
             account,
             message_type,
             data
         )
-        .execute(&self.db)
+        .fetch_one(&mut *conn)
+        .await?
+        .event;
+
+        self.update_status(conn, message_type, r, data).await?;
+
+        Ok(event)
+    }
+
+    /// Update the status of the buoy with a newly stored event: the last received event, the
+    /// latest position and wave height, and the number of events of each message type.
+    async fn update_status(
+        &self,
+        conn: &mut Connection,
+        message_type: &str,
+        received: i64,
+        data: &[u8],
+    ) -> Result<()> {
+        let family = self.known_family()?;
+
+        let fix = if family.positions().contains(&message_type) {
+            json::from_slice(data)
+                .ok()
+                .map(|event| family.points(message_type, &event))
+                .unwrap_or_default()
+                .into_iter()
+                .max_by(|a, b| a.t.total_cmp(&b.t))
+        } else {
+            None
+        };
+        let lat = fix.as_ref().map(|p| p.lat);
+        let lon = fix.as_ref().map(|p| p.lon);
+        let fix_time = fix.as_ref().map(|p| (p.t * 1000.) as i64);
+
+        let hs = family.hs(message_type, data);
+        let hs_received = hs.map(|_| received);
+
+        sqlx::query!(
+            r#"INSERT INTO buoy_status (dev, last_received, last_message_type, lat, lon, fix_time, hs, hs_received)
+            VALUES ( $1, $2, $3, $4, $5, $6, $7, $8 )
+            ON CONFLICT (dev) DO UPDATE SET
+                last_received = CASE WHEN buoy_status.last_received IS NULL OR excluded.last_received >= buoy_status.last_received
+                    THEN excluded.last_received ELSE buoy_status.last_received END,
+                last_message_type = CASE WHEN buoy_status.last_received IS NULL OR excluded.last_received >= buoy_status.last_received
+                    THEN excluded.last_message_type ELSE buoy_status.last_message_type END,
+                lat = CASE WHEN excluded.fix_time IS NOT NULL AND (buoy_status.fix_time IS NULL OR excluded.fix_time >= buoy_status.fix_time)
+                    THEN excluded.lat ELSE buoy_status.lat END,
+                lon = CASE WHEN excluded.fix_time IS NOT NULL AND (buoy_status.fix_time IS NULL OR excluded.fix_time >= buoy_status.fix_time)
+                    THEN excluded.lon ELSE buoy_status.lon END,
+                fix_time = CASE WHEN excluded.fix_time IS NOT NULL AND (buoy_status.fix_time IS NULL OR excluded.fix_time >= buoy_status.fix_time)
+                    THEN excluded.fix_time ELSE buoy_status.fix_time END,
+                hs = CASE WHEN excluded.hs_received IS NOT NULL AND (buoy_status.hs_received IS NULL OR excluded.hs_received >= buoy_status.hs_received)
+                    THEN excluded.hs ELSE buoy_status.hs END,
+                hs_received = CASE WHEN excluded.hs_received IS NOT NULL AND (buoy_status.hs_received IS NULL OR excluded.hs_received >= buoy_status.hs_received)
+                    THEN excluded.hs_received ELSE buoy_status.hs_received END"#,
+            self.dev,
+            received,
+            message_type,
+            lat,
+            lon,
+            fix_time,
+            hs,
+            hs_received
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO buoy_message_counts (dev, message_type, count) VALUES ( $1, $2, 1 ) ON CONFLICT (dev, message_type) DO UPDATE SET count = buoy_message_counts.count + 1",
+            self.dev,
+            message_type
+        )
+        .execute(&mut *conn)
         .await?;
 
         Ok(())
     }
 
+    /// Fill in the latest position fix of the status from all the stored events, for events
+    /// stored before the status was kept up to date at ingest. A later fix in the status is kept.
+    /// Returns the latest fix in the events.
+    pub async fn backfill_fix(&self) -> Result<Option<TrackPoint>> {
+        use sqlx::Row;
+
+        /// Events read at a time.
+        const PAGE: i64 = 500;
+
+        ensure!(self.known, "No such buoy");
+        let family = self.known_family()?;
+        let table = family.table();
+
+        let sql = format!(
+            "SELECT received, event, message_type, data FROM {} WHERE dev = $1 AND (received > $2 OR (received = $2 AND event > $3)) AND message_type IN ({}) ORDER BY received, event LIMIT {}",
+            table.name(),
+            placeholders(4, family.positions().len()),
+            PAGE
+        );
+
+        let mut fix: Option<TrackPoint> = None;
+        let mut after = Cursor::start();
+
+        loop {
+            let query = sqlx::query(&sql).bind(&self.dev).bind(after.received);
+            let mut query = match table {
+                Table::Events => query.bind(after.event.clone()),
+                Table::OmbEvents => query.bind(after.omb_event()?),
+            };
+            for message_type in family.positions() {
+                query = query.bind(*message_type);
+            }
+
+            let rows = query.fetch_all(&self.db).await?;
+
+            for row in &rows {
+                let message_type: String = row.try_get("message_type")?;
+                let data: Option<Vec<u8>> = row.try_get("data")?;
+
+                if let Some(event) = data.and_then(|d| json::from_slice::<json::Value>(&d).ok()) {
+                    fix = family
+                        .points(&message_type, &event)
+                        .into_iter()
+                        .chain(fix)
+                        .max_by(|a, b| a.t.total_cmp(&b.t));
+                }
+            }
+
+            match rows.last() {
+                Some(row) if rows.len() as i64 == PAGE => {
+                    let event = match table {
+                        Table::Events => row.try_get::<String, _>("event")?,
+                        Table::OmbEvents => row.try_get::<i32, _>("event")?.to_string(),
+                    };
+                    after = Cursor::after(row.try_get("received")?, &event);
+                }
+                _ => break,
+            }
+        }
+
+        if let Some(p) = &fix {
+            sqlx::query(
+                r#"INSERT INTO buoy_status (dev, lat, lon, fix_time) VALUES ( $1, $2, $3, $4 )
+                ON CONFLICT (dev) DO UPDATE SET
+                    lat = CASE WHEN buoy_status.fix_time IS NULL OR excluded.fix_time >= buoy_status.fix_time
+                        THEN excluded.lat ELSE buoy_status.lat END,
+                    lon = CASE WHEN buoy_status.fix_time IS NULL OR excluded.fix_time >= buoy_status.fix_time
+                        THEN excluded.lon ELSE buoy_status.lon END,
+                    fix_time = CASE WHEN buoy_status.fix_time IS NULL OR excluded.fix_time >= buoy_status.fix_time
+                        THEN excluded.fix_time ELSE buoy_status.fix_time END"#,
+            )
+            .bind(&self.dev)
+            .bind(p.lat)
+            .bind(p.lon)
+            .bind((p.t * 1000.) as i64)
+            .execute(&self.db)
+            .await?;
+        }
+
+        Ok(fix)
+    }
+
     pub async fn entries(&self) -> Result<Vec<(String, String)>> {
         ensure!(self.known, "No such buoy");
 
-        let events = match self.buoy_type {
-            BuoyType::SFY => {
+        let events = match self.known_family()?.table() {
+            Table::Events => {
                 sqlx::query!(
                     "SELECT received, event, message_type FROM events where dev = $1 ORDER BY received",
                     self.dev
@@ -405,7 +1242,7 @@ impl Buoy {
                 .fetch_all(&self.db)
                 .await?
             }
-            BuoyType::OMB => {
+            Table::OmbEvents => {
                 sqlx::query!(
                     "SELECT received, event, message_type FROM omb_events where dev = $1 ORDER BY received",
                     self.dev
@@ -414,27 +1251,75 @@ impl Buoy {
                 .fetch_all(&self.db)
                 .await?
             }
-            BuoyType::Unknown => return Err(eyre!("Unknown buoy type")),
         };
 
         Ok(events)
     }
 
+    /// Time of the last received event [ms], of any type.
+    pub async fn last_received(&self) -> Result<Option<i64>> {
+        ensure!(self.known, "No such buoy");
+
+        let received = match self.known_family()?.table() {
+            Table::Events => {
+                sqlx::query!(
+                    "SELECT MAX(received) AS received FROM events WHERE dev = $1",
+                    self.dev
+                )
+                .fetch_one(&self.db)
+                .await?
+                .received
+            }
+            Table::OmbEvents => {
+                sqlx::query!(
+                    "SELECT MAX(received) AS received FROM omb_events WHERE dev = $1",
+                    self.dev
+                )
+                .fetch_one(&self.db)
+                .await?
+                .received
+            }
+        };
+
+        Ok(received)
+    }
+
+    /// The latest events of the buoy, newest first.
+    pub async fn latest(&self, limit: i64) -> Result<Vec<TypedEvent>> {
+        ensure!(self.known, "No such buoy");
+        ensure!(self.buoy_type() == family::Sfy.name(), "Not an SFY buoy");
+
+        let events = sqlx::query_as!(
+            TypedEvent,
+            "SELECT received, event, message_type, data FROM events WHERE dev = $1 ORDER BY received DESC, event DESC LIMIT $2",
+            self.dev,
+            limit
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(events)
+    }
+
     /// Get the last received location-bearing entry for the buoy.
     pub async fn last(&self) -> Result<Vec<u8>> {
+        use sqlx::Row;
+
         ensure!(self.known, "No such buoy");
+        let family = self.known_family()?;
 
-        let data = match self.buoy_type {
-            BuoyType::SFY => sqlx::query!("SELECT data FROM events WHERE dev = $1 AND (message_type = 'axl.qo' or message_type = '_track.qo' or message_type = 'axlb.qo' or message_type = 'egpsb.qo') ORDER BY received DESC LIMIT 1", self.dev)
-                .fetch_one(&self.db)
-                .await?.data,
+        let sql = format!(
+            "SELECT data FROM {} WHERE dev = $1 AND message_type IN ({}) ORDER BY received DESC LIMIT 1",
+            family.table().name(),
+            placeholders(2, family.positions().len())
+        );
 
-            BuoyType::OMB => sqlx::query!("SELECT data FROM omb_events WHERE dev = $1 AND message_type = 'gps' ORDER BY received DESC LIMIT 1", self.dev)
-                .fetch_one(&self.db)
-                .await?.data,
+        let mut query = sqlx::query(&sql).bind(&self.dev);
+        for message_type in family.positions() {
+            query = query.bind(*message_type);
+        }
 
-            BuoyType::Unknown => return Err(eyre!("Unknown buoy type"))
-        };
+        let data: Option<Vec<u8>> = query.fetch_one(&self.db).await?.try_get("data")?;
 
         match data {
             Some(data) => Ok(data),
@@ -444,70 +1329,241 @@ impl Buoy {
 
     /// Return position fixes in the given received-time range (milliseconds since epoch).
     pub async fn track(&self, start: i64, end: i64) -> Result<Vec<TrackPoint>> {
+        use sqlx::Row;
+
         ensure!(self.known, "No such buoy");
+        let family = self.known_family()?;
 
-        let mut points = Vec::new();
+        let sql = format!(
+            "SELECT data, message_type FROM {} WHERE dev = $1 AND received >= $2 AND received <= $3 AND message_type IN ({}) ORDER BY received",
+            family.table().name(),
+            placeholders(4, family.positions().len())
+        );
 
-        match self.buoy_type {
-            BuoyType::SFY => {
-                let rows = sqlx::query!(
-                    "SELECT data, message_type FROM events WHERE dev = $1 AND received >= $2 AND received <= $3 AND (message_type = 'axl.qo' OR message_type = '_track.qo' OR message_type = 'axlb.qo' OR message_type = 'egpsb.qo') ORDER BY received",
-                    self.dev, start, end
-                )
-                .fetch_all(&self.db)
-                .await?;
-
-                // sfy3 sends _track.qo on every real GPS fix and also batches positions
-                // in axl.qo (which repeats the same stale fix many times). Mixing both
-                // produces a jagged track, so when _track.qo is present use only those.
-                // sfy4 uses axlb.qo / egpsb.qo and does not emit _track.qo or axl.qo,
-                // so for sfy4 the original logic (accept all types) applies unchanged.
-                let has_track_qo = rows.iter().any(|r| r.message_type == "_track.qo");
-
-                for row in rows {
-                    if has_track_qo && row.message_type != "_track.qo" {
-                        continue;
-                    }
-                    if let Some(data) = row.data {
-                        if let Ok(j) = json::from_slice::<json::Value>(&data) {
-                            if let Some(pt) = extract_sfy_point(&j, &row.message_type) {
-                                points.push(pt);
-                            }
-                        }
-                    }
-                }
-            }
-            BuoyType::OMB => {
-                let rows = sqlx::query!(
-                    "SELECT data FROM omb_events WHERE dev = $1 AND received >= $2 AND received <= $3 AND message_type = 'gps' ORDER BY received",
-                    self.dev, start, end
-                )
-                .fetch_all(&self.db)
-                .await?;
-
-                for row in rows {
-                    if let Some(data) = row.data {
-                        if let Ok(j) = json::from_slice::<json::Value>(&data) {
-                            points.extend(extract_omb_points(&j));
-                        }
-                    }
-                }
+        let mut query = sqlx::query(&sql).bind(&self.dev).bind(start).bind(end);
+        for message_type in family.positions() {
+            query = query.bind(*message_type);
+        }
+
+        let mut events = Vec::new();
+        for row in query.fetch_all(&self.db).await? {
+            let message_type: String = row.try_get("message_type")?;
+            let data: Option<Vec<u8>> = row.try_get("data")?;
+
+            if let Some(j) = data.and_then(|d| json::from_slice::<json::Value>(&d).ok()) {
+                events.push((message_type, j));
             }
-            BuoyType::Unknown => return Err(eyre!("Unknown buoy type")),
         }
 
+        let mut points = family.track(&events);
+
         // Sort by fix time (messages within a packet may be out of order).
         points.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap_or(std::cmp::Ordering::Equal));
         Ok(points)
     }
 
+    /// Return acceleration packages (`axl.qo` and `axlb.qo`) in the given received-time range.
+    pub async fn axl_range(&self, start: i64, end: i64, page: &Page) -> Result<Vec<TypedEvent>> {
+        ensure!(self.known, "No such buoy");
+        ensure!(self.buoy_type() == family::Sfy.name(), "Not an SFY buoy");
+
+        let events = sqlx::query_as!(
+            TypedEvent,
+            "SELECT received, event, message_type, data FROM events WHERE dev = $1 AND received >= $2 AND received <= $3 AND (message_type = 'axl.qo' OR message_type = 'axlb.qo') AND (received > $4 OR (received = $4 AND event > $5)) ORDER BY received, event LIMIT $6",
+            self.dev,
+            start,
+            end,
+            page.after.received,
+            page.after.event,
+            page.limit,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(events)
+    }
+
+    /// Received time of the first and last acceleration package.
+    pub async fn axl_received(&self) -> Result<Option<(i64, i64)>> {
+        ensure!(self.known, "No such buoy");
+        ensure!(self.buoy_type() == family::Sfy.name(), "Not an SFY buoy");
+
+        let r = sqlx::query!(
+            "SELECT MIN(received) AS first, MAX(received) AS last FROM events WHERE dev = $1 AND (message_type = 'axl.qo' OR message_type = 'axlb.qo')",
+            self.dev
+        )
+        .fetch_one(&self.db)
+        .await?;
+
+        Ok(r.first.zip(r.last))
+    }
+
+    /// Stored wave parameters of the windows starting in `[start, end)`.
+    pub async fn wave_stats(
+        &self,
+        start: i64,
+        end: i64,
+        window: i64,
+        nseg: usize,
+    ) -> Result<Vec<WaveStats>> {
+        let nseg = nseg as i32;
+        let stats = sqlx::query!(
+            "SELECT start, window_length, nseg, freq, segments, gaps, coverage, hm0, tp, tm01, tm02 FROM wave_stats WHERE dev = $1 AND window_length = $2 AND nseg = $3 AND start >= $4 AND start < $5 ORDER BY start",
+            self.dev,
+            window,
+            nseg,
+            start,
+            end
+        )
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|r| WaveStats {
+            start: r.start,
+            window: r.window_length,
+            nseg: r.nseg as usize,
+            freq: r.freq,
+            segments: r.segments as usize,
+            gaps: r.gaps as usize,
+            coverage: r.coverage,
+            stats: match (r.hm0, r.tp, r.tm01, r.tm02) {
+                (Some(hm0), Some(tp), Some(tm01), Some(tm02)) => Some(Stats {
+                    hm0,
+                    tp,
+                    tm01,
+                    tm02,
+                }),
+                _ => None,
+            },
+        })
+        .collect();
+
+        Ok(stats)
+    }
+
+    /// Store computed wave parameters, replacing stored windows.
+    pub async fn store_wave_stats(&self, stats: &[WaveStats]) -> Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut tx = self.db.begin().await?;
+
+        for s in stats {
+            let nseg = s.nseg as i32;
+            let segments = s.segments as i32;
+            let gaps = s.gaps as i32;
+            let (hm0, tp, tm01, tm02) = match s.stats {
+                Some(st) => (Some(st.hm0), Some(st.tp), Some(st.tm01), Some(st.tm02)),
+                None => (None, None, None, None),
+            };
+
+            sqlx::query!(
+                "INSERT INTO wave_stats (dev, window_length, nseg, start, freq, segments, gaps, coverage, hm0, tp, tm01, tm02, computed) VALUES ( $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13 )
+                ON CONFLICT (dev, window_length, nseg, start) DO UPDATE SET freq = excluded.freq, segments = excluded.segments, gaps = excluded.gaps, coverage = excluded.coverage, hm0 = excluded.hm0, tp = excluded.tp, tm01 = excluded.tm01, tm02 = excluded.tm02, computed = excluded.computed",
+                self.dev,
+                s.window,
+                nseg,
+                s.start,
+                s.freq,
+                segments,
+                gaps,
+                s.coverage,
+                hm0,
+                tp,
+                tm01,
+                tm02,
+                now
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Return GNSS packages (`egps.qo` and `egpsb.qo`) in the given received-time range.
+    pub async fn egps_range(&self, start: i64, end: i64, page: &Page) -> Result<Vec<TypedEvent>> {
+        ensure!(self.known, "No such buoy");
+        ensure!(self.buoy_type() == family::Sfy.name(), "Not an SFY buoy");
+
+        let events = sqlx::query_as!(
+            TypedEvent,
+            "SELECT received, event, message_type, data FROM events WHERE dev = $1 AND received >= $2 AND received <= $3 AND (message_type = 'egps.qo' OR message_type = 'egpsb.qo') AND (received > $4 OR (received = $4 AND event > $5)) ORDER BY received, event LIMIT $6",
+            self.dev,
+            start,
+            end,
+            page.after.received,
+            page.after.event,
+            page.limit,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(events)
+    }
+
+    /// Return spectrum packages (`spec.qo`) in the given received-time range.
+    pub async fn spec_range(&self, start: i64, end: i64, page: &Page) -> Result<Vec<TypedEvent>> {
+        ensure!(self.known, "No such buoy");
+        ensure!(self.buoy_type() == family::Sfy.name(), "Not an SFY buoy");
+
+        let events = sqlx::query_as!(
+            TypedEvent,
+            "SELECT received, event, message_type, data FROM events WHERE dev = $1 AND received >= $2 AND received <= $3 AND message_type = 'spec.qo' AND (received > $4 OR (received = $4 AND event > $5)) ORDER BY received, event LIMIT $6",
+            self.dev,
+            start,
+            end,
+            page.after.received,
+            page.after.event,
+            page.limit,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(events)
+    }
+
+    /// Return OpenMetBuoy events of `message_type` in the given received-time range.
+    pub async fn omb_range(
+        &self,
+        start: i64,
+        end: i64,
+        message_type: OmbMessageType,
+        page: &Page,
+    ) -> Result<Vec<TypedEvent>> {
+        ensure!(self.known, "No such buoy");
+        ensure!(self.buoy_type() == family::Omb.name(), "Not an OMB buoy");
+
+        let message_type = message_type.to_str();
+        let events = sqlx::query!(
+            "SELECT received, event, message_type, data FROM omb_events WHERE dev = $1 AND received >= $2 AND received <= $3 AND message_type = $4 AND (received > $5 OR (received = $5 AND event > $6)) ORDER BY received, event LIMIT $7",
+            self.dev,
+            start,
+            end,
+            message_type,
+            page.after.received,
+            page.after.omb_event()?,
+            page.limit,
+        )
+        .map(|r| TypedEvent {
+            received: r.received,
+            event: format!("{}-{}", r.event, r.message_type),
+            message_type: r.message_type,
+            data: r.data,
+        })
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(events)
+    }
+
     pub async fn get(&self, file: impl AsRef<Path>) -> Result<Vec<u8>> {
         ensure!(self.known, "No such buoy");
 
         let file = file.as_ref().to_string_lossy().into_owned();
 
-        let data = match self.buoy_type {
-            BuoyType::SFY => {
+        let data = match self.known_family()?.table() {
+            Table::Events => {
                 let (received, file) = file
                     .split_once('-')
                     .ok_or(eyre!("incorrect format of event"))?;
@@ -524,7 +1580,7 @@ impl Buoy {
                 .await?
                 .data
             }
-            BuoyType::OMB => {
+            Table::OmbEvents => {
                 let parts: Vec<_> = file.splitn(3, '-').collect();
                 ensure!(parts.len() == 3, "incorrect format of event");
                 let received = parts[0];
@@ -543,7 +1599,6 @@ impl Buoy {
                 .fetch_one(&self.db)
                 .await?.data
             }
-            _ => return Err(eyre!("Unknown buoy type")),
         };
 
         match data {
@@ -553,64 +1608,89 @@ impl Buoy {
     }
 
     pub async fn list_range(&self, start: i64, end: i64) -> Result<Vec<(i64, String, String)>> {
+        self.list_range_page(start, end, &Page::all()).await
+    }
+
+    /// List a page of events in range, ordered by received time and event.
+    pub async fn list_range_page(
+        &self,
+        start: i64,
+        end: i64,
+        page: &Page,
+    ) -> Result<Vec<(i64, String, String)>> {
         ensure!(self.known, "No such buoy");
 
-        let events = match self.buoy_type {
-            BuoyType::SFY => {
+        let events = match self.known_family()?.table() {
+            Table::Events => {
                 sqlx::query!(
-                    "SELECT event, received, message_type FROM events WHERE dev = $1 AND received >= $2 AND received <= $3 ORDER BY received",
+                    "SELECT event, received, message_type FROM events WHERE dev = $1 AND received >= $2 AND received <= $3 AND (received > $4 OR (received = $4 AND event > $5)) ORDER BY received, event LIMIT $6",
                     self.dev,
                     start,
                     end,
+                    page.after.received,
+                    page.after.event,
+                    page.limit,
                 )
                 .map(|r| (r.received, r.event, r.message_type))
                 .fetch_all(&self.db)
                 .await?
             },
-            BuoyType::OMB => {
+            Table::OmbEvents => {
                 sqlx::query!(
-                    "SELECT event, message_type, received FROM omb_events WHERE dev = $1 AND received >= $2 AND received <= $3 ORDER BY received",
+                    "SELECT event, message_type, received FROM omb_events WHERE dev = $1 AND received >= $2 AND received <= $3 AND (received > $4 OR (received = $4 AND event > $5)) ORDER BY received, event LIMIT $6",
                     self.dev,
                     start,
                     end,
+                    page.after.received,
+                    page.after.omb_event()?,
+                    page.limit,
                 )
                 .map(|r| (r.received, format!("{}-{}", r.event, r.message_type), r.message_type))
                 .fetch_all(&self.db)
                 .await?
             },
-            BuoyType::Unknown => return Err(eyre!("Unknown buoy type"))
         };
 
         Ok(events)
     }
 
     pub async fn get_range(&self, start: i64, end: i64) -> Result<Vec<Event>> {
+        self.get_range_page(start, end, &Page::all()).await
+    }
+
+    /// Get a page of events in range, ordered by received time and event.
+    pub async fn get_range_page(&self, start: i64, end: i64, page: &Page) -> Result<Vec<Event>> {
         ensure!(self.known, "No such buoy");
 
-        let events = match self.buoy_type {
-            BuoyType::SFY => {
+        let events = match self.known_family()?.table() {
+            Table::Events => {
                 sqlx::query_as!(
                     Event,
-                    "SELECT event, received, data FROM events WHERE dev = $1 AND received >= $2 AND received <= $3 ORDER BY received",
+                    "SELECT event, received, data FROM events WHERE dev = $1 AND received >= $2 AND received <= $3 AND (received > $4 OR (received = $4 AND event > $5)) ORDER BY received, event LIMIT $6",
                     self.dev,
                     start,
                     end,
+                    page.after.received,
+                    page.after.event,
+                    page.limit,
                 )
                 .fetch_all(&self.db)
                 .await?
             },
-            BuoyType::OMB => {
+            Table::OmbEvents => {
                 sqlx::query!(
-                    "SELECT event, message_type, received, data FROM omb_events WHERE dev = $1 AND received >= $2 AND received <= $3 ORDER BY received",
+                    "SELECT event, message_type, received, data FROM omb_events WHERE dev = $1 AND received >= $2 AND received <= $3 AND (received > $4 OR (received = $4 AND event > $5)) ORDER BY received, event LIMIT $6",
                     self.dev,
                     start,
                     end,
+                    page.after.received,
+                    page.after.omb_event()?,
+                    page.limit,
                 )
                 .map(|r| Event { event: format!("{}-{}", r.event, r.message_type), received: r.received, data: r.data })
                 .fetch_all(&self.db)
                 .await?
             },
-            BuoyType::Unknown => return Err(eyre!("Unknown buoy type"))
         };
 
         Ok(events)
@@ -648,8 +1728,77 @@ mod tests {
         let db = Database::temporary().await;
         let mut b = db.buoy("buoy-01").await.unwrap();
 
-        b.append(None, "entry-0", 0, None, "data-0").await.unwrap();
-        assert!(b.append(None, "entry-0", 0, None, "data-1").await.is_err());
+        assert_eq!(
+            b.append(None, "entry-0", 0, None, "data-0").await.unwrap(),
+            Appended::New
+        );
+        assert_eq!(
+            b.append(None, "entry-0", 0, None, "data-0").await.unwrap(),
+            Appended::Duplicate
+        );
+        assert!(b.conflicts().await.unwrap().is_empty());
+
+        for _ in 0..2 {
+            assert_eq!(
+                b.append(None, "entry-0", 1, None, "data-1").await.unwrap(),
+                Appended::Conflict
+            );
+        }
+
+        let conflicts = b.conflicts().await.unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].event, "entry-0");
+        assert_eq!(conflicts[0].received, 1);
+        assert_eq!(conflicts[0].data, b"data-1");
+
+        let e = b.get("0-entry-0").await.unwrap();
+        assert_eq!(e, b"data-0");
+    }
+
+    #[tokio::test]
+    async fn backfill_fix() {
+        let db = Database::temporary().await;
+        let dev = "OMB-BACKFILL-1";
+        let mut b = db.buoy(dev).await.unwrap();
+
+        for (datetime, lat) in [(1742754100000u64, 33.87), (1742754000000, 33.86)] {
+            let gps = json::json!({
+                "account": "gauteh@met.no",
+                "datetime": datetime,
+                "device": dev,
+                "type": "gps",
+                "body": { "messages": [
+                    { "is_valid": true, "datetime_fix": (datetime / 1000) as f64, "latitude": lat, "longitude": 17.71 }
+                ]},
+            });
+
+            b.append_omb(
+                "gauteh@met.no".into(),
+                datetime,
+                OmbMessageType::Gps,
+                json::to_vec(&gps).unwrap(),
+            )
+            .await
+            .unwrap();
+        }
+
+        // As for events stored before the status was kept at ingest.
+        sqlx::query(
+            "UPDATE buoy_status SET lat = NULL, lon = NULL, fix_time = NULL WHERE dev = $1",
+        )
+        .bind(dev)
+        .execute(&db.db)
+        .await
+        .unwrap();
+
+        let fix = b.backfill_fix().await.unwrap().unwrap();
+        assert_eq!(fix.lat, 33.87);
+
+        let status = db.status(|d, _| d == dev).await.unwrap();
+        assert_eq!(status[0].lat, Some(33.87));
+        assert_eq!(status[0].lon, Some(17.71));
+        assert_eq!(status[0].fix_time, Some(1742754100000));
+        assert_eq!(status[0].last_received, Some(1742754100000));
     }
 
     #[tokio::test]
@@ -662,7 +1811,7 @@ mod tests {
         let mut b = db.buoy("buoy-08").await.unwrap();
         b.append(None, "entry-1", 0, None, "data-1").await.unwrap();
 
-        let devs = db.buoys().await.unwrap();
+        let devs = db.buoys(|_, _| true).await.unwrap();
         let devs: Vec<_> = devs.iter().map(|(dev, _, _, _)| dev).collect();
 
         assert!(devs.iter().any(|e| *e == "buoy-07"));
@@ -748,6 +1897,72 @@ mod tests {
         assert_eq!(data, &Some(b"data-3".to_vec()));
     }
 
+    #[tokio::test]
+    async fn get_range_pages() {
+        let db = Database::temporary().await;
+        let mut b = db.buoy("buoy-pages").await.unwrap();
+        b.append(None, "entry-0", 0, None, "data-0").await.unwrap();
+        b.append(None, "entry-1b", 1, None, "data-1b")
+            .await
+            .unwrap();
+        b.append(None, "entry-1a", 1, None, "data-1a")
+            .await
+            .unwrap();
+        b.append(None, "entry-2", 2, None, "data-2").await.unwrap();
+
+        let p1 = b
+            .get_range_page(0, 10, &Page::new(None, Some(2)))
+            .await
+            .unwrap();
+        assert_eq!(p1.len(), 2);
+        assert_eq!(p1[0].event, "entry-0");
+        assert_eq!(p1[1].event, "entry-1a");
+
+        let after: Cursor = format!("{}-{}", p1[1].received, p1[1].event)
+            .parse()
+            .unwrap();
+        assert_eq!(after, Cursor::after(1, "entry-1a"));
+
+        let p2 = b
+            .get_range_page(0, 10, &Page::new(Some(after), Some(2)))
+            .await
+            .unwrap();
+        assert_eq!(p2.len(), 2);
+        assert_eq!(p2[0].event, "entry-1b");
+        assert_eq!(p2[1].event, "entry-2");
+
+        let l = b
+            .list_range_page(0, 10, &Page::new(Some(Cursor::after(2, "entry-2")), None))
+            .await
+            .unwrap();
+        assert!(l.is_empty());
+    }
+
+    #[tokio::test]
+    async fn omb_range_pages() {
+        let db = Database::temporary().await;
+        let mut b = db.buoy("buoy-pages-omb").await.unwrap();
+        for i in 0..3 {
+            b.append_omb("testacc".into(), i, OmbMessageType::Gps, "data")
+                .await
+                .unwrap();
+        }
+
+        let p1 = b
+            .list_range_page(0, 10, &Page::new(None, Some(2)))
+            .await
+            .unwrap();
+        assert_eq!(p1.len(), 2);
+
+        let after = Cursor::after(p1[1].0, &p1[1].1);
+        let p2 = b
+            .list_range_page(0, 10, &Page::new(Some(after), Some(2)))
+            .await
+            .unwrap();
+        assert_eq!(p2.len(), 1);
+        assert_eq!(p2[0].0, 2);
+    }
+
     #[tokio::test]
     async fn append_last() {
         let db = Database::temporary().await;
@@ -766,9 +1981,15 @@ mod tests {
     async fn append_last_axlb() {
         let db = Database::temporary().await;
         let mut b = db.buoy("buoy-sfy4").await.unwrap();
-        b.append(None, "entry-0-axlb.qo", 0, Some("axlb.qo".into()), "data-axlb")
-            .await
-            .unwrap();
+        b.append(
+            None,
+            "entry-0-axlb.qo",
+            0,
+            Some("axlb.qo".into()),
+            "data-axlb",
+        )
+        .await
+        .unwrap();
         b.append(None, "entry-1-sessi.qo", 0, None, "data-sessi")
             .await
             .unwrap();
@@ -780,12 +2001,24 @@ mod tests {
     async fn append_last_egpsb() {
         let db = Database::temporary().await;
         let mut b = db.buoy("buoy-sfy4b").await.unwrap();
-        b.append(None, "entry-0-axlb.qo", 0, Some("axlb.qo".into()), "data-axlb")
-            .await
-            .unwrap();
-        b.append(None, "entry-1-egpsb.qo", 1, Some("egpsb.qo".into()), "data-egpsb")
-            .await
-            .unwrap();
+        b.append(
+            None,
+            "entry-0-axlb.qo",
+            0,
+            Some("axlb.qo".into()),
+            "data-axlb",
+        )
+        .await
+        .unwrap();
+        b.append(
+            None,
+            "entry-1-egpsb.qo",
+            1,
+            Some("egpsb.qo".into()),
+            "data-egpsb",
+        )
+        .await
+        .unwrap();
 
         assert_eq!(b.last().await.unwrap(), b"data-egpsb");
     }
@@ -794,10 +2027,10 @@ mod tests {
     async fn append_omb_last() {
         let db = Database::temporary().await;
         let mut b = db.buoy("buoy 01-omb").await.unwrap();
-        b.append_omb("testacc".into(), 0, OmbMessageType::GPS, "data-0")
+        b.append_omb("testacc".into(), 0, OmbMessageType::Gps, "data-0")
             .await
             .unwrap();
-        b.append_omb("testacc".into(), 1, OmbMessageType::GPS, "data-1")
+        b.append_omb("testacc".into(), 1, OmbMessageType::Gps, "data-1")
             .await
             .unwrap();
 
@@ -0,0 +1,181 @@
+//! Acceleration packages (`axl.qo` and `axlb.qo`).
+
+use eyre::Result;
+use serde::Serialize;
+use serde_json as json;
+
+use super::{f16_to_f32, payload, scale_u16_to_f32, u16s};
+
+pub const SENSORS_GRAVITY_STANDARD: f64 = 9.80665;
+
+/// A decoded acceleration package (`AxlPacket` in the firmware).
+#[derive(Debug, Serialize)]
+pub struct AxlPacket {
+    /// Timestamp of sample at `offset` [ms].
+    pub timestamp: i64,
+    pub offset: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_id: Option<u32>,
+    pub storage_version: u32,
+
+    /// Time of position [s].
+    pub position_time: Option<u32>,
+    pub lon: Option<f64>,
+    pub lat: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    /// Frequency of samples [Hz].
+    pub freq: f32,
+
+    /// Accelerometer [g] and gyro range [dps]
+    pub accel_range: f32,
+    pub gyro_range: f32,
+
+    /// Time of each sample [ms].
+    pub time: Vec<f64>,
+
+    /// Acceleration [m/s^2].
+    pub x: Vec<f32>,
+    pub y: Vec<f32>,
+    pub z: Vec<f32>,
+}
+
+impl AxlPacket {
+    /// Decode a package from the stored Notehub event.
+    pub fn decode(data: &[u8], message_type: &str) -> Result<AxlPacket> {
+        let event: json::Value = json::from_slice(data)?;
+        let body = event.get("body").ok_or(eyre!("no body field"))?;
+
+        let get_f64 = |k: &str| body.get(k).and_then(json::Value::as_f64);
+        let get_u64 = |k: &str| body.get(k).and_then(json::Value::as_u64);
+
+        let timestamp = body
+            .get("timestamp")
+            .and_then(json::Value::as_i64)
+            .unwrap_or(0);
+        let offset = get_u64("offset").unwrap_or(0) as u32;
+        let storage_id = get_u64("storage_id").map(|i| i as u32);
+        let storage_version = get_u64("storage_version").unwrap_or(1) as u32;
+        let freq = get_f64("freq").unwrap_or(208.) as f32;
+
+        // Ranges were added in storage version 6.
+        let accel_range = get_f64("accel_range").unwrap_or(1.) as f32;
+        let gyro_range = get_f64("gyro_range").unwrap_or(125.) as f32;
+
+        ensure!(freq > 0., "invalid frequency: {}", freq);
+        ensure!(accel_range > 0., "invalid accel_range: {}", accel_range);
+
+        let payload = payload(&event, message_type)?;
+        let samples = u16s(&payload)?;
+        ensure!(
+            samples.len().is_multiple_of(3),
+            "length of payload: {}, does not match expected number of values",
+            payload.len()
+        );
+
+        let samples: Vec<f32> = if storage_version < 5 {
+            samples.into_iter().map(f16_to_f32).collect()
+        } else {
+            let accel_max = (2. * SENSORS_GRAVITY_STANDARD * accel_range as f64) as f32;
+            samples
+                .into_iter()
+                .map(|u| scale_u16_to_f32(accel_max, u))
+                .collect()
+        };
+
+        let n = samples.len() / 3;
+        let x = samples.iter().step_by(3).copied().collect();
+        let y = samples.iter().skip(1).step_by(3).copied().collect();
+        let mut z: Vec<f32> = samples.iter().skip(2).step_by(3).copied().collect();
+
+        // From storage version 5 the firmware subtracts g from z before encoding.
+        if storage_version >= 5 {
+            for z in &mut z {
+                *z += SENSORS_GRAVITY_STANDARD as f32;
+            }
+        }
+
+        let dt = 1000. / freq as f64;
+        let time = (0..n)
+            .map(|i| timestamp as f64 + (i as f64 - offset as f64) * dt)
+            .collect();
+
+        Ok(AxlPacket {
+            timestamp,
+            offset,
+            storage_id,
+            storage_version,
+            position_time: get_u64("position_time").map(|t| t as u32),
+            lon: get_f64("lon"),
+            lat: get_f64("lat"),
+            temperature: get_f64("temperature").map(|t| t as f32),
+            freq,
+            accel_range,
+            gyro_range,
+            time,
+            x,
+            y,
+            z,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_axlb() {
+        let event = std::fs::read("tests/events/sfy4-axlb.qo.json").unwrap();
+        let p = AxlPacket::decode(&event, "axlb.qo").unwrap();
+
+        assert_eq!(p.time.len(), 1024);
+        assert_eq!(p.x.len(), 1024);
+        assert_eq!(p.storage_version, 6);
+        assert_eq!(p.accel_range, 4.);
+        assert_eq!(p.offset, 13);
+
+        assert!((p.x[0] - -0.0059856).abs() < 1e-5);
+        assert!((p.y[0] - 0.0107741).abs() < 1e-5);
+        assert!((p.z[0] - 9.853338).abs() < 1e-5);
+
+        // The sample at `offset` is at `timestamp`.
+        assert_eq!(p.time[13], 1779178986910.);
+        assert!((p.time[14] - p.time[13] - 1000. / 52.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn invalid_axlb() {
+        let event = std::fs::read("tests/events/sfy4-axlb.qo.json").unwrap();
+        let mut event: json::Value = json::from_slice(&event).unwrap();
+        event["body"]["accel_range"] = 0.into();
+        let data = json::to_vec(&event).unwrap();
+        assert!(AxlPacket::decode(&data, "axlb.qo").is_err());
+
+        // Truncated by a byte.
+        let event = std::fs::read("tests/events/sfy4-axlb.qo.json").unwrap();
+        let mut event: json::Value = json::from_slice(&event).unwrap();
+        let payload = base64::decode(event["payload"].as_str().unwrap()).unwrap();
+        event["payload"] = base64::encode(&payload[..payload.len() - 1]).into();
+        let data = json::to_vec(&event).unwrap();
+        assert!(AxlPacket::decode(&data, "axlb.qo").is_err());
+    }
+
+    #[test]
+    fn decode_axl_f16() {
+        let event = std::fs::read(
+            "tests/events/1647870799330-1876870b-4708-4366-8db5-68f872cc4e6d_axl.qo.json",
+        )
+        .unwrap();
+        let p = AxlPacket::decode(&event, "axl.qo").unwrap();
+
+        assert_eq!(p.time.len(), 1024);
+        assert_eq!(p.storage_version, 1);
+        assert_eq!(p.time[0], 1647870457710.);
+
+        let mean = p.z.iter().sum::<f32>() / p.time.len() as f32;
+        assert!((mean - 9.7689).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,200 @@
+//! GNSS packages (`egps.qo` and `egpsb.qo`).
+
+use eyre::Result;
+use serde::Serialize;
+use serde_json as json;
+
+use super::{payload, scale_u16_to_f32, u16s};
+
+/// Default velocity range for packages that do not specify it [mm/s].
+pub const VEL_RANGE: f32 = 200.0 * 1.0e6 / 60.0 / 60.0;
+
+/// A decoded GNSS package (`GpsPacket` in the firmware).
+#[derive(Debug, Serialize)]
+pub struct EgpsPacket {
+    /// Timestamp of first sample [ms].
+    pub timestamp: i64,
+    pub version: u32,
+
+    /// Frequency of samples [Hz].
+    pub freq: f32,
+
+    /// Reference position [deg] and height above mean sea level [m].
+    pub ref_lon: f64,
+    pub ref_lat: f64,
+    pub ref_msl: f64,
+
+    /// Scaling ranges: [1e-7 deg], [mm] and [mm/s].
+    pub lonlat_range: f32,
+    pub msl_range: f32,
+    pub vel_range: f32,
+
+    /// Horizontal and vertical accuracy [mm].
+    pub ha_min: f32,
+    pub ha_max: f32,
+    pub ha_mean: f32,
+    pub va_min: f32,
+    pub va_max: f32,
+    pub va_mean: f32,
+
+    /// Histograms of fix type and carrier solution.
+    pub fix: Vec<u32>,
+    pub soln: Vec<u32>,
+
+    /// Number of fill samples (copies of the last real fix) in the package.
+    pub filled: u32,
+
+    /// Time of each sample [ms].
+    pub time: Vec<f64>,
+
+    /// Position [deg] and height above mean sea level [m].
+    pub lon: Vec<f64>,
+    pub lat: Vec<f64>,
+    pub msl: Vec<f64>,
+
+    /// Velocity north, east and down [m/s].
+    pub vn: Vec<f32>,
+    pub ve: Vec<f32>,
+    pub vd: Vec<f32>,
+}
+
+impl EgpsPacket {
+    /// Decode a package from the stored Notehub event.
+    pub fn decode(data: &[u8], message_type: &str) -> Result<EgpsPacket> {
+        let event: json::Value = json::from_slice(data)?;
+        let body = event.get("body").ok_or(eyre!("no body field"))?;
+
+        let get_f64 = |k: &str| body.get(k).and_then(json::Value::as_f64);
+        let get_f32 = |k: &str| get_f64(k).unwrap_or(0.) as f32;
+        let get_hist = |k: &str| -> Vec<u32> {
+            body.get(k)
+                .and_then(json::Value::as_array)
+                .map(|a| a.iter().map(|v| v.as_u64().unwrap_or(0) as u32).collect())
+                .unwrap_or_default()
+        };
+
+        let timestamp = body
+            .get("timestamp")
+            .and_then(json::Value::as_i64)
+            .ok_or(eyre!("no timestamp field"))?;
+        let version = body
+            .get("version")
+            .and_then(json::Value::as_u64)
+            .unwrap_or(0) as u32;
+        let freq = get_f64("freq").ok_or(eyre!("no freq field"))? as f32;
+        ensure!(freq > 0., "invalid frequency: {}", freq);
+
+        let lon = get_f64("lon").unwrap_or(0.);
+        let lat = get_f64("lat").unwrap_or(0.);
+        let msl = get_f64("msl").ok_or(eyre!("no msl field"))?;
+
+        let lonlat_range = get_f64("lonlat_range").ok_or(eyre!("no lonlat_range field"))? as f32;
+        let msl_range = get_f64("msl_range").ok_or(eyre!("no msl_range field"))? as f32;
+        let vel_range = get_f64("vel_range").map(|v| v as f32).unwrap_or(VEL_RANGE);
+
+        for (name, range) in [
+            ("lonlat_range", lonlat_range),
+            ("msl_range", msl_range),
+            ("vel_range", vel_range),
+        ] {
+            ensure!(range > 0., "invalid {}: {}", name, range);
+        }
+
+        let payload = payload(&event, message_type)?;
+        let samples = u16s(&payload)?;
+        ensure!(
+            samples.len().is_multiple_of(6),
+            "length of payload: {}, does not match expected number of values",
+            payload.len()
+        );
+
+        let n = samples.len() / 6;
+        let mut p = EgpsPacket {
+            timestamp,
+            version,
+            freq,
+            ref_lon: lon / 1.0e7,
+            ref_lat: lat / 1.0e7,
+            ref_msl: msl / 1.0e3,
+            lonlat_range,
+            msl_range,
+            vel_range,
+            ha_min: get_f32("ha_min"),
+            ha_max: get_f32("ha_max"),
+            ha_mean: get_f32("ha_mean"),
+            va_min: get_f32("va_min"),
+            va_max: get_f32("va_max"),
+            va_mean: get_f32("va_mean"),
+            fix: get_hist("fix"),
+            soln: get_hist("soln"),
+            filled: get_f64("filled").unwrap_or(0.) as u32,
+            time: Vec::with_capacity(n),
+            lon: Vec::with_capacity(n),
+            lat: Vec::with_capacity(n),
+            msl: Vec::with_capacity(n),
+            vn: Vec::with_capacity(n),
+            ve: Vec::with_capacity(n),
+            vd: Vec::with_capacity(n),
+        };
+
+        // Channel order: lon-delta, lat-delta, msl-delta, vel_n, vel_e, vel_d.
+        let dt = 1000. / freq as f64;
+        for (i, s) in samples.chunks_exact(6).enumerate() {
+            p.time.push(timestamp as f64 + i as f64 * dt);
+            p.lon
+                .push((scale_u16_to_f32(lonlat_range, s[0]) as f64 + lon) / 1.0e7);
+            p.lat
+                .push((scale_u16_to_f32(lonlat_range, s[1]) as f64 + lat) / 1.0e7);
+            p.msl
+                .push((scale_u16_to_f32(msl_range, s[2]) as f64 + msl) / 1.0e3);
+            p.vn.push(scale_u16_to_f32(vel_range, s[3]) / 1.0e3);
+            p.ve.push(scale_u16_to_f32(vel_range, s[4]) / 1.0e3);
+            p.vd.push(scale_u16_to_f32(vel_range, s[5]) / 1.0e3);
+        }
+
+        Ok(p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_egpsb() {
+        let event = std::fs::read("tests/events/sfy4-egpsb.qo.json").unwrap();
+        let p = EgpsPacket::decode(&event, "egpsb.qo").unwrap();
+
+        assert_eq!(p.time.len(), 256);
+        assert_eq!(p.version, 4);
+        assert_eq!(p.filled, 7);
+        assert_eq!(p.fix, [0, 0, 0, 256, 0, 0, 0, 0]);
+        assert_eq!(p.soln, [256, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(p.ha_mean, 114.57422);
+
+        assert!((p.lon[0] - 5.28167855).abs() < 1e-7);
+        assert!((p.lat[0] - 60.32346795).abs() < 1e-7);
+        assert!((p.msl[0] - 2.33966).abs() < 1e-4);
+        assert!((p.vn[0] - 0.0042386).abs() < 1e-5);
+        assert!((p.ve[0] - 0.0076295).abs() < 1e-5);
+        assert!((p.vd[0] - -0.0211931).abs() < 1e-5);
+
+        assert!((p.lat[255] - 60.32346373).abs() < 1e-7);
+        assert!((p.msl[255] - 2.42023).abs() < 1e-4);
+
+        assert_eq!(p.time[0], 1779178945405.);
+        assert!((p.time[1] - p.time[0] - 1000. / 14.084507).abs() < 1e-3);
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        let event = std::fs::read("tests/events/sfy4-egpsb.qo.json").unwrap();
+
+        for range in ["lonlat_range", "msl_range", "vel_range"] {
+            let mut event: json::Value = json::from_slice(&event).unwrap();
+            event["body"][range] = (-1).into();
+            let data = json::to_vec(&event).unwrap();
+            assert!(EgpsPacket::decode(&data, "egpsb.qo").is_err(), "{}", range);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Decoding of the payloads sent by the SFY buoys and the OpenMetBuoy.
+//!
+//! The scaling functions mirror the ones in `waves::wire` in the firmware, and must be kept in
+//! sync with them.
+
+use eyre::Result;
+use serde::Serialize;
+use serde_json as json;
+
+use crate::database::TypedEvent;
+
+pub mod axl;
+pub mod egps;
+pub mod omb;
+pub mod spec;
+
+/// A decoded packet together with the event it was received in.
+#[derive(Debug, Serialize)]
+pub struct Decoded<T> {
+    pub received: i64,
+    pub event: String,
+
+    #[serde(flatten)]
+    pub packet: T,
+}
+
+/// Decode the payload of each event, skipping (and logging) events that fail to decode.
+pub fn decode_events<T, F>(events: Vec<TypedEvent>, decode: F) -> Vec<Decoded<T>>
+where
+    F: Fn(&[u8], &str) -> Result<T>,
+{
+    events
+        .into_iter()
+        .filter_map(|e| {
+            let data = e.data?;
+            match decode(&data, &e.message_type) {
+                Ok(packet) => Some(Decoded {
+                    received: e.received,
+                    event: e.event,
+                    packet,
+                }),
+                Err(err) => {
+                    warn!("failed to decode {}: {:?}", e.event, err);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Decode a single event of a known message type to JSON, `None` if the message type has no
+/// decoder. Spectra are assumed to be from the standard firmware build.
+pub fn decode_value(data: &[u8], message_type: &str) -> Option<Result<json::Value>> {
+    let value = match message_type {
+        "axl.qo" | "axlb.qo" => axl::AxlPacket::decode(data, message_type).map(json::to_value),
+        "egps.qo" | "egpsb.qo" => egps::EgpsPacket::decode(data, message_type).map(json::to_value),
+        "spec.qo" => spec::SpecPacket::decode(
+            data,
+            message_type,
+            spec::OUTPUT_FREQ,
+            spec::nseg(spec::OUTPUT_FREQ),
+        )
+        .map(json::to_value),
+        _ => return None,
+    };
+
+    Some(value.and_then(|v| Ok(v?)))
+}
+
+/// Move an u16 on given -max to max range to its real value.
+pub fn scale_u16_to_f32(max: f32, u: u16) -> f32 {
+    let max = max as f64;
+    let v = u as f64;
+    let v = v * (2. * max) / u16::MAX as f64;
+    let v = v - max;
+    v as f32
+}
+
+/// Convert an IEEE 754 half-precision float to f32. Used by storage versions before 5.
+pub fn f16_to_f32(h: u16) -> f32 {
+    let sign = ((h >> 15) & 0x1) as u32;
+    let exp = ((h >> 10) & 0x1f) as u32;
+    let frac = (h & 0x3ff) as u32;
+
+    let bits = match (exp, frac) {
+        (0, 0) => sign << 31,
+        (0, _) => {
+            // subnormal: normalize.
+            let mut e = 127 - 15 + 1;
+            let mut f = frac;
+            while f & 0x400 == 0 {
+                f <<= 1;
+                e -= 1;
+            }
+            (sign << 31) | (e << 23) | ((f & 0x3ff) << 13)
+        }
+        (0x1f, 0) => (sign << 31) | (0xff << 23),
+        (0x1f, _) => (sign << 31) | (0xff << 23) | (frac << 13),
+        _ => (sign << 31) | ((exp + 127 - 15) << 23) | (frac << 13),
+    };
+
+    f32::from_bits(bits)
+}
+
+/// Get the decoded payload of a Notehub event.
+///
+/// For binary notes (`*b.qo`) `length` is the number of raw bytes and the full payload is
+/// decoded. For regular notes `length` is the length of the base64 string, and the payload may be
+/// padded beyond it.
+pub fn payload(event: &json::Value, message_type: &str) -> Result<Vec<u8>> {
+    let payload = event
+        .get("payload")
+        .and_then(json::Value::as_str)
+        .ok_or(eyre!("no payload field"))?;
+
+    let payload = if message_type.ends_with("b.qo") {
+        payload
+    } else {
+        let length = event
+            .get("body")
+            .and_then(|b| b.get("length"))
+            .and_then(json::Value::as_u64)
+            .map(|l| l as usize)
+            .unwrap_or(payload.len());
+
+        payload.get(..length).unwrap_or(payload)
+    };
+
+    Ok(base64::decode(payload)?)
+}
+
+/// Interpret bytes as little-endian u16's, fails on an odd number of bytes (a truncated
+/// payload).
+pub fn u16s(bytes: &[u8]) -> Result<Vec<u16>> {
+    ensure!(
+        bytes.len().is_multiple_of(2),
+        "odd length of payload: {}",
+        bytes.len()
+    );
+
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_limits() {
+        assert_eq!(scale_u16_to_f32(10., u16::MAX), 10.);
+        assert!((scale_u16_to_f32(10., u16::MAX / 2) - 0.).abs() < 0.001);
+        assert_eq!(scale_u16_to_f32(10., 0), -10.);
+    }
+
+    #[test]
+    fn truncated_u16s() {
+        assert_eq!(u16s(&[1, 0, 0, 1]).unwrap(), [1, 256]);
+        assert!(u16s(&[1, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert!(f16_to_f32(0x7c00).is_infinite());
+        assert!(f16_to_f32(0x7e00).is_nan());
+    }
+}
@@ -0,0 +1,454 @@
+//! Iridium SBD messages of the OpenMetBuoy (OMB), as sent by the OpenMetBuoy firmware.
+//!
+//! All values are little-endian. A message starts with a byte giving its kind, and ends with `E`:
+//!
+//! * `G` (GPS): fixes of `u32` time [s], `i32` latitude and `i32` longitude [1e-7 deg].
+//! * `Y` (wave spectrum): `u32` time [s], `u32` spectrum number, `f32` Hs [m], `f32` zero-crossing
+//!   and `f32` crest frequency [Hz], `f32` maximum of the spectrum and the acceleration spectrum
+//!   as [`SPECTRUM_BINS`] `u16`s scaled to the maximum. Padding before `E` is ignored.
+//! * `T` (thermistors): packets of `u32` time [s], `i8` mean pitch and `i8` mean roll [deg], `u8`
+//!   number of thermistors and their `i16` temperatures [1e-2 °C].
+//!
+//! Stored OMB events keep the raw message in `payload` (hex), and the decoded messages in
+//! `body.messages`.
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use serde_json as json;
+
+use super::spec::SpecPacket;
+use crate::database::OmbMessageType;
+use crate::waves::{welchint, Stats};
+
+/// Number of frequency bins in a wave spectrum.
+pub const SPECTRUM_BINS: usize = 55;
+
+/// First frequency bin of the wave spectrum.
+pub const SPECTRUM_FIRST_BIN: usize = 9;
+
+/// Sample frequency [Hz] and segment length of the wave spectrum.
+pub const SAMPLE_FREQ: f32 = 10.;
+pub const SEGMENT_LENGTH: usize = 2048;
+
+/// Frequency resolution of the wave spectrum [Hz].
+pub const FREQUENCY_RESOLUTION: f64 = SAMPLE_FREQ as f64 / SEGMENT_LENGTH as f64;
+
+/// The acceleration spectrum is scaled to this value.
+const SPECTRUM_SCALE: f64 = 65000.;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct GpsFix {
+    pub datetime_fix: f64,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub is_valid: bool,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Spectrum {
+    pub datetime_fix: f64,
+    pub spectrum_number: u32,
+    pub Hs: f64,
+    pub Tz: f64,
+    pub Tc: f64,
+    pub _array_max_value: f64,
+    pub _array_uint16: Vec<u16>,
+    pub frequency_resolution: f64,
+    pub list_frequencies: Vec<f64>,
+    pub list_acceleration_energies: Vec<f64>,
+    pub list_elevation_energies: Vec<f64>,
+    pub is_valid: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ThermistorPacket {
+    pub datetime_packet: f64,
+    pub mean_pitch: f64,
+    pub mean_roll: f64,
+
+    /// Temperatures [°C].
+    pub temperatures: Vec<f64>,
+}
+
+/// A decoded message, serialized as the `messages` of the body of OMB events.
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    Gps(Vec<GpsFix>),
+    Spectrum(Spectrum),
+    Thermistors(Vec<ThermistorPacket>),
+}
+
+impl Message {
+    pub fn message_type(&self) -> OmbMessageType {
+        match self {
+            Message::Gps(_) => OmbMessageType::Gps,
+            Message::Spectrum(_) => OmbMessageType::Imu,
+            Message::Thermistors(_) => OmbMessageType::Thermistor,
+        }
+    }
+
+    pub fn messages(&self) -> Result<serde_json::Value> {
+        Ok(match self {
+            Message::Gps(fixes) => serde_json::to_value(fixes)?,
+            Message::Spectrum(spectrum) => serde_json::to_value([spectrum])?,
+            Message::Thermistors(packets) => serde_json::to_value(packets)?,
+        })
+    }
+}
+
+/// Temperature profiles of a thermistor message.
+#[derive(Debug, Serialize)]
+pub struct Thermistors {
+    pub profiles: Vec<ThermistorPacket>,
+}
+
+/// The message of a stored OMB event.
+enum Stored {
+    /// Decoded from the raw message.
+    Raw(Message),
+
+    /// The messages decoded before the event was posted.
+    Posted(json::Value),
+}
+
+impl Stored {
+    fn decode(data: &[u8]) -> Result<Stored> {
+        let mut event: json::Value = json::from_slice(data)?;
+
+        match event.get("payload").and_then(json::Value::as_str) {
+            Some(payload) => Ok(Stored::Raw(decode(&hex::decode(payload)?)?)),
+            None => {
+                let messages = event
+                    .pointer_mut("/body/messages")
+                    .ok_or(eyre!("no messages"))?
+                    .take();
+                Ok(Stored::Posted(messages))
+            }
+        }
+    }
+}
+
+impl SpecPacket {
+    /// Decode the wave spectrum of a stored OMB `imu` event.
+    pub fn decode_omb(data: &[u8], _message_type: &str) -> Result<SpecPacket> {
+        let spectrum = match Stored::decode(data)? {
+            Stored::Raw(Message::Spectrum(spectrum)) => spectrum,
+            Stored::Raw(_) => return Err(eyre!("not a wave spectrum")),
+            Stored::Posted(messages) => {
+                let mut spectra: Vec<Spectrum> = json::from_value(messages)?;
+                ensure!(!spectra.is_empty(), "no wave spectrum");
+                spectra.swap_remove(0)
+            }
+        };
+
+        let f: Vec<f32> = spectrum
+            .list_frequencies
+            .iter()
+            .map(|f| *f as f32)
+            .collect();
+        let a: Vec<f32> = spectrum
+            .list_acceleration_energies
+            .iter()
+            .map(|a| *a as f32)
+            .collect();
+        let e = welchint(&f, &a, 2);
+        let stats = Stats::from_spectrum(&f, &e);
+
+        Ok(SpecPacket {
+            timestamp: (spectrum.datetime_fix * 1000.) as i64,
+            max: spectrum._array_max_value as f32,
+            freq: SAMPLE_FREQ,
+            nseg: SEGMENT_LENGTH,
+            df: spectrum.frequency_resolution as f32,
+            f,
+            a,
+            e,
+            stats,
+        })
+    }
+}
+
+impl Thermistors {
+    /// Decode the temperature profiles of a stored OMB `thermistor` event.
+    pub fn decode(data: &[u8], _message_type: &str) -> Result<Thermistors> {
+        let profiles = match Stored::decode(data)? {
+            Stored::Raw(Message::Thermistors(profiles)) => profiles,
+            Stored::Raw(_) => return Err(eyre!("not a thermistor message")),
+            Stored::Posted(messages) => json::from_value(messages)?,
+        };
+
+        Ok(Thermistors { profiles })
+    }
+}
+
+/// Reads little-endian values from the start of a message.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        ensure!(self.0.len() >= N, "message too short");
+        let (v, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(v.try_into()?)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn i8(&mut self) -> Result<i8> {
+        Ok(i8::from_le_bytes(self.take()?))
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    fn f32(&mut self) -> Result<f64> {
+        Ok(f32::from_le_bytes(self.take()?) as f64)
+    }
+}
+
+/// Decode a binary OMB message.
+pub fn decode(data: &[u8]) -> Result<Message> {
+    let (kind, body) = data.split_first().ok_or(eyre!("empty message"))?;
+    let body = body
+        .strip_suffix(b"E")
+        .ok_or(eyre!("message does not end with E"))?;
+
+    match kind {
+        b'G' => decode_gps(body),
+        b'Y' => decode_spectrum(body),
+        b'T' => decode_thermistors(body),
+        _ => Err(eyre!("unknown message kind: {:#04x}", kind)),
+    }
+}
+
+fn decode_gps(body: &[u8]) -> Result<Message> {
+    let chunks = body.chunks_exact(12);
+    ensure!(chunks.remainder().is_empty(), "incomplete GPS fix");
+
+    let mut fixes = Vec::new();
+
+    for fix in chunks {
+        let mut r = Reader(fix);
+        let datetime_fix = r.u32()? as f64;
+        let latitude = r.i32()? as f64 / 1e7;
+        let longitude = r.i32()? as f64 / 1e7;
+
+        fixes.push(GpsFix {
+            datetime_fix,
+            latitude,
+            longitude,
+            is_valid: (-90. ..=90.).contains(&latitude) && (-180. ..=180.).contains(&longitude),
+        });
+    }
+
+    Ok(Message::Gps(fixes))
+}
+
+fn decode_spectrum(body: &[u8]) -> Result<Message> {
+    let mut r = Reader(body);
+
+    let datetime_fix = r.u32()? as f64;
+    let spectrum_number = r.u32()?;
+    let hs = r.f32()?;
+    let fz = r.f32()?;
+    let fc = r.f32()?;
+    let max = r.f32()?;
+
+    let array = (0..SPECTRUM_BINS)
+        .map(|_| r.u16())
+        .collect::<Result<Vec<_>>>()?;
+
+    let frequencies: Vec<f64> = (0..SPECTRUM_BINS)
+        .map(|i| (SPECTRUM_FIRST_BIN + i) as f64 * FREQUENCY_RESOLUTION)
+        .collect();
+
+    let acceleration: Vec<f64> = array
+        .iter()
+        .map(|a| *a as f64 * max / SPECTRUM_SCALE)
+        .collect();
+
+    let elevation = acceleration
+        .iter()
+        .zip(&frequencies)
+        .map(|(a, f)| a / (2. * std::f64::consts::PI * f).powi(4))
+        .collect();
+
+    Ok(Message::Spectrum(Spectrum {
+        datetime_fix,
+        spectrum_number,
+        Hs: hs,
+        Tz: 1. / fz,
+        Tc: 1. / fc,
+        _array_max_value: max,
+        _array_uint16: array,
+        frequency_resolution: FREQUENCY_RESOLUTION,
+        list_frequencies: frequencies,
+        list_acceleration_energies: acceleration,
+        list_elevation_energies: elevation,
+        is_valid: true,
+    }))
+}
+
+fn decode_thermistors(body: &[u8]) -> Result<Message> {
+    let mut r = Reader(body);
+    let mut packets = Vec::new();
+
+    while !r.0.is_empty() {
+        let datetime_packet = r.u32()? as f64;
+        let mean_pitch = r.i8()? as f64;
+        let mean_roll = r.i8()? as f64;
+        let n = r.u8()?;
+
+        let temperatures = (0..n)
+            .map(|_| Ok(r.i16()? as f64 / 100.))
+            .collect::<Result<Vec<_>>>()?;
+
+        packets.push(ThermistorPacket {
+            datetime_packet,
+            mean_pitch,
+            mean_roll,
+            temperatures,
+        });
+    }
+
+    Ok(Message::Thermistors(packets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::thermistor_message;
+
+    #[test]
+    fn spectrum() {
+        let event: json::Value =
+            json::from_slice(&std::fs::read("tests/events/02-omb-imu.json").unwrap()).unwrap();
+        let payload = hex::decode(event["payload"].as_str().unwrap()).unwrap();
+        let expected = &event["body"]["messages"][0];
+
+        let m = decode(&payload).unwrap();
+        assert_eq!(m.message_type(), OmbMessageType::Imu);
+
+        let s = match m {
+            Message::Spectrum(s) => s,
+            _ => panic!("not a spectrum"),
+        };
+
+        assert_eq!(s.datetime_fix, expected["datetime_fix"].as_f64().unwrap());
+        assert_eq!(s.spectrum_number, 901);
+        assert!((s.Hs - expected["Hs"].as_f64().unwrap()).abs() < 1e-6);
+        assert!((s.Tz - expected["Tz"].as_f64().unwrap()).abs() < 1e-6);
+        assert!((s.Tc - expected["Tc"].as_f64().unwrap()).abs() < 1e-6);
+        assert_eq!(
+            json::to_value(&s._array_uint16).unwrap(),
+            expected["_array_uint16"]
+        );
+
+        for (k, v) in [
+            ("list_frequencies", &s.list_frequencies),
+            ("list_acceleration_energies", &s.list_acceleration_energies),
+            ("list_elevation_energies", &s.list_elevation_energies),
+        ] {
+            let e = expected[k].as_array().unwrap();
+            assert_eq!(e.len(), v.len());
+            for (e, v) in e.iter().zip(v) {
+                let e = e.as_f64().unwrap();
+                assert!((e - v).abs() <= 1e-6 * e.abs(), "{}: {} != {}", k, e, v);
+            }
+        }
+    }
+
+    #[test]
+    fn gps() {
+        let mut data = vec![b'G'];
+        for (t, lat, lon) in [
+            (1654003274u32, 588867932i32, 57136341i32),
+            (1654003874, -1, 2),
+        ] {
+            data.extend(t.to_le_bytes());
+            data.extend(lat.to_le_bytes());
+            data.extend(lon.to_le_bytes());
+        }
+        data.push(b'E');
+
+        let m = decode(&data).unwrap();
+        assert_eq!(m.message_type(), OmbMessageType::Gps);
+        assert_eq!(
+            m,
+            Message::Gps(vec![
+                GpsFix {
+                    datetime_fix: 1654003274.,
+                    latitude: 58.8867932,
+                    longitude: 5.7136341,
+                    is_valid: true
+                },
+                GpsFix {
+                    datetime_fix: 1654003874.,
+                    latitude: -1e-7,
+                    longitude: 2e-7,
+                    is_valid: true
+                }
+            ])
+        );
+
+        assert!(decode(&data[..20]).is_err());
+    }
+
+    #[test]
+    fn thermistors() {
+        let data = thermistor_message(1654003274, &[1234, -150]);
+
+        let m = decode(&data).unwrap();
+        assert_eq!(m.message_type(), OmbMessageType::Thermistor);
+        assert_eq!(
+            m,
+            Message::Thermistors(vec![ThermistorPacket {
+                datetime_packet: 1654003274.,
+                mean_pitch: -3.,
+                mean_roll: 5.,
+                temperatures: vec![12.34, -1.5],
+            }])
+        );
+
+        assert!(decode(b"XE").is_err());
+    }
+
+    #[test]
+    fn stored_spectrum() {
+        let event = std::fs::read("tests/events/02-omb-imu.json").unwrap();
+        let p = SpecPacket::decode_omb(&event, "imu").unwrap();
+
+        // Without the raw message the posted messages are used.
+        let mut posted: json::Value = json::from_slice(&event).unwrap();
+        posted.as_object_mut().unwrap().remove("payload");
+        let q = SpecPacket::decode_omb(&json::to_vec(&posted).unwrap(), "imu").unwrap();
+
+        for p in [p, q] {
+            assert_eq!(p.timestamp, 1742754106000);
+            assert_eq!(p.f.len(), SPECTRUM_BINS);
+            assert_eq!(p.nseg, 2048);
+            assert!((p.stats.hm0 - 2.66).abs() < 0.01, "hm0: {}", p.stats.hm0);
+            assert!(
+                (p.stats.tp - 1. / 0.1171875).abs() < 1e-3,
+                "tp: {}",
+                p.stats.tp
+            );
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! Spectrum packages (`spec.qo`) from `waves::welch` in the firmware.
+
+use eyre::Result;
+use serde::Serialize;
+use serde_json as json;
+
+use super::{payload, u16s};
+use crate::waves::{welchint, Stats};
+
+/// Default output frequency of the firmware [Hz].
+pub const OUTPUT_FREQ: f32 = 52.;
+
+/// First and last (exclusive) frequency bin in the package.
+pub const FI0: usize = 2;
+pub const FI1: usize = 79;
+
+/// Segment length used by the firmware for the given output frequency.
+pub fn nseg(freq: f32) -> usize {
+    if freq <= 10. {
+        512
+    } else if freq <= 20. {
+        1024
+    } else {
+        2048
+    }
+}
+
+/// Frequencies of the spectrum bins for segments of `nseg` samples at `freq` [Hz].
+pub fn frequencies(freq: f32, nseg: usize) -> Vec<f32> {
+    let df = freq / nseg as f32;
+    (FI0..FI1).map(|i| i as f32 * df).collect()
+}
+
+/// Move an u16 on the range 0 to max to its real value.
+pub fn scale_u16_to_f32_positive(max: f32, u: u16) -> f32 {
+    (u as f64 * max as f64 / u16::MAX as f64) as f32
+}
+
+/// A decoded spectrum package (`WelchPacket` in the firmware).
+#[derive(Debug, Serialize)]
+pub struct SpecPacket {
+    /// Timestamp of first sample [ms].
+    pub timestamp: i64,
+
+    /// Maximum value of acceleration spectrum.
+    pub max: f32,
+
+    /// Output frequency of samples [Hz] and segment length used for the spectrum.
+    pub freq: f32,
+    pub nseg: usize,
+
+    /// Frequency resolution [Hz].
+    pub df: f32,
+
+    /// Frequencies [Hz].
+    pub f: Vec<f32>,
+
+    /// Acceleration spectrum [m^2/s^4/Hz].
+    pub a: Vec<f32>,
+
+    /// Elevation spectrum [m^2/Hz].
+    pub e: Vec<f32>,
+
+    #[serde(flatten)]
+    pub stats: Stats,
+}
+
+impl SpecPacket {
+    /// Decode a package from the stored Notehub event. The spectrum was computed with segments of
+    /// `nseg` samples at `freq`.
+    pub fn decode(data: &[u8], message_type: &str, freq: f32, nseg: usize) -> Result<SpecPacket> {
+        ensure!(freq > 0., "invalid frequency: {}", freq);
+        ensure!(nseg > 2 * FI1, "invalid segment length: {}", nseg);
+
+        let event: json::Value = json::from_slice(data)?;
+        let body = event.get("body").ok_or(eyre!("no body field"))?;
+
+        let timestamp = body
+            .get("timestamp")
+            .and_then(json::Value::as_i64)
+            .ok_or(eyre!("no timestamp field"))?;
+        let max = body
+            .get("max")
+            .and_then(json::Value::as_f64)
+            .ok_or(eyre!("no max field"))? as f32;
+        ensure!(max >= 0., "invalid max: {}", max);
+
+        let payload = payload(&event, message_type)?;
+        let a: Vec<f32> = u16s(&payload)?
+            .into_iter()
+            .map(|u| scale_u16_to_f32_positive(max, u))
+            .collect();
+        ensure!(
+            a.len() == FI1 - FI0,
+            "length of payload: {}, does not match expected number of values",
+            payload.len()
+        );
+
+        let df = freq / nseg as f32;
+        let f = frequencies(freq, nseg);
+        let e = welchint(&f, &a, 2);
+        let stats = Stats::from_spectrum(&f, &e);
+
+        Ok(SpecPacket {
+            timestamp,
+            max,
+            freq,
+            nseg,
+            df,
+            f,
+            a,
+            e,
+            stats,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::spec_event;
+
+    #[test]
+    fn decode_spec() {
+        let event = json::to_vec(&spec_event(1779178945405, 20)).unwrap();
+        let p = SpecPacket::decode(&event, "spec.qo", 52., 2048).unwrap();
+
+        assert_eq!(p.timestamp, 1779178945405);
+        assert_eq!(p.f.len(), 77);
+        assert_eq!(p.a.len(), 77);
+        assert_eq!(p.df, 52. / 2048.);
+        assert_eq!(p.f[0], 2. * 52. / 2048.);
+        assert_eq!(p.a[18], 0.5);
+
+        let fp = 20. * 52. / 2048.;
+        assert!((p.stats.tp - 1. / fp).abs() < 1e-3);
+        assert!(p.stats.hm0 > 0.);
+        assert!(p.stats.tm02 > 0.);
+
+        assert!(SpecPacket::decode(&event, "spec.qo", 52., 100).is_err());
+    }
+
+    #[test]
+    fn segment_length() {
+        assert_eq!(nseg(52.), 2048);
+        assert_eq!(nseg(20.), 1024);
+        assert_eq!(nseg(10.), 512);
+    }
+}
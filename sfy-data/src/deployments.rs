@@ -0,0 +1,406 @@
+//! Deployments of buoys.
+//!
+//! The same buoy (hardware) is deployed many times, a deployment records where, when and how it
+//! was deployed. Range and track end-points and the export command accept a deployment instead
+//! of a time range.
+
+use sanitize_filename::sanitize;
+use serde::{Deserialize, Serialize};
+use warp::{reject, Filter};
+
+use crate::auth::Scope;
+use crate::buoys::{check_read_token, with_state};
+use crate::tokens::check_admin_token;
+use crate::State;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Deployment {
+    /// Assigned when the deployment is added.
+    #[serde(default)]
+    pub id: i32,
+
+    /// Buoy (dev).
+    pub dev: String,
+
+    /// Start and end of deployment [ms since epoch], ongoing deployments have no end.
+    pub start: i64,
+    pub end: Option<i64>,
+
+    /// Deployment site and position [deg].
+    pub site: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+
+    /// Notes on the hull and mooring.
+    pub hull: Option<String>,
+    pub mooring: Option<String>,
+    pub notes: Option<String>,
+
+    /// Firmware features enabled for the deployment.
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl Deployment {
+    /// Time range of deployment [ms since epoch].
+    pub fn range(&self) -> (i64, i64) {
+        (self.start, self.end.unwrap_or(i64::MAX))
+    }
+
+    fn validate(&mut self) -> eyre::Result<()> {
+        self.dev = sanitize(&self.dev);
+        ensure!(!self.dev.is_empty(), "no buoy");
+
+        if let Some(end) = self.end {
+            ensure!(end > self.start, "deployment ends before it starts");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeploymentsQuery {
+    /// Only deployments of buoy (dev).
+    pub buoy: Option<String>,
+}
+
+pub fn filters(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    list(state.clone())
+        .or(get(state.clone()))
+        .or(create(state.clone()))
+        .or(update(state.clone()))
+        .or(remove(state))
+}
+
+pub fn list(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("deployments")
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<DeploymentsQuery>())
+        .and(with_state(state))
+        .and_then(handlers::list)
+}
+
+pub fn get(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("deployments" / i32)
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(with_state(state))
+        .and_then(handlers::get)
+}
+
+pub fn create(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("deployments")
+        .and(warp::post())
+        .and(check_admin_token(state.clone()))
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::json())
+        .and(with_state(state))
+        .and_then(handlers::create)
+}
+
+pub fn update(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("deployments" / i32)
+        .and(warp::put())
+        .and(check_admin_token(state.clone()))
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::json())
+        .and(with_state(state))
+        .and_then(handlers::update)
+}
+
+pub fn remove(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("deployments" / i32)
+        .and(warp::delete())
+        .and(check_admin_token(state.clone()))
+        .and(with_state(state))
+        .and_then(handlers::remove)
+}
+
+#[derive(Debug)]
+pub enum DeploymentErrors {
+    Database,
+    Invalid,
+}
+
+impl reject::Reject for DeploymentErrors {}
+
+/// Get deployment, if it is permitted by `scope`.
+pub async fn deployment(
+    state: &State,
+    scope: &Scope,
+    id: i32,
+) -> Result<Deployment, warp::Rejection> {
+    let deployment = state
+        .db
+        .deployment(id)
+        .await
+        .map_err(|e| {
+            error!("failed to get deployment: {:?}", e);
+            reject::custom(DeploymentErrors::Database)
+        })?
+        .ok_or_else(reject::not_found)?;
+
+    let buoy = state
+        .db
+        .buoy(&deployment.dev)
+        .await
+        .map_err(|_| reject::custom(DeploymentErrors::Database))?;
+
+    if scope.permits(&buoy) {
+        Ok(deployment)
+    } else {
+        Err(reject::not_found())
+    }
+}
+
+pub mod handlers {
+    use super::*;
+
+    pub async fn list(
+        scope: Scope,
+        query: DeploymentsQuery,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let deployments = state
+            .db
+            .deployments(query.buoy.map(sanitize).as_deref())
+            .await
+            .map_err(|e| {
+                error!("failed to list deployments: {:?}", e);
+                reject::custom(DeploymentErrors::Database)
+            })?;
+
+        let mut permitted = Vec::with_capacity(deployments.len());
+
+        for d in deployments {
+            let buoy = state
+                .db
+                .buoy(&d.dev)
+                .await
+                .map_err(|_| reject::custom(DeploymentErrors::Database))?;
+
+            if scope.permits(&buoy) {
+                permitted.push(d);
+            }
+        }
+
+        Ok(warp::reply::json(&permitted))
+    }
+
+    pub async fn get(
+        id: i32,
+        scope: Scope,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let deployment = deployment(&state, &scope, id).await?;
+        Ok(warp::reply::json(&deployment))
+    }
+
+    pub async fn create(
+        mut deployment: Deployment,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        deployment.validate().map_err(|e| {
+            warn!("invalid deployment: {:?}", e);
+            reject::custom(DeploymentErrors::Invalid)
+        })?;
+
+        deployment.id = state.db.add_deployment(&deployment).await.map_err(|e| {
+            error!("failed to add deployment: {:?}", e);
+            reject::custom(DeploymentErrors::Database)
+        })?;
+
+        info!("added deployment {} of {}", deployment.id, deployment.dev);
+
+        Ok(warp::reply::json(&deployment))
+    }
+
+    pub async fn update(
+        id: i32,
+        mut deployment: Deployment,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        deployment.id = id;
+        deployment.validate().map_err(|e| {
+            warn!("invalid deployment: {:?}", e);
+            reject::custom(DeploymentErrors::Invalid)
+        })?;
+
+        let found = state.db.update_deployment(&deployment).await.map_err(|e| {
+            error!("failed to update deployment: {:?}", e);
+            reject::custom(DeploymentErrors::Database)
+        })?;
+
+        if found {
+            Ok(warp::reply::json(&deployment))
+        } else {
+            Err(reject::not_found())
+        }
+    }
+
+    pub async fn remove(id: i32, state: State) -> Result<impl warp::Reply, warp::Rejection> {
+        let found = state.db.remove_deployment(id).await.map_err(|e| {
+            error!("failed to remove deployment: {:?}", e);
+            reject::custom(DeploymentErrors::Database)
+        })?;
+
+        if found {
+            info!("removed deployment {}", id);
+            Ok("removed")
+        } else {
+            Err(reject::not_found())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json as json;
+
+    #[tokio::test]
+    async fn deployments_crud() {
+        use crate::auth::{NewToken, TokenKind};
+
+        let state = crate::test_state().await;
+        let f = filters(state.clone());
+
+        let (_, admin) = NewToken {
+            label: "test deployments".into(),
+            kind: TokenKind::Admin,
+            buoys: vec![],
+            products: vec![],
+            expires: None,
+        }
+        .create(&state.db)
+        .await
+        .unwrap();
+
+        // The write token of the buoys may not change deployments.
+        let res = warp::test::request()
+            .path("/deployments")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .json(&json::json!({
+                "dev": "dev:deployments-crud",
+                "start": 1000,
+            }))
+            .reply(&f)
+            .await;
+        assert!(res.status().is_client_error());
+
+        let res = warp::test::request()
+            .path("/deployments")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", &admin)
+            .json(&json::json!({
+                "dev": "dev:deployments-crud",
+                "start": 1000,
+                "end": 500,
+            }))
+            .reply(&f)
+            .await;
+        assert_ne!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path("/deployments")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", &admin)
+            .json(&json::json!({
+                "dev": "dev:deployments-crud",
+                "start": 1000,
+                "site": "Breivika",
+                "lat": 69.6,
+                "lon": 18.9,
+                "hull": "SFY4 hull, 3D-printed",
+                "mooring": "free drifting",
+                "features": ["raw", "spectra"],
+            }))
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let d: Deployment = json::from_slice(res.body()).unwrap();
+        assert_eq!(d.dev, "devdeployments-crud");
+        assert_eq!(d.end, None);
+        assert_eq!(d.range(), (1000, i64::MAX));
+        let id = d.id;
+
+        let res = warp::test::request()
+            .path(&format!("/deployments/{}", id))
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let d: json::Value = json::from_slice(res.body()).unwrap();
+        assert_eq!(d["site"], "Breivika");
+        assert_eq!(d["features"], json::json!(["raw", "spectra"]));
+
+        let res = warp::test::request()
+            .path(&format!("/deployments/{}", id))
+            .method("PUT")
+            .header("SFY_AUTH_TOKEN", &admin)
+            .json(&json::json!({
+                "dev": "devdeployments-crud",
+                "start": 1000,
+                "end": 2000,
+                "site": "Breivika",
+            }))
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path("/deployments?buoy=devdeployments-crud")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let ds: Vec<Deployment> = json::from_slice(res.body()).unwrap();
+        assert_eq!(ds.len(), 1);
+        assert_eq!(ds[0].end, Some(2000));
+        assert_eq!(ds[0].features.len(), 0);
+
+        let res = warp::test::request()
+            .path(&format!("/deployments/{}", id))
+            .method("DELETE")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+        assert!(res.status().is_client_error());
+
+        let res = warp::test::request()
+            .path(&format!("/deployments/{}", id))
+            .method("DELETE")
+            .header("SFY_AUTH_TOKEN", &admin)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path(&format!("/deployments/{}", id))
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+        assert!(res.status().is_client_error());
+    }
+}
@@ -0,0 +1,750 @@
+//! Export of decoded data for a buoy and time range to CF-1.8 NetCDF-4 files.
+//!
+//! The acceleration, GNSS and spectrum packages are decoded and stitched into continuous time
+//! series, and written to the file a page of events at a time so that the export does not hold
+//! the whole range in memory. Writing the NetCDF file requires the `netcdf` feature (and
+//! `libnetcdf`).
+
+use argh::FromArgs;
+use eyre::Result;
+use std::future::Future;
+use std::path::PathBuf;
+
+use crate::database::{Buoy, Cursor, Database, Page, TypedEvent};
+use crate::decode::{axl::AxlPacket, decode_events, egps::EgpsPacket, spec, Decoded};
+use crate::waves::Stats;
+
+#[derive(FromArgs, Debug)]
+/// Export decoded data for a buoy to a NetCDF file.
+#[argh(subcommand, name = "export")]
+pub struct ExportCmd {
+    /// start of time range (received) [ms since epoch].
+    #[argh(option, default = "0")]
+    from: i64,
+
+    /// end of time range (received) [ms since epoch].
+    #[argh(option, default = "i64::MAX")]
+    to: i64,
+
+    /// output frequency of the buoy, used for the spectrum frequencies [Hz].
+    #[argh(option, default = "spec::OUTPUT_FREQ")]
+    freq: f32,
+
+    /// segment length of the spectra (default: from `freq`).
+    #[argh(option)]
+    nseg: Option<usize>,
+
+    /// export the time range of deployment (overrides `from` and `to`).
+    #[argh(option)]
+    deployment: Option<i32>,
+
+    /// buoy (dev).
+    #[argh(positional)]
+    dev: String,
+
+    /// output file.
+    #[argh(positional)]
+    output: PathBuf,
+}
+
+impl ExportCmd {
+    pub async fn run(&self, db: &Database) -> Result<()> {
+        let nseg = self.nseg.unwrap_or_else(|| spec::nseg(self.freq));
+
+        let (from, to) = match self.deployment {
+            Some(id) => {
+                let deployment = db
+                    .deployment(id)
+                    .await?
+                    .ok_or_else(|| eyre!("no such deployment: {}", id))?;
+                ensure!(
+                    deployment.dev == self.dev,
+                    "deployment {} is of buoy {}, not {}",
+                    id,
+                    deployment.dev,
+                    self.dev
+                );
+                deployment.range()
+            }
+            None => (self.from, self.to),
+        };
+
+        let buoy = db.buoy(&self.dev).await?;
+
+        info!(
+            "exporting {} ({}) to {:?}",
+            buoy.dev(),
+            buoy.name().unwrap_or("unnamed"),
+            self.output
+        );
+
+        let mut writer = Writer::create(&self.output, &buoy, &spec::frequencies(self.freq, nseg))?;
+        let storage_version = export(&buoy, from, to, self.freq, nseg, &mut writer).await?;
+        writer.finish(&storage_version)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AxlSeries {
+    /// [ms since epoch]
+    pub time: Vec<f64>,
+
+    /// [m/s^2]
+    pub x: Vec<f32>,
+    pub y: Vec<f32>,
+    pub z: Vec<f32>,
+}
+
+#[derive(Debug, Default)]
+pub struct GnssSeries {
+    /// [ms since epoch]
+    pub time: Vec<f64>,
+
+    /// [deg]
+    pub lon: Vec<f64>,
+    pub lat: Vec<f64>,
+
+    /// [m]
+    pub msl: Vec<f64>,
+
+    /// [m/s]
+    pub vn: Vec<f32>,
+    pub ve: Vec<f32>,
+    pub vd: Vec<f32>,
+}
+
+#[derive(Debug, Default)]
+pub struct SpecSeries {
+    /// Start of samples [ms since epoch].
+    pub time: Vec<f64>,
+
+    /// Elevation spectra, one row per `time` [m^2/Hz].
+    pub e: Vec<Vec<f32>>,
+
+    pub stats: Vec<Stats>,
+}
+
+/// Receives the stitched series a page at a time, in time order.
+pub trait Sink {
+    fn axl(&mut self, a: AxlSeries) -> Result<()>;
+    fn gnss(&mut self, g: GnssSeries) -> Result<()>;
+    fn spec(&mut self, s: SpecSeries) -> Result<()>;
+}
+
+/// Stitches pages of packets into a series. Packets are sorted by the time of their first sample
+/// within a page, and samples at or before the last accepted sample (duplicate, overlapping or
+/// packages received more than a page late) are skipped.
+struct Stitch {
+    last: f64,
+}
+
+impl Stitch {
+    fn new() -> Stitch {
+        Stitch {
+            last: f64::NEG_INFINITY,
+        }
+    }
+
+    /// Iterate over the samples of `packets` that continue the series.
+    fn page<T>(
+        &mut self,
+        mut packets: Vec<Decoded<T>>,
+        time: impl Fn(&T) -> &[f64],
+        mut push: impl FnMut(&T, usize),
+    ) {
+        packets.sort_by(|a, b| {
+            let a = time(&a.packet).first().copied().unwrap_or(f64::NAN);
+            let b = time(&b.packet).first().copied().unwrap_or(f64::NAN);
+            a.total_cmp(&b)
+        });
+
+        for p in &packets {
+            let t = time(&p.packet);
+            let skipped = t.iter().take_while(|t| **t <= self.last).count();
+
+            if skipped > 0 {
+                debug!("{}: skipping {} overlapping samples", p.event, skipped);
+            }
+
+            for i in skipped..t.len() {
+                push(&p.packet, i);
+            }
+
+            self.last = t.last().copied().unwrap_or(self.last).max(self.last);
+        }
+    }
+}
+
+/// Events are read from the database in pages of this many events, so that the raw events are
+/// not all held in memory.
+const PAGE: i64 = 1000;
+
+/// Read and decode the events of `range` a page at a time, passing each page to `page`.
+async fn decode_paged<T, R, Fut, F>(
+    range: R,
+    decode: F,
+    mut page: impl FnMut(Vec<Decoded<T>>) -> Result<()>,
+) -> Result<()>
+where
+    R: Fn(Page) -> Fut,
+    Fut: Future<Output = Result<Vec<TypedEvent>>>,
+    F: Fn(&[u8], &str) -> Result<T>,
+{
+    let mut after = Cursor::start();
+
+    loop {
+        let events = range(Page {
+            after: after.clone(),
+            limit: PAGE,
+        })
+        .await?;
+
+        let full = events.len() as i64 == PAGE;
+        if let Some(e) = events.last() {
+            after = Cursor::after(e.received, &e.event);
+        }
+
+        page(decode_events(events, &decode))?;
+
+        if !full {
+            return Ok(());
+        }
+    }
+}
+
+/// Decode and stitch the data of `buoy` received in the time range, and pass the series to
+/// `sink`. Returns the storage versions of the acceleration packages.
+pub async fn export(
+    buoy: &Buoy,
+    from: i64,
+    to: i64,
+    freq: f32,
+    nseg: usize,
+    sink: &mut impl Sink,
+) -> Result<Vec<u32>> {
+    let mut storage_version: Vec<u32> = Vec::new();
+
+    let mut stitch = Stitch::new();
+    decode_paged(
+        |page| async move { buoy.axl_range(from, to, &page).await },
+        AxlPacket::decode,
+        |packets| {
+            storage_version.extend(packets.iter().map(|p| p.packet.storage_version));
+            storage_version.sort_unstable();
+            storage_version.dedup();
+
+            let mut a = AxlSeries::default();
+            stitch.page(
+                packets,
+                |p| &p.time,
+                |p, i| {
+                    a.time.push(p.time[i]);
+                    a.x.push(p.x[i]);
+                    a.y.push(p.y[i]);
+                    a.z.push(p.z[i]);
+                },
+            );
+            sink.axl(a)
+        },
+    )
+    .await?;
+
+    let mut stitch = Stitch::new();
+    decode_paged(
+        |page| async move { buoy.egps_range(from, to, &page).await },
+        EgpsPacket::decode,
+        |packets| {
+            let mut g = GnssSeries::default();
+            stitch.page(
+                packets,
+                |p| &p.time,
+                |p, i| {
+                    g.time.push(p.time[i]);
+                    g.lon.push(p.lon[i]);
+                    g.lat.push(p.lat[i]);
+                    g.msl.push(p.msl[i]);
+                    g.vn.push(p.vn[i]);
+                    g.ve.push(p.ve[i]);
+                    g.vd.push(p.vd[i]);
+                },
+            );
+            sink.gnss(g)
+        },
+    )
+    .await?;
+
+    let mut stitch = Stitch::new();
+    decode_paged(
+        |page| async move { buoy.spec_range(from, to, &page).await },
+        |data, message_type| spec::SpecPacket::decode(data, message_type, freq, nseg),
+        |packets| {
+            let packets = packets
+                .into_iter()
+                .map(|p| Decoded {
+                    received: p.received,
+                    event: p.event,
+                    packet: (vec![p.packet.timestamp as f64], p.packet),
+                })
+                .collect();
+
+            let mut s = SpecSeries::default();
+            stitch.page(
+                packets,
+                |(t, _)| t,
+                |(t, p), _| {
+                    s.time.push(t[0]);
+                    s.e.push(p.e.clone());
+                    s.stats.push(p.stats);
+                },
+            );
+            sink.spec(s)
+        },
+    )
+    .await?;
+
+    Ok(storage_version)
+}
+
+/// NetCDF file that the series are appended to.
+#[cfg(not(feature = "netcdf"))]
+pub enum Writer {}
+
+#[cfg(not(feature = "netcdf"))]
+impl Writer {
+    pub fn create(_path: &std::path::Path, _buoy: &Buoy, _f: &[f32]) -> Result<Writer> {
+        Err(eyre!(
+            "sfy-data was built without NetCDF support, enable the `netcdf` feature"
+        ))
+    }
+
+    pub fn finish(self, _storage_version: &[u32]) -> Result<()> {
+        match self {}
+    }
+}
+
+#[cfg(not(feature = "netcdf"))]
+impl Sink for Writer {
+    fn axl(&mut self, _a: AxlSeries) -> Result<()> {
+        match *self {}
+    }
+
+    fn gnss(&mut self, _g: GnssSeries) -> Result<()> {
+        match *self {}
+    }
+
+    fn spec(&mut self, _s: SpecSeries) -> Result<()> {
+        match *self {}
+    }
+}
+
+/// NetCDF file that the series are appended to.
+#[cfg(feature = "netcdf")]
+pub struct Writer {
+    f: netcdf::FileMut,
+
+    /// Samples written along each time dimension.
+    axl: usize,
+    gnss: usize,
+    spec: usize,
+}
+
+#[cfg(feature = "netcdf")]
+impl Writer {
+    /// Create the file and its variables for `buoy`, with spectra at the frequencies `f` [Hz].
+    pub fn create(path: &std::path::Path, buoy: &Buoy, f: &[f32]) -> Result<Writer> {
+        const TIME_UNITS: &str = "milliseconds since 1970-01-01 00:00:00 UTC";
+
+        let mut file = netcdf::create(path)?;
+
+        file.add_attribute("Conventions", "CF-1.8")?;
+        file.add_attribute("title", format!("SFY buoy {}", buoy.dev()))?;
+        file.add_attribute("source", "Small Friendly Buoy (SFY)")?;
+        file.add_attribute("dev", buoy.dev())?;
+        if let Some(name) = buoy.name() {
+            file.add_attribute("buoy_name", name)?;
+        }
+
+        fn time(f: &mut netcdf::FileMut, dim: &str, long_name: &str) -> Result<()> {
+            f.add_unlimited_dimension(dim)?;
+            let mut v = f.add_variable::<f64>(dim, &[dim])?;
+            v.put_attribute("standard_name", "time")?;
+            v.put_attribute("long_name", long_name)?;
+            v.put_attribute("units", TIME_UNITS)?;
+            v.put_attribute("calendar", "standard")?;
+            Ok(())
+        }
+
+        fn var<T: netcdf::NcTypeDescriptor>(
+            f: &mut netcdf::FileMut,
+            name: &str,
+            dims: &[&str],
+            attrs: &[(&str, &str)],
+        ) -> Result<()> {
+            let mut v = f.add_variable::<T>(name, dims)?;
+            for (k, a) in attrs {
+                v.put_attribute(k, *a)?;
+            }
+            Ok(())
+        }
+
+        // Acceleration
+        time(&mut file, "axl_time", "time of acceleration sample")?;
+        for (name, direction) in [("x", "x"), ("y", "y"), ("z", "z (upward)")] {
+            let long_name = format!("acceleration in {} direction", direction);
+            var::<f32>(
+                &mut file,
+                &format!("acc_{}", name),
+                &["axl_time"],
+                &[
+                    ("long_name", long_name.as_str()),
+                    ("units", "m s-2"),
+                    ("coordinates", "axl_time"),
+                ],
+            )?;
+        }
+
+        // GNSS
+        time(&mut file, "gnss_time", "time of gnss sample")?;
+        var::<f64>(
+            &mut file,
+            "lon",
+            &["gnss_time"],
+            &[
+                ("standard_name", "longitude"),
+                ("long_name", "longitude"),
+                ("units", "degrees_east"),
+            ],
+        )?;
+        var::<f64>(
+            &mut file,
+            "lat",
+            &["gnss_time"],
+            &[
+                ("standard_name", "latitude"),
+                ("long_name", "latitude"),
+                ("units", "degrees_north"),
+            ],
+        )?;
+        var::<f64>(
+            &mut file,
+            "msl",
+            &["gnss_time"],
+            &[
+                ("standard_name", "height_above_mean_sea_level"),
+                ("long_name", "height above mean sea level"),
+                ("units", "m"),
+                ("coordinates", "gnss_time lat lon"),
+            ],
+        )?;
+        for (name, standard_name) in [
+            ("vn", "platform_northward_velocity"),
+            ("ve", "platform_eastward_velocity"),
+            ("vd", "platform_downward_velocity"),
+        ] {
+            var::<f32>(
+                &mut file,
+                name,
+                &["gnss_time"],
+                &[
+                    ("long_name", standard_name),
+                    ("units", "m s-1"),
+                    ("coordinates", "gnss_time lat lon"),
+                ],
+            )?;
+        }
+
+        // Spectra
+        time(&mut file, "spec_time", "start of spectrum samples")?;
+        file.add_dimension("frequency", f.len())?;
+        var::<f32>(
+            &mut file,
+            "frequency",
+            &["frequency"],
+            &[
+                ("standard_name", "wave_frequency"),
+                ("long_name", "frequency"),
+                ("units", "Hz"),
+            ],
+        )?;
+        file.variable_mut("frequency")
+            .ok_or_else(|| eyre!("no variable: frequency"))?
+            .put_values(f, ..)?;
+
+        var::<f32>(
+            &mut file,
+            "E",
+            &["spec_time", "frequency"],
+            &[
+                (
+                    "standard_name",
+                    "sea_surface_wave_variance_spectral_density",
+                ),
+                ("long_name", "elevation spectrum"),
+                ("units", "m2 s"),
+            ],
+        )?;
+
+        for (name, standard_name) in [
+            ("hm0", "sea_surface_wave_significant_height"),
+            (
+                "tp",
+                "sea_surface_wave_period_at_variance_spectral_density_maximum",
+            ),
+            (
+                "tm01",
+                "sea_surface_wave_mean_period_from_variance_spectral_density_first_frequency_moment",
+            ),
+            (
+                "tm02",
+                "sea_surface_wave_mean_period_from_variance_spectral_density_second_frequency_moment",
+            ),
+        ] {
+            var::<f64>(
+                &mut file,
+                name,
+                &["spec_time"],
+                &[
+                    ("standard_name", standard_name),
+                    ("units", if name == "hm0" { "m" } else { "s" }),
+                ],
+            )?;
+        }
+
+        Ok(Writer {
+            f: file,
+            axl: 0,
+            gnss: 0,
+            spec: 0,
+        })
+    }
+
+    /// Write `values` to variable `name` from `start` along its time dimension.
+    fn put<T: netcdf::NcTypeDescriptor>(
+        &mut self,
+        name: &str,
+        start: usize,
+        values: &[T],
+    ) -> Result<()> {
+        self.f
+            .variable_mut(name)
+            .ok_or_else(|| eyre!("no variable: {}", name))?
+            .put_values(values, start..start + values.len())?;
+        Ok(())
+    }
+
+    pub fn finish(mut self, storage_version: &[u32]) -> Result<()> {
+        info!(
+            "wrote {} acceleration, {} gnss and {} spectrum samples, storage versions: {:?}",
+            self.axl, self.gnss, self.spec, storage_version
+        );
+
+        self.f
+            .add_attribute("storage_version", storage_version.to_vec())?;
+        self.f.close()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "netcdf")]
+impl Sink for Writer {
+    fn axl(&mut self, a: AxlSeries) -> Result<()> {
+        if a.time.is_empty() {
+            return Ok(());
+        }
+
+        let start = self.axl;
+        self.put("axl_time", start, &a.time)?;
+        self.put("acc_x", start, &a.x)?;
+        self.put("acc_y", start, &a.y)?;
+        self.put("acc_z", start, &a.z)?;
+        self.axl += a.time.len();
+
+        Ok(())
+    }
+
+    fn gnss(&mut self, g: GnssSeries) -> Result<()> {
+        if g.time.is_empty() {
+            return Ok(());
+        }
+
+        let start = self.gnss;
+        self.put("gnss_time", start, &g.time)?;
+        self.put("lon", start, &g.lon)?;
+        self.put("lat", start, &g.lat)?;
+        self.put("msl", start, &g.msl)?;
+        self.put("vn", start, &g.vn)?;
+        self.put("ve", start, &g.ve)?;
+        self.put("vd", start, &g.vd)?;
+        self.gnss += g.time.len();
+
+        Ok(())
+    }
+
+    fn spec(&mut self, s: SpecSeries) -> Result<()> {
+        if s.time.is_empty() {
+            return Ok(());
+        }
+
+        let start = self.spec;
+        let n = s.time.len();
+        let nf = s.e[0].len();
+
+        self.put("spec_time", start, &s.time)?;
+
+        let e: Vec<f32> = s.e.iter().flatten().copied().collect();
+        self.f
+            .variable_mut("E")
+            .ok_or_else(|| eyre!("no variable: E"))?
+            .put_values(&e, [start..start + n, 0..nf])?;
+
+        self.put(
+            "hm0",
+            start,
+            &s.stats.iter().map(|s| s.hm0).collect::<Vec<_>>(),
+        )?;
+        self.put(
+            "tp",
+            start,
+            &s.stats.iter().map(|s| s.tp).collect::<Vec<_>>(),
+        )?;
+        self.put(
+            "tm01",
+            start,
+            &s.stats.iter().map(|s| s.tm01).collect::<Vec<_>>(),
+        )?;
+        self.put(
+            "tm02",
+            start,
+            &s.stats.iter().map(|s| s.tm02).collect::<Vec<_>>(),
+        )?;
+        self.spec += n;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json as json;
+
+    /// Collects the pages written to it.
+    #[derive(Default)]
+    struct Collected {
+        axl: AxlSeries,
+        gnss: GnssSeries,
+        spec: SpecSeries,
+    }
+
+    impl Sink for Collected {
+        fn axl(&mut self, a: AxlSeries) -> Result<()> {
+            self.axl.time.extend(a.time);
+            self.axl.x.extend(a.x);
+            self.axl.y.extend(a.y);
+            self.axl.z.extend(a.z);
+            Ok(())
+        }
+
+        fn gnss(&mut self, g: GnssSeries) -> Result<()> {
+            self.gnss.time.extend(g.time);
+            self.gnss.lon.extend(g.lon);
+            Ok(())
+        }
+
+        fn spec(&mut self, s: SpecSeries) -> Result<()> {
+            self.spec.time.extend(s.time);
+            self.spec.e.extend(s.e);
+            self.spec.stats.extend(s.stats);
+            Ok(())
+        }
+    }
+
+    fn decoded(event: &str, start: f64, n: usize) -> Decoded<AxlPacket> {
+        Decoded {
+            received: 0,
+            event: event.into(),
+            packet: crate::testing::packet(start, n),
+        }
+    }
+
+    #[test]
+    fn stitch_pages() {
+        let mut stitch = Stitch::new();
+        let mut time = Vec::new();
+
+        stitch.page(
+            vec![decoded("b", 1000., 52), decoded("a", 0., 52)],
+            |p| &p.time,
+            |p, i| time.push(p.time[i]),
+        );
+        assert_eq!(time.len(), 104);
+
+        // A duplicate of a package in the previous page, and a package overlapping it.
+        stitch.page(
+            vec![decoded("a", 0., 52), decoded("c", 1500., 52)],
+            |p| &p.time,
+            |p, i| time.push(p.time[i]),
+        );
+        assert_eq!(time.len(), 104 + 26);
+        assert!(time.windows(2).all(|t| t[0] < t[1]));
+    }
+
+    #[tokio::test]
+    async fn collect_stitched() {
+        let state = crate::test_state().await;
+        let db = &state.db;
+        let dev = "dev:export-stitched";
+
+        let mut b = db.buoy(dev).await.unwrap();
+
+        let axlb = std::fs::read("tests/events/sfy4-axlb.qo.json").unwrap();
+        let egpsb = std::fs::read("tests/events/sfy4-egpsb.qo.json").unwrap();
+
+        // The same package twice should only be exported once.
+        for (received, file, data) in [
+            (100, "axlb.qo", &axlb),
+            (101, "axlb.qo", &axlb),
+            (102, "egpsb.qo", &egpsb),
+        ] {
+            b.append(
+                Some("SFY4-01".into()),
+                format!("{}-{}", received, file),
+                received,
+                Some(file.into()),
+                data,
+            )
+            .await
+            .unwrap();
+        }
+
+        for (received, t, peak) in [(200, 1779180166329, 24), (201, 1779178945405, 20)] {
+            let event = json::to_vec(&crate::testing::spec_event(t, peak)).unwrap();
+            b.append(
+                Some("SFY4-01".into()),
+                format!("{}-spec.qo", received),
+                received,
+                Some("spec.qo".into()),
+                &event,
+            )
+            .await
+            .unwrap();
+        }
+
+        let b = db.buoy(dev).await.unwrap();
+        assert_eq!(b.name(), Some("SFY4-01"));
+
+        let mut e = Collected::default();
+        let storage_version = export(&b, 0, 1000, 52., 2048, &mut e).await.unwrap();
+
+        assert_eq!(storage_version, [6]);
+        assert_eq!(e.axl.time.len(), 1024);
+        assert_eq!(e.axl.x.len(), 1024);
+        assert!(e.axl.time.windows(2).all(|t| t[0] < t[1]));
+        assert_eq!(e.gnss.time.len(), 256);
+
+        assert_eq!(e.spec.time, [1779178945405., 1779180166329.]);
+        assert_eq!(e.spec.e.len(), 2);
+        assert_eq!(e.spec.e[0].len(), 77);
+    }
+}
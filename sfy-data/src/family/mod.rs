@@ -0,0 +1,122 @@
+//! Buoy families: the platforms that send events to sfy-data.
+//!
+//! Each family knows how to parse its incoming events, which of its message types carry
+//! positions, how to extract the position fixes from them and how to decode its payloads. The
+//! database stores events of a family in one of the event tables, queries that only depend on the
+//! family (e.g. the last position or the track) use the registry instead of matching on the
+//! family. A new platform is added by implementing [`Family`] and adding it to [`FAMILIES`].
+
+use eyre::Result;
+use serde_json as json;
+
+use crate::database::TrackPoint;
+
+mod omb;
+mod sfy;
+mod spotter;
+
+pub use omb::{Modem, Omb, Sbd};
+pub use sfy::Sfy;
+pub use spotter::Spotter;
+
+/// All known buoy families.
+pub static FAMILIES: &[&dyn Family] = &[&Sfy, &Omb, &Spotter];
+
+/// Look up family by the name stored in the `buoy_type` column.
+pub fn get(name: &str) -> Option<&'static dyn Family> {
+    FAMILIES.iter().copied().find(|f| f.name() == name)
+}
+
+/// Table that events of a family are stored in.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Table {
+    /// `events`: events are named by the platform (e.g. the Notehub event id).
+    Events,
+
+    /// `omb_events`: events are numbered by the database.
+    OmbEvents,
+}
+
+impl Table {
+    pub fn name(self) -> &'static str {
+        match self {
+            Table::Events => "events",
+            Table::OmbEvents => "omb_events",
+        }
+    }
+}
+
+/// An incoming event parsed by its family.
+#[derive(Debug)]
+pub struct Parsed {
+    pub device: String,
+
+    /// Name of event given by the platform, if any.
+    pub event: Option<String>,
+
+    /// Time received [ms].
+    pub received: u64,
+
+    /// Serial number of buoy.
+    pub name: Option<String>,
+
+    /// Notehub file (e.g. `axl.qo`) or message type (e.g. `gps`).
+    pub message_type: Option<String>,
+
+    /// Notehub product or Rockblock account.
+    pub product: Option<String>,
+
+    #[allow(unused)]
+    pub body: json::Value,
+}
+
+pub trait Family: Sync + std::fmt::Debug {
+    /// Name of family, as stored in the `buoy_type` column.
+    fn name(&self) -> &'static str;
+
+    fn table(&self) -> Table;
+
+    /// Message types that carry positions.
+    fn positions(&self) -> &'static [&'static str];
+
+    /// Parse an incoming event.
+    fn parse(&self, body: &[u8]) -> Result<Parsed>;
+
+    /// Position fixes in a stored event of one of the position message types.
+    fn points(&self, message_type: &str, event: &json::Value) -> Vec<TrackPoint>;
+
+    /// Position fixes in a range of events `(message_type, event)`, ordered by received time.
+    fn track(&self, events: &[(String, json::Value)]) -> Vec<TrackPoint> {
+        events
+            .iter()
+            .flat_map(|(message_type, event)| self.points(message_type, event))
+            .collect()
+    }
+
+    /// Decode the payload of an event to JSON, `None` if the message type has no decoder.
+    fn decode(&self, _message_type: &str, _data: &[u8]) -> Option<Result<json::Value>> {
+        None
+    }
+
+    /// Significant wave height [m] in a stored event, `None` if the event carries no waves.
+    fn hs(&self, _message_type: &str, _data: &[u8]) -> Option<f64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry() {
+        assert_eq!(get("sfy").unwrap().name(), "sfy");
+        assert_eq!(get("omb").unwrap().table(), Table::OmbEvents);
+        assert_eq!(get("spotter").unwrap().table(), Table::Events);
+        assert!(get("unknown").is_none());
+
+        for f in FAMILIES {
+            assert!(!f.positions().is_empty());
+        }
+    }
+}
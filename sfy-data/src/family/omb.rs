@@ -0,0 +1,237 @@
+//! The OpenMetBuoy (OMB), sending events through Rockblock.
+
+use chrono::{TimeZone, Utc};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use serde_json as json;
+
+use super::{Family, Parsed, Table};
+use crate::database::{OmbMessageType, TrackPoint};
+use crate::decode::omb;
+use crate::decode::spec::SpecPacket;
+
+#[derive(Debug)]
+pub struct Omb;
+
+impl Family for Omb {
+    fn name(&self) -> &'static str {
+        "omb"
+    }
+
+    fn table(&self) -> Table {
+        Table::OmbEvents
+    }
+
+    fn positions(&self) -> &'static [&'static str] {
+        &["gps"]
+    }
+
+    fn parse(&self, body: &[u8]) -> Result<Parsed> {
+        let body: json::Value = json::from_slice(body)?;
+
+        let device = body
+            .get("device")
+            .and_then(json::Value::as_str)
+            .map(String::from)
+            .ok_or(eyre!("no dev field"))?;
+
+        let account = body
+            .get("account")
+            .and_then(json::Value::as_str)
+            .map(String::from)
+            .ok_or(eyre!("no account field"))?;
+
+        let received = body
+            .get("datetime")
+            .and_then(json::Value::as_f64)
+            .ok_or(eyre!("no datetime field"))?;
+        let received = received.trunc() as u64;
+
+        let message_type = body
+            .get("type")
+            .and_then(json::Value::as_str)
+            .map(String::from)
+            .ok_or(eyre!("no type field"))?;
+
+        Ok(Parsed {
+            device,
+            event: None,
+            received,
+            name: None,
+            message_type: Some(message_type),
+            product: Some(account),
+            body,
+        })
+    }
+
+    fn points(&self, _message_type: &str, event: &json::Value) -> Vec<TrackPoint> {
+        let mut points = Vec::new();
+        let messages = event
+            .get("body")
+            .and_then(|b| b.get("messages"))
+            .and_then(|m| m.as_array());
+        if let Some(messages) = messages {
+            for msg in messages {
+                let valid = msg
+                    .get("is_valid")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
+                if !valid {
+                    continue;
+                }
+                let lat = msg.get("latitude").and_then(|v| v.as_f64());
+                let lon = msg.get("longitude").and_then(|v| v.as_f64());
+                let t = msg.get("datetime_fix").and_then(|v| v.as_f64());
+                if let (Some(lat), Some(lon), Some(t)) = (lat, lon, t) {
+                    points.push(TrackPoint { t, lat, lon });
+                }
+            }
+        }
+        points
+    }
+
+    fn hs(&self, message_type: &str, data: &[u8]) -> Option<f64> {
+        if OmbMessageType::from(message_type) != OmbMessageType::Imu {
+            return None;
+        }
+
+        let packet = SpecPacket::decode_omb(data, message_type).ok()?;
+        Some(packet.stats.hm0)
+    }
+}
+
+/// A message posted by the RockBLOCK web service, with the raw Iridium SBD message.
+#[derive(Debug, Deserialize)]
+pub struct Sbd {
+    pub imei: String,
+    pub momsn: u32,
+
+    /// UTC time of transmission, as `YY-MM-DD HH:MM:SS`.
+    pub transmit_time: String,
+
+    pub iridium_latitude: Option<f64>,
+    pub iridium_longitude: Option<f64>,
+
+    /// Hex encoded message.
+    pub data: String,
+}
+
+/// The Iridium modem of an OpenMetBuoy. SBD messages from `imei` are stored as the buoy
+/// `device`, its name in the OpenMetBuoy service, with the RockBLOCK `account` as product.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Modem {
+    pub imei: String,
+    pub device: String,
+    pub account: Option<String>,
+}
+
+impl Sbd {
+    /// Account of events received from RockBLOCK when the account is not known.
+    pub const ACCOUNT: &'static str = "rockblock";
+
+    /// The message as an OMB event of `device`. The raw message is kept in `payload`, and the
+    /// decoded messages in the body. Messages that cannot be decoded are stored with message type
+    /// `unknown`, so that they can be reprocessed.
+    pub fn event(&self, device: &str, account: &str) -> Result<json::Value> {
+        let transmitted =
+            chrono::NaiveDateTime::parse_from_str(&self.transmit_time, "%y-%m-%d %H:%M:%S")?;
+        let data = hex::decode(&self.data)?;
+
+        let mut body = json::json!({
+            "iridium_pos": {
+                "lat": self.iridium_latitude,
+                "lon": self.iridium_longitude,
+            }
+        });
+
+        let message_type = match omb::decode(&data) {
+            Ok(message) => {
+                body["messages"] = message.messages()?;
+                message.message_type()
+            }
+            Err(e) => {
+                warn!("failed to decode SBD message from {}: {:?}", self.imei, e);
+                body["error"] = e.to_string().into();
+                OmbMessageType::Unknown
+            }
+        };
+
+        Ok(json::json!({
+            "account": account,
+            "datetime": Utc.from_utc_datetime(&transmitted).timestamp_millis(),
+            "device": device,
+            "type": message_type.to_str(),
+            "imei": self.imei,
+            "momsn": self.momsn,
+            "payload": self.data,
+            "body": body,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_gps() {
+        let event = std::fs::read("tests/events/01-omb.json").unwrap();
+        let parsed = Omb.parse(&event).unwrap();
+        println!("{:?}", parsed);
+
+        let event = br##"{"account": "gauteh@met.no", "datetime": 1654003378000.0, "device": "NOFO-OPV-2022-01", "type": "gps", "body": {"iridium_pos": {"lat": 58.92556666666667, "lon": 5.987166666666667}, "messages": [{"datetime_fix": 1654003274.0, "latitude": 58.8867932, "longitude": 5.7136341, "is_valid": true}]}}"##;
+        let parsed = Omb.parse(event).unwrap();
+        assert_eq!(parsed.device, "NOFO-OPV-2022-01");
+        assert_eq!(parsed.product.as_deref(), Some("gauteh@met.no"));
+        assert_eq!(parsed.received, 1654003378000);
+
+        let points = Omb.points("gps", &parsed.body);
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].t, 1654003274.0);
+    }
+
+    #[test]
+    fn sbd_event() {
+        let imu: json::Value =
+            json::from_slice(&std::fs::read("tests/events/02-omb-imu.json").unwrap()).unwrap();
+
+        let sbd = Sbd {
+            imei: "300434065196260".into(),
+            momsn: 12,
+            transmit_time: "25-03-23 18:22:19".into(),
+            iridium_latitude: Some(33.8623),
+            iridium_longitude: Some(17.71),
+            data: imu["payload"].as_str().unwrap().into(),
+        };
+
+        let event = json::to_vec(&sbd.event("OMB-SBD-1", "gauteh@met.no").unwrap()).unwrap();
+        let parsed = Omb.parse(&event).unwrap();
+        assert_eq!(parsed.device, "OMB-SBD-1");
+        assert_eq!(parsed.product.as_deref(), Some("gauteh@met.no"));
+        assert_eq!(parsed.body["imei"], "300434065196260");
+        assert_eq!(parsed.message_type.as_deref(), Some("imu"));
+        assert_eq!(parsed.received, 1742754139000);
+        assert_eq!(parsed.body["payload"], imu["payload"]);
+        assert_eq!(
+            parsed.body["body"]["messages"][0]["spectrum_number"],
+            imu["body"]["messages"][0]["spectrum_number"]
+        );
+
+        let sbd = Sbd {
+            data: "00ff".into(),
+            ..sbd
+        };
+        let parsed = Omb
+            .parse(&json::to_vec(&sbd.event(&sbd.imei, Sbd::ACCOUNT).unwrap()).unwrap())
+            .unwrap();
+        assert_eq!(parsed.message_type.as_deref(), Some("unknown"));
+        assert_eq!(parsed.body["payload"], "00ff");
+    }
+
+    #[test]
+    fn parse_imu() {
+        let event = std::fs::read("tests/events/02-omb-imu.json").unwrap();
+        let parsed = Omb.parse(&event).unwrap();
+        assert_eq!(parsed.message_type.as_deref(), Some("imu"));
+    }
+}
@@ -0,0 +1,178 @@
+//! The SFY buoys, sending events through Notehub.
+
+use eyre::Result;
+use serde_json as json;
+
+use super::{Family, Parsed, Table};
+use crate::database::TrackPoint;
+use crate::decode::spec;
+
+#[derive(Debug)]
+pub struct Sfy;
+
+impl Family for Sfy {
+    fn name(&self) -> &'static str {
+        "sfy"
+    }
+
+    fn table(&self) -> Table {
+        Table::Events
+    }
+
+    fn positions(&self) -> &'static [&'static str] {
+        &["axl.qo", "_track.qo", "axlb.qo", "egpsb.qo"]
+    }
+
+    fn parse(&self, body: &[u8]) -> Result<Parsed> {
+        let body: json::Value = json::from_slice(body)?;
+
+        let event = body
+            .get("event")
+            .and_then(json::Value::as_str)
+            .map(String::from)
+            .ok_or(eyre!("no event field"))?;
+
+        let device = body
+            .get("device")
+            .and_then(json::Value::as_str)
+            .map(String::from)
+            .ok_or(eyre!("no dev field"))?;
+
+        let name = body
+            .get("sn")
+            .and_then(json::Value::as_str)
+            .map(String::from);
+
+        let received = body
+            .get("received")
+            .and_then(json::Value::as_f64)
+            .unwrap_or(0.0);
+        let received = (received * 1000.).trunc() as u64;
+
+        let file = body
+            .get("file")
+            .and_then(json::Value::as_str)
+            .map(String::from);
+
+        let product = body
+            .get("product")
+            .and_then(json::Value::as_str)
+            .map(String::from);
+
+        Ok(Parsed {
+            device,
+            event: Some(event),
+            received,
+            name,
+            message_type: file,
+            product,
+            body,
+        })
+    }
+
+    fn points(&self, message_type: &str, event: &json::Value) -> Vec<TrackPoint> {
+        point(message_type, event).into_iter().collect()
+    }
+
+    fn track(&self, events: &[(String, json::Value)]) -> Vec<TrackPoint> {
+        // sfy3 sends _track.qo on every real GPS fix and also batches positions
+        // in axl.qo (which repeats the same stale fix many times). Mixing both
+        // produces a jagged track, so when _track.qo is present use only those.
+        // sfy4 uses axlb.qo / egpsb.qo and does not emit _track.qo or axl.qo,
+        // so for sfy4 the original logic (accept all types) applies unchanged.
+        let has_track_qo = events.iter().any(|(t, _)| t == "_track.qo");
+
+        events
+            .iter()
+            .filter(|(t, _)| !has_track_qo || t == "_track.qo")
+            .filter_map(|(t, e)| point(t, e))
+            .collect()
+    }
+
+    fn decode(&self, message_type: &str, data: &[u8]) -> Option<Result<json::Value>> {
+        crate::decode::decode_value(data, message_type)
+    }
+
+    fn hs(&self, message_type: &str, data: &[u8]) -> Option<f64> {
+        if message_type != "spec.qo" {
+            return None;
+        }
+
+        let packet = spec::SpecPacket::decode(
+            data,
+            message_type,
+            spec::OUTPUT_FREQ,
+            spec::nseg(spec::OUTPUT_FREQ),
+        )
+        .ok()?;
+        Some(packet.stats.hm0)
+    }
+}
+
+fn point(message_type: &str, data: &json::Value) -> Option<TrackPoint> {
+    let body = data.get("body")?;
+    match message_type {
+        "axl.qo" => {
+            let lat = body.get("lat")?.as_f64()?;
+            let lon = body.get("lon")?.as_f64()?;
+            let t = body.get("timestamp")?.as_f64()? / 1000.0;
+            Some(TrackPoint { t, lat, lon })
+        }
+        "_track.qo" => {
+            let lat = data.get("best_lat")?.as_f64()?;
+            let lon = data.get("best_lon")?.as_f64()?;
+            let t = data.get("best_location_when")?.as_f64()?;
+            Some(TrackPoint { t, lat, lon })
+        }
+        "axlb.qo" => {
+            let lat = body.get("lat")?.as_f64()?;
+            let lon = body.get("lon")?.as_f64()?;
+            let t = body.get("position_time")?.as_f64()?;
+            Some(TrackPoint { t, lat, lon })
+        }
+        "egpsb.qo" => {
+            let lat = body.get("lat")?.as_f64()? / 1e7;
+            let lon = body.get("lon")?.as_f64()? / 1e7;
+            let t = body.get("timestamp")?.as_f64()? / 1000.0;
+            Some(TrackPoint { t, lat, lon })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sensor_db() {
+        let event = std::fs::read("tests/events/sensor.db_01.json").unwrap();
+        let parsed = Sfy.parse(&event).unwrap();
+
+        assert_eq!(
+            parsed.event.as_deref(),
+            Some("9ef2e080-f0b4-4036-8ccc-ec4206553537")
+        );
+        assert_eq!(parsed.device, "dev:864475044203262");
+        assert_eq!(parsed.message_type, Some(String::from("sensor.db")));
+    }
+
+    #[test]
+    fn prefer_track_qo() {
+        let axl = json::json!({ "body": { "lat": 60.0, "lon": 5.0, "timestamp": 1000.0 } });
+        let track = json::json!({ "body": {}, "best_lat": 61.0, "best_lon": 6.0, "best_location_when": 2.0 });
+
+        let events = vec![("axl.qo".to_string(), axl.clone())];
+        let points = Sfy.track(&events);
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].t, 1.0);
+
+        let events = vec![
+            ("axl.qo".to_string(), axl),
+            ("_track.qo".to_string(), track),
+        ];
+        let points = Sfy.track(&events);
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].lat, 61.0);
+    }
+}
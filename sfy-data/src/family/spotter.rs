@@ -0,0 +1,209 @@
+//! Sofar Spotter buoys, posting payloads in the format of the Spotter API (`wave-data`).
+//!
+//! A payload may carry several kinds of records: wave parameters (`waves`), spectra
+//! (`frequencyData`) and positions (`track`). It is stored as one event per kind, using the field
+//! names of the API, and named by the time of the last record in it.
+
+use eyre::Result;
+use serde_json as json;
+
+use super::{Family, Parsed, Table};
+use crate::database::TrackPoint;
+
+/// Kinds of records, these are the message types of the stored events.
+pub const KINDS: &[&str] = &["waves", "frequencyData", "track"];
+
+#[derive(Debug)]
+pub struct Spotter;
+
+impl Spotter {
+    /// Split a payload into one event per kind of records.
+    pub fn split(&self, body: &[u8]) -> Result<Vec<Vec<u8>>> {
+        let mut body: json::Value = json::from_slice(body)?;
+
+        // The API wraps the payload in `data`, webhooks may not.
+        if let Some(data) = body.get_mut("data") {
+            body = data.take();
+        }
+
+        let id = body
+            .get("spotterId")
+            .and_then(json::Value::as_str)
+            .ok_or(eyre!("no spotterId field"))?;
+
+        let mut events = Vec::new();
+
+        for kind in KINDS {
+            let records = match body.get(kind).and_then(json::Value::as_array) {
+                Some(records) if !records.is_empty() => records,
+                _ => continue,
+            };
+
+            let mut event = json::Map::new();
+            event.insert("spotterId".into(), id.into());
+            if let Some(name) = body.get("spotterName") {
+                event.insert("spotterName".into(), name.clone());
+            }
+            event.insert((*kind).into(), records.clone().into());
+
+            events.push(json::to_vec(&event)?);
+        }
+
+        ensure!(!events.is_empty(), "no waves, frequencyData or track");
+
+        Ok(events)
+    }
+}
+
+/// Time of record [ms].
+fn timestamp(record: &json::Value) -> Option<i64> {
+    let t = record.get("timestamp")?.as_str()?;
+    Some(
+        chrono::DateTime::parse_from_rfc3339(t)
+            .ok()?
+            .timestamp_millis(),
+    )
+}
+
+impl Family for Spotter {
+    fn name(&self) -> &'static str {
+        "spotter"
+    }
+
+    fn table(&self) -> Table {
+        Table::Events
+    }
+
+    fn positions(&self) -> &'static [&'static str] {
+        &["waves", "track"]
+    }
+
+    fn parse(&self, body: &[u8]) -> Result<Parsed> {
+        let body: json::Value = json::from_slice(body)?;
+
+        let device = body
+            .get("spotterId")
+            .and_then(json::Value::as_str)
+            .map(String::from)
+            .ok_or(eyre!("no spotterId field"))?;
+
+        let name = body
+            .get("spotterName")
+            .and_then(json::Value::as_str)
+            .map(String::from);
+
+        let kind = KINDS
+            .iter()
+            .find(|k| body.get(k).is_some())
+            .ok_or(eyre!("no waves, frequencyData or track"))?;
+
+        let received = body[kind]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(timestamp)
+            .max()
+            .ok_or(eyre!("no timestamp in {}", kind))?;
+
+        Ok(Parsed {
+            device,
+            event: Some(received.to_string()),
+            received: received as u64,
+            name,
+            message_type: Some(kind.to_string()),
+            product: None,
+            body,
+        })
+    }
+
+    fn points(&self, message_type: &str, event: &json::Value) -> Vec<TrackPoint> {
+        event
+            .get(message_type)
+            .and_then(json::Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|r| {
+                let lat = r.get("latitude")?.as_f64()?;
+                let lon = r.get("longitude")?.as_f64()?;
+                let t = timestamp(r)? as f64 / 1000.;
+                Some(TrackPoint { t, lat, lon })
+            })
+            .collect()
+    }
+
+    fn track(&self, events: &[(String, json::Value)]) -> Vec<TrackPoint> {
+        // The waves are at the same positions as the track, but less frequent.
+        let has_track = events.iter().any(|(t, _)| t == "track");
+
+        events
+            .iter()
+            .filter(|(t, _)| !has_track || t == "track")
+            .flat_map(|(t, e)| self.points(t, e))
+            .collect()
+    }
+
+    fn decode(&self, _message_type: &str, data: &[u8]) -> Option<Result<json::Value>> {
+        Some(json::from_slice(data).map_err(Into::into))
+    }
+
+    fn hs(&self, message_type: &str, data: &[u8]) -> Option<f64> {
+        if message_type != "waves" {
+            return None;
+        }
+
+        let event: json::Value = json::from_slice(data).ok()?;
+        event
+            .get("waves")?
+            .as_array()?
+            .iter()
+            .filter_map(|r| Some((timestamp(r)?, r.get("significantWaveHeight")?.as_f64()?)))
+            .max_by_key(|(t, _)| *t)
+            .map(|(_, hs)| hs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_and_parse() {
+        let payload = std::fs::read("tests/events/spotter-01.json").unwrap();
+        let events = Spotter.split(&payload).unwrap();
+        assert_eq!(events.len(), 3);
+
+        let waves = Spotter.parse(&events[0]).unwrap();
+        assert_eq!(waves.device, "SPOT-30152C");
+        assert_eq!(waves.name.as_deref(), Some("Spotter Bergen"));
+        assert_eq!(waves.message_type.as_deref(), Some("waves"));
+        assert_eq!(waves.received, 1709296200000);
+        assert_eq!(waves.event.as_deref(), Some("1709296200000"));
+
+        let spectra = Spotter.parse(&events[1]).unwrap();
+        assert_eq!(spectra.message_type.as_deref(), Some("frequencyData"));
+
+        let track = Spotter.parse(&events[2]).unwrap();
+        let points = Spotter.points("track", &track.body);
+        assert_eq!(points.len(), 3);
+        assert_eq!(points[0].t, 1709294400.);
+        assert_eq!(points[2].lat, 60.39845);
+
+        let events = vec![
+            ("waves".to_string(), waves.body),
+            ("track".to_string(), track.body),
+        ];
+        assert_eq!(Spotter.track(&events).len(), 3);
+    }
+
+    #[test]
+    fn unwrapped_payload() {
+        let payload = br#"{"spotterId": "SPOT-1", "track": [{"latitude": 1.0, "longitude": 2.0, "timestamp": "2024-03-01T12:00:00.000Z"}]}"#;
+        let events = Spotter.split(payload).unwrap();
+        assert_eq!(events.len(), 1);
+
+        assert!(Spotter
+            .split(br#"{"spotterId": "SPOT-1", "waves": []}"#)
+            .is_err());
+        assert!(Spotter.split(br#"{"waves": []}"#).is_err());
+    }
+}
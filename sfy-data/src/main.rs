@@ -19,15 +19,43 @@ struct Sfy {
     /// configuration file.
     #[argh(option, short = 'c', default = "PathBuf::from(\"sfy-data.toml\")")]
     config: PathBuf,
+
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Backfill(backfill::BackfillCmd),
+    Export(export::ExportCmd),
+    Token(tokens::TokenCmd),
 }
 
+mod alerts;
+mod auth;
+mod backfill;
 mod buoys;
 mod config;
 mod database;
+mod decode;
+mod deployments;
+mod export;
+mod family;
+mod mqtt;
+mod notify;
+mod stitch;
+#[cfg(test)]
+mod testing;
+mod tokens;
+mod track;
+mod waves;
+mod webhooks;
 
 pub struct SfyState {
     pub db: database::Database,
     pub config: config::Config,
+    pub notifier: notify::Notifier,
 }
 
 pub type State = Arc<SfyState>;
@@ -49,17 +77,30 @@ async fn main() -> eyre::Result<()> {
     let database = config.database.clone().expect("no database path specified");
     let database = database::Database::open(&database).await?;
 
+    match sfy.command {
+        Some(Command::Backfill(cmd)) => return cmd.run(&database).await,
+        Some(Command::Export(cmd)) => return cmd.run(&database).await,
+        Some(Command::Token(cmd)) => return cmd.run(&database).await,
+        None => (),
+    }
+
     let state = Arc::new(SfyState {
         db: database,
         config: config.clone(),
+        notifier: notify::Notifier::new(),
     });
 
+    tokio::spawn(webhooks::run(state.clone()));
+    tokio::spawn(mqtt::run(state.clone()));
+    tokio::spawn(alerts::run(state.clone()));
+
     info!("listening on: {:?}", config.address);
 
     let cors = warp::cors()
         .allow_any_origin()
-        .allow_methods(&[Method::GET, Method::POST])
-        .allow_headers(["SFY_AUTH_TOKEN"]);
+        .allow_methods(&[Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers(["SFY_AUTH_TOKEN"])
+        .expose_headers([buoys::NEXT_AFTER]);
 
     if let Some(dir) = config.files {
         info!("serving files in directory: {:?}", dir);
@@ -76,12 +117,18 @@ async fn main() -> eyre::Result<()> {
 
         let sfy = redirect.or(warp::path("sfy").and(warp::fs::dir(dir)));
         let api = sfy
-            .or(buoys::filters(state))
+            .or(buoys::filters(state.clone()))
+            .or(tokens::filters(state.clone()))
+            .or(deployments::filters(state.clone()))
+            .or(notify::filters(state))
             .with(cors)
             .with(warp::log("sfy_data::api"));
         warp::serve(api).run(config.address).await;
     } else {
-        let api = buoys::filters(state)
+        let api = buoys::filters(state.clone())
+            .or(tokens::filters(state.clone()))
+            .or(deployments::filters(state.clone()))
+            .or(notify::filters(state))
             .with(cors)
             .with(warp::log("sfy_data::api"));
         warp::serve(api).run(config.address).await;
@@ -95,8 +142,11 @@ async fn test_state() -> State {
     let config = config::Config::test_config();
     let db = database::Database::temporary().await;
 
-    let state = SfyState { config, db };
-    let state = Arc::new(state);
+    let state = SfyState {
+        config,
+        db,
+        notifier: notify::Notifier::new(),
+    };
 
-    state
+    Arc::new(state)
 }
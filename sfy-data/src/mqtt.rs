@@ -0,0 +1,253 @@
+//! Publish ingested events to an MQTT broker.
+//!
+//! Events are published to `{prefix}/{dev}/{message_type}`. The last position fix in events of
+//! the position message types of the buoy family is published to `{prefix}/{dev}/position` and
+//! retained by the broker, so that new subscribers get the last position of each buoy
+//! immediately. Publishing requires the `mqtt` feature.
+
+use serde::{Deserialize, Serialize};
+use serde_json as json;
+
+use crate::database::TrackPoint;
+use crate::notify::{Ingested, PayloadFormat};
+use crate::State;
+
+/// Last position of a buoy, retained on `{prefix}/{dev}/position`.
+#[derive(Debug, Serialize)]
+pub struct Position {
+    pub dev: String,
+
+    /// Event with the fix, as in the range end-points.
+    pub event: String,
+    pub received: i64,
+
+    #[serde(flatten)]
+    pub fix: TrackPoint,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Mqtt {
+    pub host: String,
+
+    #[serde(default = "default_port")]
+    pub port: u16,
+
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+
+    pub username: Option<String>,
+    pub password: Option<String>,
+
+    /// First level of topics.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+
+    #[serde(default)]
+    pub format: PayloadFormat,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "sfy-data".into()
+}
+
+fn default_prefix() -> String {
+    "sfy".into()
+}
+
+/// Replace characters that are not allowed in topic names, or that would add levels.
+fn topic_level(s: &str) -> String {
+    s.replace(['/', '+', '#', '\0'], "_")
+}
+
+impl Mqtt {
+    pub fn topic(&self, e: &Ingested) -> String {
+        format!(
+            "{}/{}/{}",
+            self.prefix,
+            topic_level(&e.dev),
+            topic_level(&e.message_type)
+        )
+    }
+
+    /// Topic and payload of the last position fix in `e`, if it carries positions.
+    pub fn position(&self, e: &Ingested) -> Option<(String, Position)> {
+        let family = crate::family::get(e.buoy_type)?;
+
+        if !family.positions().contains(&e.message_type.as_str()) {
+            return None;
+        }
+
+        let event: json::Value = json::from_slice(&e.data).ok()?;
+        let fix = family
+            .points(&e.message_type, &event)
+            .into_iter()
+            .max_by(|a, b| a.t.total_cmp(&b.t))?;
+
+        Some((
+            format!("{}/{}/position", self.prefix, topic_level(&e.dev)),
+            Position {
+                dev: e.dev.clone(),
+                event: e.event.clone(),
+                received: e.received,
+                fix,
+            },
+        ))
+    }
+}
+
+#[cfg(not(feature = "mqtt"))]
+pub async fn run(state: State) {
+    if state.config.mqtt.is_some() {
+        error!("sfy-data was built without MQTT support, enable the `mqtt` feature");
+    }
+}
+
+/// Publish ingested events as they arrive, reconnecting to the broker as needed.
+#[cfg(feature = "mqtt")]
+pub async fn run(state: State) {
+    use futures_util::StreamExt;
+    use rumqttc::{AsyncClient, MqttOptions, QoS};
+    use std::time::Duration;
+
+    let config = match &state.config.mqtt {
+        Some(config) => config.clone(),
+        None => return,
+    };
+
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.as_deref().unwrap_or(""));
+    }
+
+    info!("publishing to MQTT broker: {}:{}", config.host, config.port);
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+
+    // The event loop handles (re-)connecting and acknowledgements, and must be polled.
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = eventloop.poll().await {
+                warn!("MQTT connection error: {}, reconnecting..", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    });
+
+    let mut events = Box::pin(crate::notify::events(state.notifier.subscribe()));
+
+    while let Some(e) = events.next().await {
+        let payload = match e.payload(config.format) {
+            Ok(payload) => payload,
+            Err(err) => {
+                error!("failed to serialize {}: {:?}", e.event, err);
+                continue;
+            }
+        };
+
+        let topic = config.topic(&e);
+        trace!("publishing {} to {}", e.event, topic);
+
+        if let Err(err) = client
+            .publish(topic, QoS::AtLeastOnce, false, payload)
+            .await
+        {
+            error!("failed to publish {}: {}", e.event, err);
+        }
+
+        if let Some((topic, position)) = config.position(&e) {
+            let payload = match json::to_vec(&position) {
+                Ok(payload) => payload,
+                Err(err) => {
+                    error!("failed to serialize position of {}: {:?}", e.event, err);
+                    continue;
+                }
+            };
+
+            if let Err(err) = client.publish(topic, QoS::AtLeastOnce, true, payload).await {
+                error!("failed to publish position of {}: {}", e.event, err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topics() {
+        let config: Mqtt = toml::from_str(r#"host = "localhost""#).unwrap();
+        assert_eq!(config.port, 1883);
+        assert_eq!(config.format, PayloadFormat::Raw);
+
+        let mut e = Ingested {
+            dev: "dev864475044204278".into(),
+            product: None,
+            buoy_type: "sfy",
+            event: "event".into(),
+            received: 0,
+            message_type: "egpsb.qo".into(),
+            data: std::fs::read("tests/events/sfy4-egpsb.qo.json")
+                .unwrap()
+                .into(),
+        };
+
+        assert_eq!(config.topic(&e), "sfy/dev864475044204278/egpsb.qo");
+
+        let (topic, position) = config.position(&e).unwrap();
+        assert_eq!(topic, "sfy/dev864475044204278/position");
+        assert_eq!(position.dev, "dev864475044204278");
+
+        let payload: json::Value = json::from_slice(&json::to_vec(&position).unwrap()).unwrap();
+        assert!(payload["lat"].is_f64());
+        assert!(payload["t"].is_f64());
+
+        e.message_type = "axl.qo".into();
+        e.data = std::fs::read(
+            "tests/events/1647870799330-1876870b-4708-4366-8db5-68f872cc4e6d_axl.qo.json",
+        )
+        .unwrap()
+        .into();
+        assert!(config.position(&e).is_some());
+
+        e.message_type = "sessi.qo".into();
+        assert!(config.position(&e).is_none());
+
+        e.dev = "dev#1+".into();
+        assert_eq!(config.topic(&e), "sfy/dev_1_/sessi.qo");
+    }
+
+    #[test]
+    fn spotter_position() {
+        use crate::family::{Family, Spotter};
+
+        let config: Mqtt = toml::from_str(r#"host = "localhost""#).unwrap();
+
+        let payload = std::fs::read("tests/events/spotter-01.json").unwrap();
+        let positions = Spotter
+            .split(&payload)
+            .unwrap()
+            .into_iter()
+            .filter_map(|data| {
+                let parsed = Spotter.parse(&data).unwrap();
+                let e = Ingested {
+                    dev: parsed.device,
+                    product: None,
+                    buoy_type: "spotter",
+                    event: "event".into(),
+                    received: 0,
+                    message_type: parsed.message_type.unwrap_or_default(),
+                    data: data.into(),
+                };
+
+                config.position(&e)
+            })
+            .count();
+
+        assert!(positions > 0);
+    }
+}
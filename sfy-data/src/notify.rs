@@ -0,0 +1,283 @@
+//! Notifications of newly ingested events, and live push of them to subscribers over
+//! Server-Sent Events.
+
+use futures_util::{future, StreamExt};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json as json;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use warp::{sse, Filter};
+
+use crate::auth::Scope;
+use crate::buoys::{check_read_token, with_state};
+use crate::State;
+
+/// Number of events kept for slow subscribers before they start to miss events.
+const CAPACITY: usize = 1024;
+
+/// An event that has been stored in the database.
+#[derive(Debug, Clone, Serialize)]
+pub struct Ingested {
+    pub dev: String,
+
+    #[serde(skip)]
+    pub product: Option<String>,
+
+    /// Name of the buoy family, e.g. `sfy` or `omb`.
+    pub buoy_type: &'static str,
+
+    /// Name of event and time received [ms], as in the range end-points.
+    pub event: String,
+    pub received: i64,
+
+    /// Notehub file (e.g. `axl.qo`) or OpenMetBuoy message type (e.g. `gps`).
+    pub message_type: String,
+
+    /// The raw event, base64 encoded.
+    #[serde(serialize_with = "base64_data")]
+    pub data: bytes::Bytes,
+}
+
+/// Payload of events pushed to other systems.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+    /// The raw event, base64 encoded.
+    #[default]
+    Raw,
+
+    /// The decoded packages, for message types that can be decoded (raw otherwise).
+    Decoded,
+}
+
+impl Ingested {
+    /// The event serialized as JSON, with the data raw or decoded.
+    pub fn payload(&self, format: PayloadFormat) -> eyre::Result<Vec<u8>> {
+        let mut value = json::to_value(self)?;
+
+        if format == PayloadFormat::Decoded {
+            let decoded = crate::family::get(self.buoy_type)
+                .and_then(|f| f.decode(&self.message_type, &self.data));
+
+            match decoded {
+                Some(Ok(decoded)) => {
+                    if let Some(o) = value.as_object_mut() {
+                        o.remove("data");
+                        o.insert("decoded".into(), decoded);
+                    }
+                }
+                Some(Err(e)) => warn!("failed to decode {}, sending raw: {:?}", self.event, e),
+                None => (),
+            }
+        }
+
+        Ok(json::to_vec(&value)?)
+    }
+}
+
+fn base64_data<S: Serializer>(data: &bytes::Bytes, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&base64::encode(data))
+}
+
+/// Broadcasts ingested events to all subscribers.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    tx: broadcast::Sender<Arc<Ingested>>,
+}
+
+impl Notifier {
+    pub fn new() -> Notifier {
+        let (tx, _) = broadcast::channel(CAPACITY);
+        Notifier { tx }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Ingested>> {
+        self.tx.subscribe()
+    }
+
+    pub fn notify(&self, event: Ingested) {
+        trace!(
+            "notifying {} subscribers of: {}",
+            self.tx.receiver_count(),
+            event.event
+        );
+
+        // No subscribers is not an error.
+        let _ = self.tx.send(Arc::new(event));
+    }
+}
+
+/// Stream of ingested events from `rx`, skipping events missed by slow subscribers.
+pub fn events(
+    rx: broadcast::Receiver<Arc<Ingested>>,
+) -> impl futures_util::Stream<Item = Arc<Ingested>> {
+    futures_util::stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(e) => return Some((e, rx)),
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("subscriber lagging behind, skipped {} events", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+/// Filter of a subscription, buoys and message types are comma-separated lists.
+#[derive(Debug, Deserialize, Default)]
+pub struct Subscription {
+    pub buoy: Option<String>,
+    pub message_type: Option<String>,
+}
+
+impl Subscription {
+    pub fn matches(&self, e: &Ingested) -> bool {
+        fn contains(list: &Option<String>, v: &str) -> bool {
+            match list {
+                Some(l) => l.split(',').any(|i| i == v),
+                None => true,
+            }
+        }
+
+        contains(&self.buoy, &e.dev) && contains(&self.message_type, &e.message_type)
+    }
+}
+
+pub fn filters(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    subscribe(state)
+}
+
+pub fn subscribe(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("events")
+        .and(warp::get())
+        .and(check_read_token(state.clone()))
+        .and(warp::query::<Subscription>())
+        .and(with_state(state))
+        .map(handlers::subscribe)
+}
+
+pub mod handlers {
+    use super::*;
+
+    pub fn subscribe(scope: Scope, subscription: Subscription, state: State) -> impl warp::Reply {
+        debug!("new subscriber: {:?}", subscription);
+
+        let stream = events(state.notifier.subscribe())
+            .filter(move |e| {
+                future::ready(
+                    subscription.matches(e) && scope.permits_dev(&e.dev, e.product.as_deref()),
+                )
+            })
+            .map(|e| {
+                sse::Event::default()
+                    .id(format!("{}-{}", e.received, e.event))
+                    .event(e.message_type.clone())
+                    .json_data(&*e)
+            });
+
+        sse::reply(sse::keep_alive().stream(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ingested(dev: &str, message_type: &str) -> Ingested {
+        Ingested {
+            dev: dev.into(),
+            product: None,
+            buoy_type: "sfy",
+            event: "event".into(),
+            received: 0,
+            message_type: message_type.into(),
+            data: bytes::Bytes::from_static(b"data"),
+        }
+    }
+
+    #[test]
+    fn subscription_matches() {
+        let e = ingested("dev1", "axl.qo");
+
+        assert!(Subscription::default().matches(&e));
+
+        let s = Subscription {
+            buoy: Some("dev0,dev1".into()),
+            message_type: None,
+        };
+        assert!(s.matches(&e));
+
+        let s = Subscription {
+            buoy: Some("dev1".into()),
+            message_type: Some("egps.qo".into()),
+        };
+        assert!(!s.matches(&e));
+
+        let j = json::to_value(&e).unwrap();
+        assert_eq!(j["data"], base64::encode("data"));
+        assert!(j.get("product").is_none());
+    }
+
+    #[test]
+    fn decoded_payload() {
+        let data = std::fs::read("tests/events/sfy4-egpsb.qo.json").unwrap();
+        let mut e = ingested("dev1", "egpsb.qo");
+        e.data = data.into();
+
+        let raw: json::Value = json::from_slice(&e.payload(PayloadFormat::Raw).unwrap()).unwrap();
+        assert!(raw.get("decoded").is_none());
+        assert_eq!(raw["dev"], "dev1");
+
+        let decoded: json::Value =
+            json::from_slice(&e.payload(PayloadFormat::Decoded).unwrap()).unwrap();
+        assert!(decoded.get("data").is_none());
+        assert!(!decoded["decoded"]["lat"].as_array().unwrap().is_empty());
+
+        let mut e = ingested("dev1", "sessi.qo");
+        e.data = bytes::Bytes::from_static(b"{}");
+        let decoded: json::Value =
+            json::from_slice(&e.payload(PayloadFormat::Decoded).unwrap()).unwrap();
+        assert_eq!(decoded["data"], base64::encode("{}"));
+    }
+
+    #[tokio::test]
+    async fn append_notifies() {
+        let state = crate::test_state().await;
+        let mut rx = state.notifier.subscribe();
+
+        let f = crate::buoys::filters(state.clone());
+
+        let event = crate::testing::event("sfy4-egpsb.qo.json", "dev:notify");
+
+        let res = crate::testing::post(&f, &event).await;
+        assert_eq!(res.status(), 200);
+
+        let e = rx.recv().await.unwrap();
+        assert_eq!(e.dev, "devnotify");
+        assert_eq!(e.message_type, "egpsb.qo");
+        assert_eq!(e.buoy_type, "sfy");
+        assert_eq!(e.product.as_deref(), Some("product:no.met.gauteh:sfy"));
+
+        let omb = std::fs::read("tests/events/01-omb.json").unwrap();
+        let res = warp::test::request()
+            .path("/buoy/omb")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", "token1")
+            .body(omb)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let e = Box::pin(events(rx).filter(|e| future::ready(e.buoy_type == "omb")))
+            .next()
+            .await
+            .unwrap();
+        assert_eq!(e.message_type, "gps");
+        assert!(e.event.ends_with("-gps"));
+    }
+}
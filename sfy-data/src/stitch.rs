@@ -0,0 +1,403 @@
+//! Joining acceleration packages into continuous series.
+//!
+//! The time of each sample follows from the `timestamp` of the sample at `offset` and the
+//! output frequency. Consecutive packages are continuous if the first sample of the next package
+//! follows the last sample of the previous within [`MAX_JITTER`]. Otherwise there is a break:
+//! a gap, an overlap, a change of frequency, or a clock jump if the packages follow each other on
+//! the SD-card (consecutive `storage_id`) although their times do not. Clock jumps are found by
+//! following the `storage_id`, so that a jump backwards in time is found as well.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::decode::axl::AxlPacket;
+
+/// Largest difference between the expected and the actual time of the first sample of the next
+/// package for the packages to be continuous [samples].
+pub const MAX_JITTER: f64 = 1.;
+
+/// A continuous series of vertical acceleration.
+#[derive(Debug)]
+pub struct Series {
+    /// Time of first sample [ms].
+    pub start: f64,
+
+    /// Frequency of samples [Hz].
+    pub freq: f32,
+
+    /// Vertical acceleration [m/s^2].
+    pub z: Vec<f32>,
+
+    /// Number of packages joined.
+    pub packets: usize,
+
+    /// Storage id of the first and last package.
+    pub storage_ids: Option<[u32; 2]>,
+}
+
+impl Series {
+    fn new(p: AxlPacket) -> Series {
+        Series {
+            start: p.time.first().copied().unwrap_or(p.timestamp as f64),
+            freq: p.freq,
+            z: p.z,
+            packets: 1,
+            storage_ids: p.storage_id.map(|id| [id, id]),
+        }
+    }
+
+    fn push(&mut self, p: AxlPacket) {
+        self.z.extend(p.z);
+        self.packets += 1;
+        self.storage_ids = match (self.storage_ids, p.storage_id) {
+            (Some([first, _]), Some(id)) => Some([first, id]),
+            _ => None,
+        };
+    }
+
+    /// Time between samples [ms].
+    pub fn dt(&self) -> f64 {
+        1000. / self.freq as f64
+    }
+
+    /// Time after the last sample [ms].
+    pub fn end(&self) -> f64 {
+        self.start + self.z.len() as f64 * self.dt()
+    }
+
+    /// Difference between the first sample of `p` and the sample expected after this series
+    /// [samples], `None` if the frequencies differ.
+    pub fn offset_of(&self, p: &AxlPacket) -> Option<f64> {
+        let t = p.time.first()?;
+        (p.freq == self.freq).then(|| (t - self.end()) / self.dt())
+    }
+
+    /// The range of samples in `[t0, t1)` [ms].
+    pub fn samples(&self, t0: f64, t1: f64) -> std::ops::Range<usize> {
+        let index = |t: f64| {
+            ((t - self.start) / self.dt())
+                .ceil()
+                .clamp(0., self.z.len() as f64) as usize
+        };
+
+        index(t0)..index(t1)
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakKind {
+    /// The next package starts later than expected.
+    Gap,
+
+    /// The next package starts before the previous ended.
+    Overlap,
+
+    /// The packages follow each other on the SD-card, but their times do not.
+    ClockJump,
+
+    /// The frequency of the samples changed.
+    Frequency,
+}
+
+/// A break between two consecutive packages.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Break {
+    pub kind: BreakKind,
+
+    /// Time after the last sample before the break [ms].
+    pub end: f64,
+
+    /// Time of the first sample after the break [ms].
+    pub next: f64,
+
+    /// Time from the expected to the actual time of the first sample after the break [ms].
+    pub offset: f64,
+
+    /// Storage id of the packages before and after the break.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_ids: Option<[u32; 2]>,
+}
+
+/// Packages that follow each other on the SD-card (the next `storage_id`, which wraps around),
+/// with the same frequency, but are not continuous in time.
+fn clock_jumps(packets: &[AxlPacket]) -> Vec<Break> {
+    let by_id: HashMap<u32, &AxlPacket> = packets
+        .iter()
+        .filter_map(|p| p.storage_id.map(|id| (id, p)))
+        .collect();
+
+    packets
+        .iter()
+        .filter_map(|p| {
+            let id = p.storage_id?;
+            let next = by_id.get(&id.wrapping_add(1))?;
+
+            if next.freq != p.freq {
+                return None;
+            }
+
+            let dt = 1000. / p.freq as f64;
+            let end = p.time[0] + p.time.len() as f64 * dt;
+            let offset = next.time[0] - end;
+
+            (offset.abs() > MAX_JITTER * dt).then(|| Break {
+                kind: BreakKind::ClockJump,
+                end,
+                next: next.time[0],
+                offset,
+                storage_ids: Some([id, id.wrapping_add(1)]),
+            })
+        })
+        .collect()
+}
+
+/// Sort packages by time, remove repeated packages and join continuous packages. Returns the
+/// continuous series, the breaks between them (ordered by the end of the series before the
+/// break), and the number of repeated packages.
+pub fn join(mut packets: Vec<AxlPacket>) -> (Vec<Series>, Vec<Break>, usize) {
+    packets.retain(|p| !p.time.is_empty());
+    packets.sort_by(|a, b| a.time[0].total_cmp(&b.time[0]));
+
+    let n = packets.len();
+    packets.dedup_by(|p, prev| p.timestamp == prev.timestamp && p.offset == prev.offset);
+    let duplicates = n - packets.len();
+
+    let mut series: Vec<Series> = Vec::new();
+    let mut breaks = clock_jumps(&packets);
+
+    for p in packets {
+        let s = match series.last_mut() {
+            Some(s) => s,
+            None => {
+                series.push(Series::new(p));
+                continue;
+            }
+        };
+
+        let last_id = s.storage_ids.map(|[_, last]| last);
+        let kind = match s.offset_of(&p) {
+            Some(o) if o.abs() <= MAX_JITTER => {
+                s.push(p);
+                continue;
+            }
+            None => BreakKind::Frequency,
+            // Already found as a clock jump.
+            Some(_) if matches!((last_id, p.storage_id), (Some(a), Some(b)) if b == a.wrapping_add(1)) =>
+            {
+                series.push(Series::new(p));
+                continue;
+            }
+            Some(o) if o > 0. => BreakKind::Gap,
+            Some(_) => BreakKind::Overlap,
+        };
+
+        breaks.push(Break {
+            kind,
+            end: s.end(),
+            next: p.time[0],
+            offset: p.time[0] - s.end(),
+            storage_ids: last_id.zip(p.storage_id).map(|(a, b)| [a, b]),
+        });
+        series.push(Series::new(p));
+    }
+
+    breaks.sort_by(|a, b| a.end.total_cmp(&b.end));
+
+    (series, breaks, duplicates)
+}
+
+/// Sort packages by time, remove repeated packages and join continuous packages.
+pub fn stitch(packets: Vec<AxlPacket>) -> Vec<Series> {
+    join(packets).0
+}
+
+/// A continuous interval in a [`Report`].
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Segment {
+    /// Time of first sample [ms].
+    pub start: f64,
+
+    /// Time after the last sample [ms].
+    pub end: f64,
+
+    pub freq: f32,
+    pub samples: usize,
+    pub packets: usize,
+
+    /// Storage id of the first and last package.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub storage_ids: Option<[u32; 2]>,
+}
+
+/// Continuous intervals of a range of packages, and what breaks them.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Report {
+    pub segments: Vec<Segment>,
+    pub breaks: Vec<Break>,
+
+    /// Number of packages received more than once.
+    pub duplicates: usize,
+
+    /// Ranges `[first, last]` of storage ids between the first and the last that were not
+    /// received. A range across the wrap of the storage id has `first > last`.
+    pub missing_storage_ids: Vec<[u32; 2]>,
+}
+
+/// Ranges of storage ids missing between the first and the last of `ids`. The storage id wraps
+/// around, the largest step between the sorted ids (also from the last to the first) is taken as
+/// the step from the last to the first.
+fn missing_ids(mut ids: Vec<u32>) -> Vec<[u32; 2]> {
+    ids.sort_unstable();
+    ids.dedup();
+
+    let n = ids.len();
+    if n < 2 {
+        return Vec::new();
+    }
+
+    let next = |i: usize| ids[(i + 1) % n];
+    let last = (0..n)
+        .max_by_key(|i| next(*i).wrapping_sub(ids[*i]))
+        .unwrap();
+
+    (1..n)
+        .map(|k| (last + k) % n)
+        .filter(|i| next(*i).wrapping_sub(ids[*i]) > 1)
+        .map(|i| [ids[i].wrapping_add(1), next(i).wrapping_sub(1)])
+        .collect()
+}
+
+impl Report {
+    pub fn new(packets: Vec<AxlPacket>) -> Report {
+        let missing_storage_ids =
+            missing_ids(packets.iter().filter_map(|p| p.storage_id).collect());
+
+        let (series, breaks, duplicates) = join(packets);
+
+        let segments = series
+            .iter()
+            .map(|s| Segment {
+                start: s.start,
+                end: s.end(),
+                freq: s.freq,
+                samples: s.z.len(),
+                packets: s.packets,
+                storage_ids: s.storage_ids,
+            })
+            .collect();
+
+        Report {
+            segments,
+            breaks,
+            duplicates,
+            missing_storage_ids,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::packet;
+
+    #[test]
+    fn join_packets() {
+        let dt = 1000. / 52.;
+        let n = 1024;
+        let length = n as f64 * dt;
+
+        let packets = vec![
+            packet(length + 3., n),
+            packet(0., n),
+            packet(length + 3., n),
+            packet(2. * length, n),
+            packet(10. * length, n),
+        ];
+
+        let series = stitch(packets);
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[0].packets, 3);
+        assert_eq!(series[0].z.len(), 3 * n);
+        assert_eq!(series[1].start, 10. * length);
+
+        assert_eq!(series[0].samples(-100., dt / 2.), 0..1);
+        assert_eq!(series[0].samples(dt / 2., 10. * length), 1..3 * n);
+    }
+
+    #[test]
+    fn report_breaks() {
+        let dt = 1000. / 52.;
+        let n = 1024;
+        let length = n as f64 * dt;
+
+        let with_id = |start: f64, id: u32| {
+            let mut p = packet(start, n);
+            p.storage_id = Some(id);
+            p
+        };
+
+        let mut slow = with_id(20. * length, 9);
+        slow.freq = 26.;
+
+        let packets = vec![
+            with_id(0., 1),
+            with_id(length, 2),
+            with_id(length, 2),
+            // Clock jump of a minute.
+            with_id(2. * length + 60_000., 3),
+            // Gap with packages 4 and 5 missing.
+            with_id(10. * length, 6),
+            // Overlap, with package 7 missing (with consecutive storage ids it is a clock jump).
+            with_id(10.5 * length, 8),
+            slow,
+            // Clock jump backwards, the package after 10 on the SD-card is sampled before it.
+            with_id(30. * length, 10),
+            with_id(25. * length, 11),
+        ];
+
+        let r = Report::new(packets);
+
+        assert_eq!(r.duplicates, 1);
+        assert_eq!(r.missing_storage_ids, [[4, 5], [7, 7]]);
+        assert_eq!(r.segments.len(), 7);
+        assert_eq!(r.segments[0].packets, 2);
+        assert_eq!(r.segments[0].storage_ids, Some([1, 2]));
+        assert_eq!(r.segments[0].end, 2. * length);
+
+        let kinds: Vec<BreakKind> = r.breaks.iter().map(|b| b.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                BreakKind::ClockJump,
+                BreakKind::Gap,
+                BreakKind::Overlap,
+                BreakKind::Frequency,
+                BreakKind::Frequency,
+                BreakKind::Gap,
+                BreakKind::ClockJump
+            ]
+        );
+        assert!((r.breaks[0].offset - 60_000.).abs() < 1e-6);
+        assert_eq!(r.breaks[0].storage_ids, Some([2, 3]));
+        assert_eq!(r.breaks[1].storage_ids, Some([3, 6]));
+        assert!(r.breaks[2].offset < 0.);
+        assert_eq!(r.breaks[5].storage_ids, Some([11, 10]));
+        assert_eq!(r.breaks[6].storage_ids, Some([10, 11]));
+        assert!((r.breaks[6].offset + 6. * length).abs() < 1e-6);
+    }
+
+    #[test]
+    fn missing_storage_ids() {
+        assert_eq!(missing_ids(vec![]), Vec::<[u32; 2]>::new());
+        assert_eq!(missing_ids(vec![5, 2, 3, 9]), [[4, 4], [6, 8]]);
+
+        // The storage id wraps around.
+        assert_eq!(
+            missing_ids(vec![u32::MAX - 3, 1, u32::MAX, 3]),
+            [[u32::MAX - 2, u32::MAX - 1], [0, 0], [2, 2]]
+        );
+        assert_eq!(missing_ids(vec![u32::MAX - 1, 2]), [[u32::MAX, 1]]);
+    }
+}
@@ -0,0 +1,88 @@
+//! Fixtures shared by the tests.
+
+use bytes::Bytes;
+use serde_json as json;
+use warp::{http::Response, Filter, Reply};
+
+use crate::decode::axl::AxlPacket;
+use crate::decode::spec::{FI0, FI1};
+
+/// The event in `tests/events/<fixture>` as sent by `dev`.
+pub fn event(fixture: &str, dev: &str) -> json::Value {
+    let mut event: json::Value =
+        json::from_slice(&std::fs::read(format!("tests/events/{}", fixture)).unwrap()).unwrap();
+    event["device"] = dev.into();
+    event
+}
+
+/// Post `event` to `/buoy` with the write token.
+pub async fn post<F>(f: &F, event: &json::Value) -> Response<Bytes>
+where
+    F: Filter + 'static,
+    F::Extract: Reply + Send,
+{
+    warp::test::request()
+        .path("/buoy")
+        .method("POST")
+        .header("SFY_AUTH_TOKEN", "token1")
+        .body(json::to_vec(event).unwrap())
+        .reply(f)
+        .await
+}
+
+/// A `spec.qo` event with a spectrum peaking at bin `peak`.
+pub fn spec_event(timestamp: i64, peak: usize) -> json::Value {
+    let a: Vec<u8> = (FI0..FI1)
+        .map(|i| {
+            let d = (i as f32 - peak as f32).abs();
+            (u16::MAX as f32 * f32::max(0., 1. - d / 4.)) as u16
+        })
+        .flat_map(u16::to_le_bytes)
+        .collect();
+
+    json::json!({
+        "event": format!("spec-{}", timestamp),
+        "file": "spec.qo",
+        "device": "dev:860264050364604",
+        "sn": "SFY4-01",
+        "received": (timestamp / 1000 + 1300) as f64,
+        "body": { "timestamp": timestamp, "max": 0.5 },
+        "payload": base64::encode(a),
+    })
+}
+
+/// A package of `n` samples at 52 Hz starting at `start` [ms].
+pub fn packet(start: f64, n: usize) -> AxlPacket {
+    let freq = 52.;
+    let dt = 1000. / freq as f64;
+
+    AxlPacket {
+        timestamp: start as i64,
+        offset: 0,
+        storage_id: None,
+        storage_version: 6,
+        position_time: None,
+        lon: None,
+        lat: None,
+        temperature: None,
+        freq,
+        accel_range: 2.,
+        gyro_range: 125.,
+        time: (0..n).map(|i| start + i as f64 * dt).collect(),
+        x: vec![0.; n],
+        y: vec![0.; n],
+        z: vec![9.81; n],
+    }
+}
+
+/// An OpenMetBuoy thermistor message with a single profile.
+pub fn thermistor_message(time: u32, temperatures: &[i16]) -> Vec<u8> {
+    let mut data = vec![b'T'];
+    data.extend(time.to_le_bytes());
+    data.extend([-3i8 as u8, 5, temperatures.len() as u8]);
+    for t in temperatures {
+        data.extend(t.to_le_bytes());
+    }
+    data.push(b'E');
+    data
+}
@@ -0,0 +1,469 @@
+//! Management of the access tokens stored in the database, through the `token` command or the
+//! admin end-points. Changes take effect immediately, without restarting the server.
+
+use argh::FromArgs;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use warp::{reject, Filter, Rejection};
+
+use crate::auth::{self, NewToken, Token, TokenKind};
+use crate::buoys::{handle_reject, with_state, BadRequest};
+use crate::database::Database;
+use crate::State;
+
+#[derive(FromArgs, Debug)]
+/// Create, list, expire and revoke access tokens.
+#[argh(subcommand, name = "token")]
+pub struct TokenCmd {
+    #[argh(subcommand)]
+    command: TokenSubCommand,
+}
+
+#[derive(FromArgs, Debug)]
+#[argh(subcommand)]
+enum TokenSubCommand {
+    Create(CreateCmd),
+    List(ListCmd),
+    Expire(ExpireCmd),
+    Revoke(RevokeCmd),
+}
+
+#[derive(FromArgs, Debug)]
+/// Create a new token, the token is only shown once.
+#[argh(subcommand, name = "create")]
+struct CreateCmd {
+    /// kind of token: write, read or admin.
+    #[argh(option)]
+    kind: TokenKind,
+
+    /// limit read token to buoy (dev), may be repeated.
+    #[argh(option)]
+    buoy: Vec<String>,
+
+    /// limit read token to Notehub product, may be repeated.
+    #[argh(option)]
+    product: Vec<String>,
+
+    /// expiry time [ms since epoch].
+    #[argh(option)]
+    expires: Option<i64>,
+
+    /// label describing the token.
+    #[argh(positional)]
+    label: String,
+}
+
+#[derive(FromArgs, Debug)]
+/// List tokens.
+#[argh(subcommand, name = "list")]
+struct ListCmd {}
+
+#[derive(FromArgs, Debug)]
+/// Set the expiry time of a token.
+#[argh(subcommand, name = "expire")]
+struct ExpireCmd {
+    /// expiry time [ms since epoch] (default: now).
+    #[argh(option)]
+    at: Option<i64>,
+
+    /// token id.
+    #[argh(positional)]
+    id: i32,
+}
+
+#[derive(FromArgs, Debug)]
+/// Revoke a token.
+#[argh(subcommand, name = "revoke")]
+struct RevokeCmd {
+    /// token id.
+    #[argh(positional)]
+    id: i32,
+}
+
+impl TokenCmd {
+    pub async fn run(&self, db: &Database) -> Result<()> {
+        match &self.command {
+            TokenSubCommand::Create(c) => {
+                let (token, secret) = NewToken {
+                    label: c.label.clone(),
+                    kind: c.kind,
+                    buoys: c.buoy.clone(),
+                    products: c.product.clone(),
+                    expires: c.expires,
+                }
+                .create(db)
+                .await?;
+
+                println!("{}\t{}", token.id, secret);
+            }
+            TokenSubCommand::List(_) => {
+                let now = chrono::Utc::now().timestamp_millis();
+
+                for t in db.tokens().await? {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}",
+                        t.id,
+                        t.kind.to_str(),
+                        if t.active(now) { "active" } else { "inactive" },
+                        t.label,
+                        [t.buoys.join(","), t.products.join(",")].join(" ")
+                    );
+                }
+            }
+            TokenSubCommand::Expire(c) => {
+                let at =
+                    c.at.unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+                ensure!(db.expire_token(c.id, Some(at)).await?, "no such token");
+            }
+            TokenSubCommand::Revoke(c) => {
+                ensure!(
+                    db.revoke_token(c.id, chrono::Utc::now().timestamp_millis())
+                        .await?,
+                    "no such token, or already revoked"
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn filters(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    list(state.clone())
+        .or(create(state.clone()))
+        .or(expire(state.clone()))
+        .or(revoke(state))
+        .recover(handle_reject)
+}
+
+pub fn list(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("tokens")
+        .and(warp::get())
+        .and(check_admin_token(state.clone()))
+        .and(with_state(state))
+        .and_then(handlers::list)
+}
+
+pub fn create(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("tokens")
+        .and(warp::post())
+        .and(check_admin_token(state.clone()))
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::json())
+        .and(with_state(state))
+        .and_then(handlers::create)
+}
+
+pub fn expire(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("tokens" / i32 / "expire")
+        .and(warp::post())
+        .and(check_admin_token(state.clone()))
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::json())
+        .and(with_state(state))
+        .and_then(handlers::expire)
+}
+
+pub fn revoke(
+    state: State,
+) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("tokens" / i32 / "revoke")
+        .and(warp::post())
+        .and(check_admin_token(state.clone()))
+        .and(with_state(state))
+        .and_then(handlers::revoke)
+}
+
+pub(crate) fn check_admin_token(
+    state: State,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::<String>("SFY_AUTH_TOKEN")
+        .and(with_state(state))
+        .and_then(|v: String, state: State| async move {
+            if auth::admin(&state, &v).await {
+                Ok(())
+            } else {
+                warn!("rejected admin token: {}", auth::fingerprint(&v));
+                Err(reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+/// Token returned on creation, the only time the token itself is available.
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    pub token: String,
+
+    #[serde(flatten)]
+    pub info: Token,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Expiry {
+    /// Expiry time [ms since epoch], `null` removes the expiry.
+    pub expires: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum TokenErrors {
+    Database,
+}
+
+impl reject::Reject for TokenErrors {}
+
+pub mod handlers {
+    use super::*;
+
+    pub async fn list(state: State) -> Result<impl warp::Reply, warp::Rejection> {
+        let tokens = state.db.tokens().await.map_err(|e| {
+            error!("failed to list tokens: {:?}", e);
+            reject::custom(TokenErrors::Database)
+        })?;
+
+        Ok(warp::reply::json(&tokens))
+    }
+
+    pub async fn create(
+        token: NewToken,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        if token.label.is_empty() {
+            warn!("token without label");
+            return Err(reject::custom(BadRequest));
+        }
+
+        let (info, token) = token.create(&state.db).await.map_err(|e| {
+            error!("failed to create token: {:?}", e);
+            reject::custom(TokenErrors::Database)
+        })?;
+
+        Ok(warp::reply::json(&CreatedToken { token, info }))
+    }
+
+    pub async fn expire(
+        id: i32,
+        expiry: Expiry,
+        state: State,
+    ) -> Result<impl warp::Reply, warp::Rejection> {
+        let found = state
+            .db
+            .expire_token(id, expiry.expires)
+            .await
+            .map_err(|e| {
+                error!("failed to expire token: {:?}", e);
+                reject::custom(TokenErrors::Database)
+            })?;
+
+        if found {
+            info!("token {} expires at: {:?}", id, expiry.expires);
+            Ok("expired")
+        } else {
+            Err(reject::not_found())
+        }
+    }
+
+    pub async fn revoke(id: i32, state: State) -> Result<impl warp::Reply, warp::Rejection> {
+        let found = state
+            .db
+            .revoke_token(id, chrono::Utc::now().timestamp_millis())
+            .await
+            .map_err(|e| {
+                error!("failed to revoke token: {:?}", e);
+                reject::custom(TokenErrors::Database)
+            })?;
+
+        if found {
+            info!("revoked token {}", id);
+            Ok("revoked")
+        } else {
+            Err(reject::not_found())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json as json;
+
+    #[tokio::test]
+    async fn manage_tokens() {
+        let state = crate::test_state().await;
+
+        let (_, admin) = NewToken {
+            label: "test admin".into(),
+            kind: TokenKind::Admin,
+            buoys: vec![],
+            products: vec![],
+            expires: None,
+        }
+        .create(&state.db)
+        .await
+        .unwrap();
+
+        let f = filters(state.clone()).or(crate::buoys::filters(state.clone()));
+
+        let event = crate::testing::event("sfy4-egpsb.qo.json", "dev:tokens-a");
+
+        let res = crate::testing::post(&f, &event).await;
+        assert_eq!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path("/tokens")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", "r-token1")
+            .reply(&f)
+            .await;
+        assert!(res.status().is_client_error());
+
+        let res = warp::test::request()
+            .path("/tokens")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", &admin)
+            .json(&json::json!({ "label": "", "kind": "read" }))
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 400);
+
+        let res = warp::test::request()
+            .path("/tokens")
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", &admin)
+            .json(&json::json!({ "label": "test reader", "kind": "read", "buoys": [ "devtokens-a" ] }))
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let created: json::Value = json::from_slice(res.body()).unwrap();
+        let id = created["id"].as_i64().unwrap();
+        let reader = created["token"].as_str().unwrap().to_string();
+        assert_eq!(created["kind"], "read");
+        assert_eq!(created["label"], "test reader");
+
+        let res = warp::test::request()
+            .path("/tokens")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", &admin)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let body = std::str::from_utf8(res.body()).unwrap();
+        assert!(!body.contains(&reader), "token leaked in list");
+        let tokens: json::Value = json::from_slice(res.body()).unwrap();
+        assert!(tokens
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|t| t["id"].as_i64() == Some(id)));
+
+        // Token works immediately, and is scoped.
+        let res = warp::test::request()
+            .path("/buoys/devtokens-a")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", &reader)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path("/buoys/devtokens-b")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", &reader)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 404);
+
+        // Expired tokens are rejected.
+        let res = warp::test::request()
+            .path(&format!("/tokens/{}/expire", id))
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", &admin)
+            .json(&json::json!({ "expires": 0 }))
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path("/buoys/devtokens-a")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", &reader)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 404);
+
+        let res = warp::test::request()
+            .path(&format!("/tokens/{}/expire", id))
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", &admin)
+            .json(&json::json!({ "expires": null }))
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path("/buoys/devtokens-a")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", &reader)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        // Revoked tokens are rejected.
+        let res = warp::test::request()
+            .path(&format!("/tokens/{}/revoke", id))
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", &admin)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 200);
+
+        let res = warp::test::request()
+            .path("/buoys/devtokens-a")
+            .method("GET")
+            .header("SFY_AUTH_TOKEN", &reader)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 404);
+
+        let res = warp::test::request()
+            .path(&format!("/tokens/{}/revoke", id))
+            .method("POST")
+            .header("SFY_AUTH_TOKEN", &admin)
+            .reply(&f)
+            .await;
+        assert_eq!(res.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn write_token() {
+        let state = crate::test_state().await;
+
+        let (_, writer) = NewToken {
+            label: "test writer".into(),
+            kind: TokenKind::Write,
+            buoys: vec![],
+            products: vec![],
+            expires: None,
+        }
+        .create(&state.db)
+        .await
+        .unwrap();
+
+        assert!(auth::write(&state, &writer).await);
+        assert!(auth::read(&state, &writer).await.is_none());
+        assert!(!auth::admin(&state, &writer).await);
+
+        assert!(auth::write(&state, "token1").await);
+        assert!(!auth::write(&state, "r-token1").await);
+    }
+}
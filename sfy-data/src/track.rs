@@ -0,0 +1,660 @@
+//! Output formats and cleaning of buoy tracks.
+
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json as json;
+use std::fmt::Write;
+
+use crate::database::TrackPoint;
+
+/// Output format of tracks.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackFormat {
+    #[default]
+    Json,
+    GeoJson,
+    Gpx,
+    Kml,
+    Csv,
+}
+
+impl TrackFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            TrackFormat::Json => "application/json",
+            TrackFormat::GeoJson => "application/geo+json",
+            TrackFormat::Gpx => "application/gpx+xml",
+            TrackFormat::Kml => "application/vnd.google-earth.kml+xml",
+            TrackFormat::Csv => "text/csv",
+        }
+    }
+}
+
+/// Mean radius of the earth [m].
+const EARTH_RADIUS: f64 = 6_371_000.;
+
+/// The track of a single buoy.
+#[derive(Debug)]
+pub struct Track {
+    pub dev: String,
+    pub name: Option<String>,
+    pub points: Vec<TrackPoint>,
+
+    /// Include the drift speed and heading from the previous fix (JSON, GeoJSON and CSV).
+    pub drift: bool,
+}
+
+/// Drift from the previous fix.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub struct Drift {
+    /// Speed over ground [m/s].
+    pub speed: f64,
+
+    /// Heading [degrees clockwise from north].
+    pub heading: f64,
+}
+
+#[derive(Serialize)]
+struct DriftPoint<'a> {
+    #[serde(flatten)]
+    point: &'a TrackPoint,
+    speed: Option<f64>,
+    heading: Option<f64>,
+}
+
+/// Optional processing of a track, applied in this order.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Cleaning {
+    /// Sort the fixes by time and remove fixes repeated at the same time or position.
+    pub dedup: bool,
+
+    /// Remove isolated fixes that can only be reached from both neighbours faster than this
+    /// [m/s], e.g. GPS jumps.
+    pub max_speed: Option<f64>,
+
+    /// Simplify the track to at most this number of fixes (Douglas-Peucker).
+    pub simplify: Option<usize>,
+}
+
+impl Cleaning {
+    pub fn apply(&self, mut points: Vec<TrackPoint>) -> Vec<TrackPoint> {
+        if self.dedup {
+            points = dedup(points);
+        }
+
+        if let Some(max_speed) = self.max_speed {
+            points = reject_outliers(points, max_speed);
+        }
+
+        if let Some(n) = self.simplify {
+            points = simplify(points, n);
+        }
+
+        points
+    }
+}
+
+/// Great-circle distance between two points [m].
+pub fn distance(a: &TrackPoint, b: &TrackPoint) -> f64 {
+    let (lat0, lat1) = (a.lat.to_radians(), b.lat.to_radians());
+    let dlat = lat1 - lat0;
+    let dlon = (b.lon - a.lon).to_radians();
+
+    let h = (dlat / 2.).sin().powi(2) + lat0.cos() * lat1.cos() * (dlon / 2.).sin().powi(2);
+    2. * EARTH_RADIUS * h.sqrt().asin()
+}
+
+/// Whether point is inside polygon of `[lon, lat]` points, by ray casting.
+pub fn inside(polygon: &[[f64; 2]], p: &TrackPoint) -> bool {
+    let mut inside = false;
+
+    for (i, a) in polygon.iter().enumerate() {
+        let b = &polygon[(i + polygon.len() - 1) % polygon.len()];
+
+        if (a[1] > p.lat) != (b[1] > p.lat)
+            && p.lon < (b[0] - a[0]) * (p.lat - a[1]) / (b[1] - a[1]) + a[0]
+        {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+/// An area to look for positions in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Area {
+    /// `[min_lon, min_lat, max_lon, max_lat]` [deg]. The box crosses the antimeridian if
+    /// `min_lon > max_lon`.
+    BBox([f64; 4]),
+
+    /// Polygon of `[lon, lat]` points [deg].
+    Polygon(Vec<[f64; 2]>),
+}
+
+impl Area {
+    /// Bounding box from `min_lon,min_lat,max_lon,max_lat`.
+    pub fn bbox(s: &str) -> eyre::Result<Area> {
+        let c = coordinates(s)?;
+        ensure!(c.len() == 4, "bbox must have four coordinates");
+        ensure!(c[1] <= c[3], "min_lat must be less than max_lat");
+
+        Ok(Area::BBox([c[0], c[1], c[2], c[3]]))
+    }
+
+    /// Polygon from `lon,lat,lon,lat,..`.
+    pub fn polygon(s: &str) -> eyre::Result<Area> {
+        let c = coordinates(s)?;
+        ensure!(
+            c.len() >= 6 && c.chunks_exact(2).remainder().is_empty(),
+            "polygon must have at least three lon,lat points"
+        );
+
+        Ok(Area::Polygon(c.chunks(2).map(|p| [p[0], p[1]]).collect()))
+    }
+
+    pub fn contains(&self, p: &TrackPoint) -> bool {
+        match self {
+            Area::BBox([lon0, lat0, lon1, lat1]) => {
+                let lon = if lon0 <= lon1 {
+                    *lon0 <= p.lon && p.lon <= *lon1
+                } else {
+                    *lon0 <= p.lon || p.lon <= *lon1
+                };
+
+                lon && *lat0 <= p.lat && p.lat <= *lat1
+            }
+            Area::Polygon(polygon) => inside(polygon, p),
+        }
+    }
+}
+
+fn coordinates(s: &str) -> eyre::Result<Vec<f64>> {
+    s.split(',')
+        .map(|c| {
+            let c: f64 = c.trim().parse()?;
+            ensure!(c.is_finite(), "invalid coordinate: {}", c);
+            Ok(c)
+        })
+        .collect()
+}
+
+/// Speed between two fixes [m/s], `None` if they are not ordered in time.
+fn speed(a: &TrackPoint, b: &TrackPoint) -> Option<f64> {
+    let dt = b.t - a.t;
+    (dt > 0.).then(|| distance(a, b) / dt)
+}
+
+/// Initial great-circle bearing from `a` to `b` [degrees clockwise from north].
+fn heading(a: &TrackPoint, b: &TrackPoint) -> f64 {
+    let (lat0, lat1) = (a.lat.to_radians(), b.lat.to_radians());
+    let dlon = (b.lon - a.lon).to_radians();
+
+    let y = dlon.sin() * lat1.cos();
+    let x = lat0.cos() * lat1.sin() - lat0.sin() * lat1.cos() * dlon.cos();
+    (y.atan2(x).to_degrees() + 360.) % 360.
+}
+
+/// Drift from the previous fix for every fix, `None` for the first.
+pub fn drift(points: &[TrackPoint]) -> Vec<Option<Drift>> {
+    std::iter::once(None)
+        .chain(points.windows(2).map(|w| {
+            speed(&w[0], &w[1]).map(|speed| Drift {
+                speed,
+                heading: heading(&w[0], &w[1]),
+            })
+        }))
+        .take(points.len())
+        .collect()
+}
+
+fn dedup(mut points: Vec<TrackPoint>) -> Vec<TrackPoint> {
+    points.sort_by(|a, b| a.t.total_cmp(&b.t));
+    points.dedup_by(|p, prev| p.t == prev.t || (p.lat == prev.lat && p.lon == prev.lon));
+    points
+}
+
+/// Remove fixes that are too far from both the previous kept fix and the next fix. A jump that
+/// the track stays at (e.g. a buoy moved by boat) is kept. The first and last fixes are removed
+/// if they are too far from their neighbour, while the neighbour is consistent with the rest of
+/// the track.
+fn reject_outliers(points: Vec<TrackPoint>, max_speed: f64) -> Vec<TrackPoint> {
+    let fast = |a: &TrackPoint, b: &TrackPoint| matches!(speed(a, b), Some(s) if s > max_speed);
+
+    let mut kept: Vec<TrackPoint> = Vec::with_capacity(points.len());
+
+    for (i, p) in points.iter().enumerate() {
+        let outlier = match (kept.last(), points.get(i + 1)) {
+            (Some(prev), Some(next)) => fast(prev, p) && fast(p, next),
+            (None, Some(next)) => {
+                fast(p, next) && matches!(points.get(i + 2), Some(n) if !fast(next, n))
+            }
+            (Some(prev), None) => {
+                fast(prev, p) && kept.len() > 1 && !fast(&kept[kept.len() - 2], prev)
+            }
+            (None, None) => false,
+        };
+
+        if outlier {
+            debug!("rejecting outlier in track: {:?}", p);
+        } else {
+            kept.push(p.clone());
+        }
+    }
+
+    kept
+}
+
+/// Distance from `p` to the segment `a`-`b` [m], in a local plane around `a`.
+fn segment_distance(a: &TrackPoint, b: &TrackPoint, p: &TrackPoint) -> f64 {
+    let project = |q: &TrackPoint| {
+        (
+            (q.lon - a.lon).to_radians() * a.lat.to_radians().cos() * EARTH_RADIUS,
+            (q.lat - a.lat).to_radians() * EARTH_RADIUS,
+        )
+    };
+    let (bx, by) = project(b);
+    let (px, py) = project(p);
+
+    let l2 = bx * bx + by * by;
+    let u = if l2 > 0. {
+        ((px * bx + py * by) / l2).clamp(0., 1.)
+    } else {
+        0.
+    };
+
+    ((px - u * bx).powi(2) + (py - u * by).powi(2)).sqrt()
+}
+
+/// Simplify track to at most `n` (at least 2) fixes: starting with the end-points, the fix
+/// farthest from the simplified track is added until there are `n` fixes.
+fn simplify(points: Vec<TrackPoint>, n: usize) -> Vec<TrackPoint> {
+    let n = n.max(2);
+    if points.len() <= n {
+        return points;
+    }
+
+    // The fix farthest from the segment between `a` and `b`.
+    let farthest = |a: usize, b: usize| {
+        (a + 1..b)
+            .map(|i| (i, segment_distance(&points[a], &points[b], &points[i])))
+            .max_by(|x, y| x.1.total_cmp(&y.1))
+    };
+
+    let last = points.len() - 1;
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[last] = true;
+
+    let mut segments = vec![(0, last, farthest(0, last))];
+
+    for _ in 2..n {
+        let next = segments
+            .iter()
+            .enumerate()
+            .filter_map(|(s, (_, _, f))| f.map(|(i, d)| (s, i, d)))
+            .max_by(|x, y| x.2.total_cmp(&y.2));
+
+        let (s, i) = match next {
+            Some((s, i, _)) => (s, i),
+            None => break,
+        };
+
+        let (a, b, _) = segments.swap_remove(s);
+        keep[i] = true;
+        segments.push((a, i, farthest(a, i)));
+        segments.push((i, b, farthest(i, b)));
+    }
+
+    points
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(p, k)| k.then_some(p))
+        .collect()
+}
+
+impl Track {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.dev)
+    }
+
+    pub fn format(&self, format: TrackFormat) -> eyre::Result<Vec<u8>> {
+        Ok(match format {
+            TrackFormat::Json if self.drift => json::to_vec(&self.drift_points())?,
+            TrackFormat::Json => json::to_vec(&self.points)?,
+            TrackFormat::GeoJson => json::to_vec(&geojson(std::slice::from_ref(self)))?,
+            TrackFormat::Gpx => self.gpx().into_bytes(),
+            TrackFormat::Kml => self.kml().into_bytes(),
+            TrackFormat::Csv => self.csv()?,
+        })
+    }
+
+    fn drift_points(&self) -> Vec<DriftPoint<'_>> {
+        self.points
+            .iter()
+            .zip(drift(&self.points))
+            .map(|(point, d)| DriftPoint {
+                point,
+                speed: d.map(|d| d.speed),
+                heading: d.map(|d| d.heading),
+            })
+            .collect()
+    }
+
+    pub fn gpx(&self) -> String {
+        let mut s = String::new();
+        s.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        s.push('\n');
+        s.push_str(
+            r#"<gpx version="1.1" creator="sfy-data" xmlns="http://www.topografix.com/GPX/1/1">"#,
+        );
+        s.push('\n');
+        writeln!(s, "<trk><name>{}</name><trkseg>", escape(self.name())).unwrap();
+
+        for p in &self.points {
+            writeln!(
+                s,
+                r#"<trkpt lat="{}" lon="{}"><time>{}</time></trkpt>"#,
+                p.lat,
+                p.lon,
+                time(p.t)
+            )
+            .unwrap();
+        }
+
+        s.push_str("</trkseg></trk>\n</gpx>\n");
+        s
+    }
+
+    pub fn kml(&self) -> String {
+        let mut s = String::new();
+        s.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        s.push('\n');
+        s.push_str(r#"<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">"#);
+        s.push('\n');
+        writeln!(
+            s,
+            "<Document><Placemark><name>{}</name><gx:Track>",
+            escape(self.name())
+        )
+        .unwrap();
+
+        for p in &self.points {
+            writeln!(s, "<when>{}</when>", time(p.t)).unwrap();
+        }
+
+        for p in &self.points {
+            writeln!(s, "<gx:coord>{} {} 0</gx:coord>", p.lon, p.lat).unwrap();
+        }
+
+        s.push_str("</gx:Track></Placemark></Document>\n</kml>\n");
+        s
+    }
+
+    pub fn csv(&self) -> eyre::Result<Vec<u8>> {
+        let mut w = csv::Writer::from_writer(Vec::new());
+        if self.drift {
+            w.write_record(["time", "t", "lat", "lon", "speed", "heading"])?;
+        } else {
+            w.write_record(["time", "t", "lat", "lon"])?;
+        }
+
+        for p in self.drift_points() {
+            let mut record = vec![
+                time(p.point.t),
+                p.point.t.to_string(),
+                p.point.lat.to_string(),
+                p.point.lon.to_string(),
+            ];
+
+            if self.drift {
+                let field = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+                record.push(field(p.speed));
+                record.push(field(p.heading));
+            }
+
+            w.write_record(&record)?;
+        }
+
+        Ok(w.into_inner()?)
+    }
+}
+
+/// A GeoJSON FeatureCollection with a point feature for every fix in the tracks.
+pub fn geojson(tracks: &[Track]) -> json::Value {
+    let features: Vec<json::Value> = tracks
+        .iter()
+        .flat_map(|track| {
+            track.drift_points().into_iter().map(move |p| {
+                let mut feature = json::json!({
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Point",
+                        "coordinates": [p.point.lon, p.point.lat],
+                    },
+                    "properties": {
+                        "dev": track.dev,
+                        "name": track.name(),
+                        "time": time(p.point.t),
+                        "t": p.point.t,
+                    },
+                });
+
+                if track.drift {
+                    feature["properties"]["speed"] = p.speed.into();
+                    feature["properties"]["heading"] = p.heading.into();
+                }
+
+                feature
+            })
+        })
+        .collect();
+
+    json::json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+/// ISO 8601 time from unix timestamp in seconds.
+fn time(t: f64) -> String {
+    Utc.timestamp_millis_opt((t * 1000.).round() as i64)
+        .single()
+        .map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, true))
+        .unwrap_or_default()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> Track {
+        Track {
+            dev: "dev1".into(),
+            name: Some("SFY<1>".into()),
+            points: vec![
+                TrackPoint {
+                    t: 1700000000.,
+                    lat: 60.1,
+                    lon: 5.2,
+                },
+                TrackPoint {
+                    t: 1700000060.5,
+                    lat: 60.2,
+                    lon: 5.3,
+                },
+            ],
+            drift: false,
+        }
+    }
+
+    fn point(t: f64, lat: f64, lon: f64) -> TrackPoint {
+        TrackPoint { t, lat, lon }
+    }
+
+    #[test]
+    fn iso_time() {
+        assert_eq!(time(1700000060.5), "2023-11-14T22:14:20.500Z");
+    }
+
+    #[test]
+    fn track_geojson() {
+        let g = geojson(&[track()]);
+        assert_eq!(g["type"], "FeatureCollection");
+        assert_eq!(g["features"].as_array().unwrap().len(), 2);
+        assert_eq!(g["features"][1]["geometry"]["coordinates"][0], 5.3);
+        assert_eq!(g["features"][1]["properties"]["name"], "SFY<1>");
+        assert_eq!(
+            g["features"][1]["properties"]["time"],
+            "2023-11-14T22:14:20.500Z"
+        );
+    }
+
+    #[test]
+    fn track_gpx() {
+        let g = track().gpx();
+        assert!(g.contains("<name>SFY&lt;1&gt;</name>"));
+        assert!(g.contains(
+            r#"<trkpt lat="60.2" lon="5.3"><time>2023-11-14T22:14:20.500Z</time></trkpt>"#
+        ));
+    }
+
+    #[test]
+    fn track_kml() {
+        let k = track().kml();
+        assert!(k.contains("<when>2023-11-14T22:13:20.000Z</when>"));
+        assert!(k.contains("<gx:coord>5.2 60.1 0</gx:coord>"));
+    }
+
+    #[test]
+    fn track_csv() {
+        let c = String::from_utf8(track().csv().unwrap()).unwrap();
+        let lines: Vec<_> = c.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "time,t,lat,lon");
+        assert_eq!(lines[1], "2023-11-14T22:13:20.000Z,1700000000,60.1,5.2");
+    }
+
+    #[test]
+    fn track_drift() {
+        let mut track = track();
+        track.drift = true;
+
+        let d = drift(&track.points);
+        assert_eq!(d[0], None);
+        let d = d[1].unwrap();
+        assert!((d.heading - 26.42).abs() < 0.01, "{}", d.heading);
+        assert!((d.speed - 12_420.7 / 60.5).abs() < 0.1, "{}", d.speed);
+
+        let c = String::from_utf8(track.csv().unwrap()).unwrap();
+        let lines: Vec<_> = c.lines().collect();
+        assert_eq!(lines[0], "time,t,lat,lon,speed,heading");
+        assert!(lines[1].ends_with(",5.2,,"));
+
+        let j: json::Value = json::from_slice(&track.format(TrackFormat::Json).unwrap()).unwrap();
+        assert_eq!(j[0]["speed"], json::Value::Null);
+        assert!(j[1]["heading"].as_f64().unwrap() > 26.);
+
+        let g = geojson(&[track]);
+        assert!(g["features"][1]["properties"]["speed"].as_f64().unwrap() > 200.);
+    }
+
+    #[test]
+    fn clean_dedup() {
+        let points = vec![
+            point(60., 60.1, 5.1),
+            point(0., 60., 5.),
+            point(60., 60.1, 5.1),
+            point(120., 60.1, 5.1),
+            point(180., 60.2, 5.2),
+        ];
+
+        let points = Cleaning {
+            dedup: true,
+            ..Default::default()
+        }
+        .apply(points);
+
+        let t: Vec<f64> = points.iter().map(|p| p.t).collect();
+        assert_eq!(t, [0., 60., 180.]);
+    }
+
+    #[test]
+    fn clean_outliers() {
+        // Drifting about 1 m/s north, with a jump at 180 s and a bad first fix.
+        let mut points: Vec<TrackPoint> = (0..8)
+            .map(|i| point(60. * i as f64, 60. + 0.0005 * i as f64, 5.))
+            .collect();
+        points[0].lon = 6.;
+        points[3].lat = 61.;
+
+        let points = Cleaning {
+            max_speed: Some(5.),
+            ..Default::default()
+        }
+        .apply(points);
+
+        let t: Vec<f64> = points.iter().map(|p| p.t).collect();
+        assert_eq!(t, [60., 120., 240., 300., 360., 420.]);
+
+        // A buoy that is moved and stays is kept.
+        let mut points: Vec<TrackPoint> = (0..6).map(|i| point(60. * i as f64, 60., 5.)).collect();
+        for p in &mut points[3..] {
+            p.lat = 61.;
+        }
+
+        assert_eq!(reject_outliers(points, 5.).len(), 6);
+    }
+
+    #[test]
+    fn clean_simplify() {
+        // A straight line with one corner.
+        let points: Vec<TrackPoint> = (0..20)
+            .map(|i| {
+                let i = i as f64;
+                let lat = if i < 10. { 60. + 0.01 * i } else { 60.09 };
+                let lon = if i < 10. { 5. } else { 5. + 0.01 * (i - 9.) };
+                point(60. * i, lat, lon)
+            })
+            .collect();
+
+        let simple = simplify(points.clone(), 3);
+        let t: Vec<f64> = simple.iter().map(|p| p.t).collect();
+        assert_eq!(t, [0., 540., 1140.]);
+
+        assert_eq!(simplify(points.clone(), 100).len(), 20);
+        assert_eq!(simplify(points.clone(), 0).len(), 2);
+        assert_eq!(simplify(points, 7).len(), 7);
+    }
+
+    #[test]
+    fn areas() {
+        let bbox = Area::bbox("5,60,6,61").unwrap();
+        assert!(bbox.contains(&point(0., 60.5, 5.5)));
+        assert!(!bbox.contains(&point(0., 60.5, 6.5)));
+
+        let antimeridian = Area::bbox("179,60,-179,61").unwrap();
+        assert!(antimeridian.contains(&point(0., 60.5, 179.5)));
+        assert!(antimeridian.contains(&point(0., 60.5, -179.5)));
+        assert!(!antimeridian.contains(&point(0., 60.5, 0.)));
+
+        let triangle = Area::polygon("5,60, 6,60, 5,61").unwrap();
+        assert!(triangle.contains(&point(0., 60.2, 5.2)));
+        assert!(!triangle.contains(&point(0., 60.8, 5.8)));
+
+        assert!(Area::bbox("5,60,6").is_err());
+        assert!(Area::bbox("5,61,6,60").is_err());
+        assert!(Area::bbox("5,60,6,NaN").is_err());
+        assert!(Area::polygon("5,60,6,60").is_err());
+        assert!(Area::polygon("5,60,6,60,5,61,5").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+//! Bulk wave parameters from spectra.
+//!
+//! Follows `sfy.signal` in `sfy-processing`, see: Holthuijsen, Leo H. Waves in Oceanic and
+//! Coastal Waters. Cambridge University Press, 2010.
+
+use serde::Serialize;
+use std::f64::consts::PI;
+
+pub mod series;
+pub mod welch;
+
+/// Frequencies below this are discarded when computing moments (T = 20 minutes) [Hz].
+pub const F0: f64 = 1. / (20. * 60.);
+
+/// Integrate an _acceleration_ spectrum `order` times (2 gives an _elevation_ spectrum).
+pub fn welchint(f: &[f32], p: &[f32], order: i32) -> Vec<f32> {
+    f.iter()
+        .zip(p)
+        .map(|(f, p)| {
+            let d = (2. * PI * *f as f64).powi(2 * order);
+            if d > 0. {
+                (*p as f64 / d) as f32
+            } else {
+                *p
+            }
+        })
+        .collect()
+}
+
+/// Spectral moment of `order` using the trapezoidal rule.
+pub fn spectral_moment(f: &[f32], e: &[f32], order: i32) -> f64 {
+    let m: Vec<(f64, f64)> = f
+        .iter()
+        .zip(e)
+        .map(|(f, e)| (*f as f64, *e as f64))
+        .filter(|(f, _)| *f >= F0)
+        .map(|(f, e)| (f, f.powi(order) * e))
+        .collect();
+
+    m.windows(2)
+        .map(|w| (w[1].0 - w[0].0) * (w[0].1 + w[1].1) / 2.)
+        .sum()
+}
+
+/// Bulk wave parameters of an elevation spectrum.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub struct Stats {
+    /// Significant wave height [m].
+    pub hm0: f64,
+
+    /// Peak period [s].
+    pub tp: f64,
+
+    /// Mean period [s].
+    pub tm01: f64,
+
+    /// Mean zero-crossing period [s].
+    pub tm02: f64,
+}
+
+impl Stats {
+    pub fn from_spectrum(f: &[f32], e: &[f32]) -> Stats {
+        let m0 = spectral_moment(f, e, 0);
+        let m1 = spectral_moment(f, e, 1);
+        let m2 = spectral_moment(f, e, 2);
+
+        let fp = f
+            .iter()
+            .zip(e)
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(f, _)| *f as f64)
+            .unwrap_or(0.);
+
+        Stats {
+            hm0: 4. * m0.sqrt(),
+            tp: 1. / fp,
+            tm01: m0 / m1,
+            tm02: (m0 / m2).sqrt(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrow_peak() {
+        // Triangular peak at 0.1 Hz with a base of 0.01 Hz.
+        let df = 0.001;
+        let f: Vec<f32> = (0..1000).map(|i| (i as f64 * df) as f32).collect();
+        let e: Vec<f32> = f
+            .iter()
+            .map(|f| f32::max(0., 1. - (f - 0.1).abs() / 0.005))
+            .collect();
+
+        let s = Stats::from_spectrum(&f, &e);
+
+        let m0: f64 = 0.005;
+        assert!((s.hm0 - 4. * m0.sqrt()).abs() < 1e-3);
+        assert!((s.tp - 10.).abs() < 1e-3);
+        assert!((s.tm01 - 10.).abs() < 0.1);
+        assert!((s.tm02 - 10.).abs() < 0.1);
+    }
+
+    #[test]
+    fn integrate_acceleration() {
+        let f = [0., 1. / PI as f32];
+        let p = [1., 32.];
+
+        let e = welchint(&f, &p, 2);
+        assert_eq!(e[0], 1.);
+        assert!((e[1] - 2.).abs() < 1e-5);
+        assert_eq!(welchint(&f, &p, 0), p);
+    }
+}
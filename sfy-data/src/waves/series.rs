@@ -0,0 +1,250 @@
+//! Time series of bulk wave parameters computed from the acceleration packages (`axl.qo` and
+//! `axlb.qo`), for buoys that do not send spectra.
+//!
+//! The packages are joined into continuous series, and a Welch spectrum is computed for each
+//! window from the segments that fit in the continuous parts of the window. Windows are aligned
+//! to multiples of the window length since epoch, and stored in the `wave_stats` table. Windows
+//! are removed from the table when a package with samples in the window is appended.
+
+use eyre::Result;
+use serde::Serialize;
+use std::collections::HashSet;
+
+use super::{welch::Welch, welchint, Stats};
+use crate::database::{Buoy, Page};
+use crate::decode::{axl::AxlPacket, decode_events, spec};
+use crate::stitch::{stitch, Series};
+
+/// Default length of windows [s].
+pub const DEFAULT_WINDOW: i64 = 20 * 60;
+
+/// Packages received later than this after they were sampled are not used [ms].
+pub const MAX_DELAY: i64 = 24 * 3600 * 1000;
+
+/// Packages are loaded for at most this long a time at once [ms].
+const CHUNK: i64 = 6 * 3600 * 1000;
+
+/// Bulk wave parameters of a window.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct WaveStats {
+    /// Start of window [ms].
+    pub start: i64,
+
+    /// Length of window [ms].
+    pub window: i64,
+
+    /// Segment length of the Welch spectrum.
+    pub nseg: usize,
+
+    /// Frequency of samples [Hz].
+    pub freq: Option<f32>,
+
+    /// Number of segments in the spectrum.
+    pub segments: usize,
+
+    /// Number of gaps between continuous series in the window.
+    pub gaps: usize,
+
+    /// Fraction of the window covered by samples.
+    pub coverage: f64,
+
+    /// Not set if there are too few samples for a single segment.
+    #[serde(flatten)]
+    pub stats: Option<Stats>,
+}
+
+impl WaveStats {
+    /// Compute the window from `start` of length `window` [ms] from the continuous series.
+    pub fn compute(series: &[Series], start: i64, window: i64, nseg: usize) -> WaveStats {
+        let (t0, t1) = (start as f64, (start + window) as f64);
+        let series: Vec<&Series> = series
+            .iter()
+            .filter(|s| s.start < t1 && s.end() > t0)
+            .collect();
+
+        // Series at another frequency than the first are not used.
+        let freq = series.first().map(|s| s.freq);
+        let series: Vec<&Series> = series
+            .into_iter()
+            .filter(|s| Some(s.freq) == freq)
+            .collect();
+
+        let mut welch = freq.map(|freq| Welch::new(freq, nseg));
+        let mut samples = 0;
+
+        for s in &series {
+            let range = s.samples(t0, t1);
+            samples += range.len();
+
+            if let Some(welch) = welch.as_mut() {
+                welch.add(&s.z[range]);
+            }
+        }
+
+        let segments = welch.as_ref().map_or(0, Welch::segments);
+        let stats = welch.filter(|w| w.segments() > 0).map(|w| {
+            let f = &w.frequencies()[spec::FI0..spec::FI1];
+            let a = &w.spectrum()[spec::FI0..spec::FI1];
+            let e = welchint(f, a, 2);
+            Stats::from_spectrum(f, &e)
+        });
+
+        WaveStats {
+            start,
+            window,
+            nseg,
+            freq,
+            segments,
+            gaps: series.len().saturating_sub(1),
+            coverage: freq.map_or(0., |freq| {
+                samples as f64 / (window as f64 / 1000. * freq as f64)
+            }),
+            stats,
+        }
+    }
+}
+
+/// Segment lengths that cover the frequency bins of the spectrum.
+pub fn valid_nseg(nseg: usize) -> bool {
+    nseg > 2 * spec::FI1
+}
+
+/// Segment length used by the firmware for the frequency of the first package received in
+/// `[from, to]`, `None` if there are no packages.
+async fn firmware_nseg(buoy: &Buoy, from: i64, to: i64) -> Result<Option<usize>> {
+    let events = buoy.axl_range(from, to, &Page::new(None, Some(16))).await?;
+
+    Ok(decode_events(events, AxlPacket::decode)
+        .first()
+        .map(|d| spec::nseg(d.packet.freq)))
+}
+
+/// Wave parameters for the windows of length `window` [ms] starting in `[from, to)` with data,
+/// computing and storing the windows that are not stored yet. Without `nseg` the segment length
+/// of the firmware for the frequency of the packages is used.
+pub async fn wave_stats(
+    buoy: &Buoy,
+    from: i64,
+    to: i64,
+    window: i64,
+    nseg: Option<usize>,
+) -> Result<Vec<WaveStats>> {
+    ensure!(window > 0, "invalid window: {}", window);
+
+    let (first, last) = match buoy.axl_received().await? {
+        Some(span) => span,
+        None => return Ok(Vec::new()),
+    };
+
+    // Samples are received after they are sampled, but not more than `MAX_DELAY` after.
+    let from = from.max(first - MAX_DELAY).div_euclid(window) * window;
+    let to = to.min(last + 1);
+
+    let nseg = match nseg {
+        Some(nseg) => nseg,
+        None => match firmware_nseg(buoy, from, to.saturating_add(MAX_DELAY)).await? {
+            Some(nseg) => nseg,
+            None => return Ok(Vec::new()),
+        },
+    };
+    ensure!(valid_nseg(nseg), "invalid segment length: {}", nseg);
+
+    let mut stats = buoy.wave_stats(from, to, window, nseg).await?;
+    let stored: HashSet<i64> = stats.iter().map(|s| s.start).collect();
+
+    let missing: Vec<i64> = (0..)
+        .map(|i| from + i * window)
+        .take_while(|start| *start < to)
+        .filter(|start| !stored.contains(start))
+        .collect();
+
+    // Consecutive missing windows, at most `CHUNK` long.
+    let per_chunk = (CHUNK / window).max(1) as usize;
+    let mut runs: Vec<Vec<i64>> = Vec::new();
+    for start in missing {
+        match runs.last_mut() {
+            Some(run) if run.len() < per_chunk && run.last() == Some(&(start - window)) => {
+                run.push(start)
+            }
+            _ => runs.push(vec![start]),
+        }
+    }
+
+    for run in runs {
+        let (t0, t1) = (run[0], run[run.len() - 1] + window);
+        debug!(
+            "{}: computing {} wave windows from {} to {}",
+            buoy.dev(),
+            run.len(),
+            t0,
+            t1
+        );
+
+        let events = buoy.axl_range(t0, t1 + MAX_DELAY, &Page::all()).await?;
+        let packets = decode_events(events, AxlPacket::decode)
+            .into_iter()
+            .map(|d| d.packet)
+            .collect();
+        let series = stitch(packets);
+
+        let computed: Vec<WaveStats> = run
+            .into_iter()
+            .map(|start| WaveStats::compute(&series, start, window, nseg))
+            .collect();
+
+        buoy.store_wave_stats(&computed).await?;
+        stats.extend(computed);
+    }
+
+    stats.retain(|s| s.coverage > 0.);
+    stats.sort_by_key(|s| s.start);
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::packet;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn compute_window() {
+        // A wave of 1 m amplitude at frequency bin 20 (about 2 s): Hm0 = 4 * sqrt(0.5). At lower
+        // frequencies the leakage of the window is amplified by the integration to elevation.
+        let (freq, n) = (52., 1024);
+        let dt = 1000. / freq as f64;
+        let fw = 20. * freq / 2048.;
+        let w = 2. * PI * fw;
+
+        let packets = (0..70)
+            .filter(|i| *i != 40)
+            .map(|i| {
+                let mut p = packet(i as f64 * n as f64 * dt, n);
+                for (z, t) in p.z.iter_mut().zip(&p.time) {
+                    *z = -w * w * (w * (*t / 1000.) as f32).sin();
+                }
+                p
+            })
+            .collect();
+        let series = stitch(packets);
+        assert_eq!(series.len(), 2);
+
+        let s = WaveStats::compute(&series, 0, 20 * 60 * 1000, 2048);
+        assert_eq!(s.gaps, 1);
+        assert!(s.coverage > 0.9 && s.coverage < 1., "{}", s.coverage);
+        assert_eq!(s.segments, 57);
+
+        let stats = s.stats.unwrap();
+        assert!(
+            (stats.hm0 - 4. * 0.5f64.sqrt()).abs() < 0.1,
+            "{}",
+            stats.hm0
+        );
+        assert!((stats.tp - 1. / fw as f64).abs() < 1e-3, "{}", stats.tp);
+
+        let empty = WaveStats::compute(&series, -20 * 60 * 1000, 20 * 60 * 1000, 2048);
+        assert_eq!(empty.coverage, 0.);
+        assert_eq!(empty.stats, None);
+    }
+}
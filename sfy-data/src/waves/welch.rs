@@ -0,0 +1,137 @@
+//! Welch spectra of acceleration series, following `waves::welch::Welch` in the firmware: a
+//! Hanning window (`numpy.hanning`), segments of `nseg` samples overlapping by `nseg / 2`, each
+//! detrended by its mean, and a one-sided density (PSD) scaling. Based on the `scipy.welch`
+//! implementation.
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+pub struct Welch {
+    /// Sample frequency [Hz].
+    fs: f32,
+    nseg: usize,
+    window: Vec<f32>,
+    scaling: f32,
+    fft: Arc<dyn Fft<f32>>,
+
+    /// Sum of the real side of the spectra of the segments.
+    spec: Vec<f32>,
+
+    /// Number of segments in the spectrum.
+    segments: usize,
+}
+
+impl Welch {
+    pub fn new(fs: f32, nseg: usize) -> Welch {
+        let window: Vec<f32> = (0..nseg)
+            .map(|i| 0.5 - 0.5 * f32::cos((2.0 * PI * i as f32) / (nseg - 1) as f32))
+            .collect();
+        let sqrsum: f32 = window.iter().map(|w| w * w).sum();
+
+        Welch {
+            fs,
+            nseg,
+            scaling: 2.0 / (fs * sqrsum),
+            window,
+            fft: FftPlanner::new().plan_fft_forward(nseg),
+            spec: vec![0.; nseg / 2],
+            segments: 0,
+        }
+    }
+
+    pub fn noverlap(&self) -> usize {
+        self.nseg / 2
+    }
+
+    /// Add the segments of a continuous series. Samples at the end that do not fill a segment
+    /// are not used.
+    pub fn add(&mut self, z: &[f32]) {
+        let step = self.nseg - self.noverlap();
+        let mut start = 0;
+
+        while start + self.nseg <= z.len() {
+            self.add_segment(&z[start..start + self.nseg]);
+            start += step;
+        }
+    }
+
+    fn add_segment(&mut self, z: &[f32]) {
+        let mean = z.iter().sum::<f32>() / z.len() as f32;
+
+        let mut v: Vec<Complex<f32>> = z
+            .iter()
+            .zip(&self.window)
+            .map(|(z, w)| Complex::new(w * (z - mean), 0.))
+            .collect();
+        self.fft.process(&mut v);
+
+        for (s, v) in self.spec.iter_mut().zip(&v) {
+            *s += v.norm_sqr() * self.scaling;
+        }
+
+        self.segments += 1;
+    }
+
+    /// Number of segments in the spectrum.
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    /// Δf between frequency bins.
+    pub fn frequency_resolution(&self) -> f32 {
+        self.fs / self.nseg as f32
+    }
+
+    /// Frequency bins [Hz].
+    pub fn frequencies(&self) -> Vec<f32> {
+        let df = self.frequency_resolution();
+        (0..self.nseg / 2).map(|i| i as f32 * df).collect()
+    }
+
+    /// The spectrum averaged over the segments, zero if there are no segments.
+    pub fn spectrum(&self) -> Vec<f32> {
+        let n = self.segments.max(1) as f32;
+        self.spec.iter().map(|s| s / n).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sine_energy() {
+        // A sine with amplitude 1 m/s^2 at a frequency bin has variance 0.5.
+        let (fs, nseg) = (52., 2048);
+        let f0 = 100. * fs / nseg as f32;
+        let z: Vec<f32> = (0..20 * 60 * 52)
+            .map(|i| (2. * PI * f0 * i as f32 / fs).sin() + 9.81)
+            .collect();
+
+        let mut w = Welch::new(fs, nseg);
+        w.add(&z);
+
+        assert_eq!(w.segments(), (z.len() - nseg) / (nseg / 2) + 1);
+
+        let s = w.spectrum();
+        let peak = s
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap()
+            .0;
+        assert_eq!(peak, 100);
+
+        let variance: f32 = s.iter().sum::<f32>() * w.frequency_resolution();
+        assert!((variance - 0.5).abs() < 0.01, "{}", variance);
+    }
+
+    #[test]
+    fn short_series() {
+        let mut w = Welch::new(52., 2048);
+        w.add(&[0.; 2047]);
+        assert_eq!(w.segments(), 0);
+        assert!(w.spectrum().iter().all(|s| *s == 0.));
+    }
+}
@@ -0,0 +1,312 @@
+//! Outbound webhooks.
+//!
+//! Ingested events matching a webhook are put in a persistent queue in the database, and POSTed
+//! to the webhook by a background task. Failed deliveries are retried with exponential backoff,
+//! and survive restarts of the server.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::database::{Database, Transaction};
+use crate::notify::{Ingested, PayloadFormat};
+use crate::State;
+
+/// Delay before the first retry, doubled for every failed attempt [ms].
+const RETRY_BASE: i64 = 10_000;
+
+/// Maximum delay between retries [ms].
+const RETRY_MAX: i64 = 6 * 3600 * 1000;
+
+/// Deliveries are given up after this many attempts.
+const MAX_ATTEMPTS: i32 = 12;
+
+/// Queue is checked at least this often, for retries.
+const POLL: Duration = Duration::from_secs(5);
+
+/// Deliveries handled at a time.
+const BATCH: i64 = 50;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A webhook target, receiving events of the given buoys and message types (all if empty).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Webhook {
+    pub url: String,
+
+    #[serde(default)]
+    pub buoys: Vec<String>,
+
+    #[serde(default)]
+    pub message_types: Vec<String>,
+
+    #[serde(default)]
+    pub format: PayloadFormat,
+}
+
+impl Webhook {
+    pub fn matches(&self, e: &Ingested) -> bool {
+        (self.buoys.is_empty() || self.buoys.contains(&e.dev))
+            && (self.message_types.is_empty() || self.message_types.contains(&e.message_type))
+    }
+}
+
+/// A queued delivery.
+#[derive(Debug)]
+pub struct Delivery {
+    pub id: i32,
+    pub url: String,
+    pub payload: Vec<u8>,
+    pub attempts: i32,
+}
+
+/// Delay before next attempt after `attempts` failed attempts [ms].
+fn backoff(attempts: i32) -> i64 {
+    let exp = (attempts - 1).clamp(0, 30) as u32;
+    RETRY_BASE.saturating_mul(1 << exp).min(RETRY_MAX)
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// Queue event for the matching webhooks in the transaction that stores the event, so that
+/// deliveries are not lost. Payloads that cannot be encoded are logged and skipped.
+pub async fn enqueue(state: &State, tx: &mut Transaction, e: &Ingested) -> eyre::Result<()> {
+    for hook in state.config.webhooks.iter().filter(|h| h.matches(e)) {
+        let payload = match e.payload(hook.format) {
+            Ok(payload) => payload,
+            Err(err) => {
+                error!("failed to encode {} for {}: {:?}", e.event, hook.url, err);
+                continue;
+            }
+        };
+
+        let id = Database::enqueue_webhook_in(tx, &hook.url, &payload, now()).await?;
+        debug!("queued {} for {} ({})", e.event, hook.url, id);
+    }
+
+    Ok(())
+}
+
+pub fn client() -> eyre::Result<reqwest::Client> {
+    Ok(reqwest::Client::builder().timeout(TIMEOUT).build()?)
+}
+
+/// Deliver the queued events that are due at `now`, returns the number of delivered events.
+pub async fn deliver(state: &State, client: &reqwest::Client, now: i64) -> eyre::Result<usize> {
+    let mut delivered = 0;
+
+    for d in state.db.due_webhooks(now, BATCH).await? {
+        let res = client
+            .post(&d.url)
+            .header("Content-Type", "application/json")
+            .body(d.payload)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+
+        match res {
+            Ok(_) => {
+                trace!("delivered {} to {}", d.id, d.url);
+                state.db.webhook_delivered(d.id).await?;
+                delivered += 1;
+            }
+            Err(err) => {
+                let attempts = d.attempts + 1;
+                let failed = if attempts >= MAX_ATTEMPTS {
+                    error!(
+                        "giving up delivery {} to {} after {} attempts: {}",
+                        d.id, d.url, attempts, err
+                    );
+                    Some(now)
+                } else {
+                    warn!(
+                        "failed delivery {} to {} (attempt {}): {}",
+                        d.id, d.url, attempts, err
+                    );
+                    None
+                };
+
+                state
+                    .db
+                    .webhook_attempted(d.id, &err.to_string(), now + backoff(attempts), failed)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(delivered)
+}
+
+/// Deliver queued events and alerts as they arrive, and retry failed deliveries.
+pub async fn run(state: State) {
+    let alerts = state.config.alerts.as_ref().map_or(0, |a| a.webhooks.len());
+    let targets = state.config.webhooks.len() + alerts;
+
+    if targets == 0 {
+        return;
+    }
+
+    let client = match client() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("failed to set up webhook client: {:?}", e);
+            return;
+        }
+    };
+
+    info!("delivering to {} webhooks", targets);
+    let mut rx = state.notifier.subscribe();
+
+    loop {
+        match deliver(&state, &client, now()).await {
+            Ok(0) => (),
+            Ok(n) => debug!("delivered {} webhook events", n),
+            Err(e) => error!("failed to deliver webhooks: {:?}", e),
+        }
+
+        let _ = tokio::time::timeout(POLL, rx.recv()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json as json;
+    use std::sync::{Arc, Mutex};
+    use warp::Filter;
+
+    /// The queue is shared by the tests, tests that deliver take turns.
+    static DELIVERING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    /// A stand-in target that records the payloads, and fails the first `fail` requests.
+    fn target(fail: usize) -> (std::net::SocketAddr, Arc<Mutex<Vec<bytes::Bytes>>>) {
+        let received = Arc::new(Mutex::new(Vec::<bytes::Bytes>::new()));
+        let target = {
+            let received = Arc::clone(&received);
+            warp::post()
+                .and(warp::body::bytes())
+                .map(move |body: bytes::Bytes| {
+                    let mut received = received.lock().unwrap();
+                    received.push(body);
+
+                    if received.len() <= fail {
+                        warp::http::StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        warp::http::StatusCode::OK
+                    }
+                })
+        };
+        let (addr, server) = warp::serve(target).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        (addr, received)
+    }
+
+    #[test]
+    fn retry_backoff() {
+        assert_eq!(backoff(1), RETRY_BASE);
+        assert_eq!(backoff(2), 2 * RETRY_BASE);
+        assert_eq!(backoff(MAX_ATTEMPTS - 1), RETRY_BASE << (MAX_ATTEMPTS - 2));
+        assert_eq!(backoff(1000), RETRY_MAX);
+    }
+
+    #[tokio::test]
+    async fn deliver_with_retry() {
+        let _turn = DELIVERING.lock().await;
+        let (addr, received) = target(1);
+
+        let mut state = crate::test_state().await;
+        Arc::get_mut(&mut state).unwrap().config.webhooks = vec![Webhook {
+            url: format!("http://{}/hook", addr),
+            buoys: vec!["devwebhook-a".into()],
+            message_types: vec![],
+            format: PayloadFormat::Decoded,
+        }];
+
+        let f = crate::buoys::filters(state.clone());
+
+        for dev in ["dev:webhook-a", "dev:webhook-b"] {
+            let event = crate::testing::event("sfy4-egpsb.qo.json", dev);
+
+            let res = crate::testing::post(&f, &event).await;
+            assert_eq!(res.status(), 200);
+        }
+
+        let client = client().unwrap();
+        let t0 = now();
+
+        assert_eq!(deliver(&state, &client, t0).await.unwrap(), 0);
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        // Not due yet.
+        assert_eq!(deliver(&state, &client, t0).await.unwrap(), 0);
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        assert_eq!(
+            deliver(&state, &client, t0 + RETRY_BASE * 2).await.unwrap(),
+            1
+        );
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 2);
+
+        let payload: json::Value = json::from_slice(&received[1]).unwrap();
+        assert_eq!(payload["dev"], "devwebhook-a");
+        assert_eq!(payload["message_type"], "egpsb.qo");
+        assert!(!payload["decoded"]["lon"].as_array().unwrap().is_empty());
+
+        assert!(state
+            .db
+            .due_webhooks(i64::MAX, 100)
+            .await
+            .unwrap()
+            .iter()
+            .all(|d| !d.url.contains(&addr.to_string())));
+    }
+
+    #[tokio::test]
+    async fn deliver_alerts() {
+        use crate::alerts::{self, Alert, Alerts};
+
+        let _turn = DELIVERING.lock().await;
+        let (addr, received) = target(0);
+
+        // Only alert webhooks.
+        let mut state = crate::test_state().await;
+        Arc::get_mut(&mut state).unwrap().config.alerts = Some(Alerts {
+            interval: 300,
+            webhooks: vec![format!("http://{}/alerts", addr)],
+            email: None,
+            rules: vec![],
+        });
+        assert!(state.config.webhooks.is_empty());
+
+        let alert = Alert {
+            id: 0,
+            dev: "devwebhook-alert".into(),
+            kind: "silence".into(),
+            message: "no data".into(),
+            raised: now(),
+            resolved: None,
+        };
+        alerts::send(&state, state.config.alerts.as_ref().unwrap(), &alert).await;
+
+        let delivery = tokio::spawn(run(state.clone()));
+
+        for _ in 0..100 {
+            if !received.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        delivery.abort();
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+
+        let payload: json::Value = json::from_slice(&received[0]).unwrap();
+        assert_eq!(payload["dev"], "devwebhook-alert");
+        assert_eq!(payload["kind"], "silence");
+    }
+}